@@ -0,0 +1,65 @@
+//! Compares the `Vec<u8>` conversions against the `Bytes` based encode/decode paths.
+//!
+//! Run with `cargo bench`.
+
+use bytes::{Bytes, BytesMut};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use ws_com_framework::Message;
+
+fn large_auth_res() -> Message {
+    Message::AuthRes {
+        public_id: 123087497859,
+        passcode: vec![42; 64 * 1024],
+    }
+}
+
+fn metadata_res() -> Message {
+    Message::MetadataRes {
+        file_id: 12343,
+        exp: 1234,
+        crt: 13834,
+        file_size: 34014,
+        username: String::from("hello, world"),
+        file_name: "a".repeat(4096),
+        upload_id: 123434199,
+    }
+}
+
+fn bench_decode(c: &mut Criterion) {
+    for (name, msg) in [
+        ("auth_res", large_auth_res()),
+        ("metadata_res", metadata_res()),
+    ] {
+        let vec: Vec<u8> = msg.into();
+        let bytes = Bytes::from(vec.clone());
+
+        c.bench_function(&format!("decode/{}/vec", name), |b| {
+            b.iter(|| Message::try_from(black_box(vec.clone())).unwrap())
+        });
+        c.bench_function(&format!("decode/{}/bytes", name), |b| {
+            b.iter(|| Message::decode_bytes(black_box(bytes.clone())).unwrap())
+        });
+    }
+}
+
+fn bench_encode(c: &mut Criterion) {
+    for (name, msg) in [
+        ("auth_res", large_auth_res()),
+        ("metadata_res", metadata_res()),
+    ] {
+        c.bench_function(&format!("encode/{}/vec", name), |b| {
+            b.iter(|| Vec::<u8>::from(black_box(msg.clone())))
+        });
+
+        let mut buf = BytesMut::new();
+        c.bench_function(&format!("encode/{}/bytes", name), |b| {
+            b.iter(|| {
+                buf.clear();
+                black_box(&msg).encode_into(&mut buf);
+            })
+        });
+    }
+}
+
+criterion_group!(benches, bench_decode, bench_encode);
+criterion_main!(benches);
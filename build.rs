@@ -2,6 +2,8 @@ fn main() {
     println!("cargo:rerun-if-changed=src/message.proto");
     let mut config = prost_build::Config::new();
     config.protoc_arg("--experimental_allow_proto3_optional");
+    // decode `bytes` fields as `bytes::Bytes`, so they can share the receive buffer
+    config.bytes(["."]);
     config
         .compile_protos(&["src/message.proto"], &["src/"])
         .unwrap();
@@ -0,0 +1,200 @@
+//! Sending a message to many agents at once, and collecting their replies.
+//!
+//! `send_all` and `broadcast` encode the message a single time, and send the same frame to each
+//! agent's sink of encoded frames, so every connection must use a plain `Codec` without
+//! encryption or signing. Where connections encrypt or sign their messages, use `send_all_with`
+//! and `broadcast_with` instead, which encode the message for each agent with its own codec.
+//!
+//! # Example
+//! ```rust
+//! use std::collections::HashMap;
+//! use std::time::Duration;
+//!
+//! use bytes::Bytes;
+//! use futures_util::{sink, stream};
+//! use ws_com_framework::broadcast::broadcast;
+//! use ws_com_framework::Message;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! // sinks of encoded frames, such as each connection's outbound queue
+//! let mut sinks = HashMap::from([(43, sink::drain::<Bytes>())]);
+//!
+//! // replies received from every agent, tagged with the agent they came from
+//! let res = Message::StatusRes {
+//!     public_id: 43,
+//!     ready: true,
+//!     uptime: 10,
+//!     upload_id: 1,
+//!     message: None,
+//!     active_shares: None,
+//!     bytes_shared: None,
+//!     uploads_in_progress: None,
+//!     free_disk: None,
+//!     agent_version: None,
+//!     os: None,
+//! };
+//! let mut replies = stream::iter(vec![(43, res.clone())]);
+//!
+//! let req = Message::StatusReq { public_id: 0, upload_id: 1, deadline: None };
+//! let results = broadcast(&req, &mut sinks, &mut replies, Duration::from_secs(5)).await;
+//! assert_eq!(results[&43], Ok(res));
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_util::future::join_all;
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use tokio::time::{sleep_until, timeout, Instant};
+
+use crate::error::Error;
+use crate::message::{Message, PublicId};
+
+/// The outcome of a broadcast for each agent, keyed by `PublicId`.
+pub type Replies = HashMap<PublicId, Result<Message, Error>>;
+
+/// Encode `msg` once and send it to every sink, waiting at most `timeout` for each.
+pub async fn send_all<S>(
+    msg: &Message,
+    sinks: &mut HashMap<PublicId, S>,
+    timeout: Duration,
+) -> HashMap<PublicId, Result<(), Error>>
+where
+    S: Sink<Bytes> + Unpin,
+    S::Error: Display,
+{
+    let frame = Bytes::from(msg.to_bytes());
+    send_all_with(msg, sinks, |_, _| Ok(frame.clone()), timeout).await
+}
+
+/// Encode `msg` for each sink with `encode`, such as with that agent's `Codec`, and send it,
+/// waiting at most `timeout` for each. Agents whose message failed to encode have that error
+/// as their result.
+pub async fn send_all_with<S, E>(
+    msg: &Message,
+    sinks: &mut HashMap<PublicId, S>,
+    mut encode: E,
+    limit: Duration,
+) -> HashMap<PublicId, Result<(), Error>>
+where
+    S: Sink<Bytes> + Unpin,
+    S::Error: Display,
+    E: FnMut(PublicId, &Message) -> Result<Bytes, Error>,
+{
+    let sends = sinks.iter_mut().map(|(&public_id, sink)| {
+        let frame = encode(public_id, msg);
+        async move {
+            let res = match frame {
+                Ok(frame) => match timeout(limit, sink.send(frame)).await {
+                    Ok(Ok(())) => Ok(()),
+                    Ok(Err(e)) => Err(Error::TransportError(e.to_string())),
+                    Err(_) => Err(Error::Timeout { public_id }),
+                },
+                Err(e) => Err(e),
+            };
+            (public_id, res)
+        }
+    });
+    join_all(sends).await.into_iter().collect()
+}
+
+/// Encode `msg` once, send it to every sink, and wait for each agent's reply.
+///
+/// `replies` yields messages received from any agent, tagged with the agent's `PublicId`. If
+/// `msg` carries an `UploadId`, a reply with the same `UploadId` answers it, otherwise the
+/// first `Message::Ok` does. Either way the first `Message::Error` also answers it. Other
+/// messages from `replies` are discarded.
+///
+/// Each agent has `timeout` from when the message was sent to it to reply, after which its
+/// result is `Error::Timeout`. Agents whose sink failed have that error as their result, and
+/// agents still waiting when `replies` ends have `Error::NotConnected`.
+pub async fn broadcast<S, R>(
+    msg: &Message,
+    sinks: &mut HashMap<PublicId, S>,
+    replies: &mut R,
+    timeout: Duration,
+) -> Replies
+where
+    S: Sink<Bytes> + Unpin,
+    S::Error: Display,
+    R: Stream<Item = (PublicId, Message)> + Unpin,
+{
+    let frame = Bytes::from(msg.to_bytes());
+    broadcast_with(msg, sinks, |_, _| Ok(frame.clone()), replies, timeout).await
+}
+
+/// Encode `msg` for each sink with `encode`, send it, and wait for each agent's reply, as
+/// `broadcast` does.
+pub async fn broadcast_with<S, E, R>(
+    msg: &Message,
+    sinks: &mut HashMap<PublicId, S>,
+    encode: E,
+    replies: &mut R,
+    timeout: Duration,
+) -> Replies
+where
+    S: Sink<Bytes> + Unpin,
+    S::Error: Display,
+    E: FnMut(PublicId, &Message) -> Result<Bytes, Error>,
+    R: Stream<Item = (PublicId, Message)> + Unpin,
+{
+    let sent = send_all_with(msg, sinks, encode, timeout).await;
+
+    let mut results = Replies::with_capacity(sent.len());
+    let mut deadlines = HashMap::with_capacity(sent.len());
+    let now = Instant::now();
+    for (public_id, res) in sent {
+        match res {
+            Ok(()) => {
+                deadlines.insert(public_id, now + timeout);
+            }
+            Err(e) => {
+                results.insert(public_id, Err(e));
+            }
+        }
+    }
+
+    while let Some(&next) = deadlines.values().min() {
+        tokio::select! {
+            reply = replies.next() => match reply {
+                Some((public_id, reply)) => {
+                    if is_reply(msg, &reply) && deadlines.remove(&public_id).is_some() {
+                        results.insert(public_id, Ok(reply));
+                    }
+                }
+                None => break,
+            },
+            _ = sleep_until(next) => {
+                let now = Instant::now();
+                deadlines.retain(|&public_id, deadline| {
+                    let waiting = *deadline > now;
+                    if !waiting {
+                        results.insert(public_id, Err(Error::Timeout { public_id }));
+                    }
+                    waiting
+                });
+            }
+        }
+    }
+    for public_id in deadlines.into_keys() {
+        results.insert(public_id, Err(Error::NotConnected { public_id }));
+    }
+    results
+}
+
+fn is_reply(req: &Message, reply: &Message) -> bool {
+    match (req, reply) {
+        (Message::MetadataReq { upload_id: a, .. }, Message::MetadataRes { upload_id: b, .. })
+        | (Message::StatusReq { upload_id: a, .. }, Message::StatusRes { upload_id: b, .. }) => {
+            a == b
+        }
+        (req, Message::Ok) => req.upload_id().is_none(),
+        // an agent which cannot answer a request with an `UploadId` replies with an error
+        (_, Message::Error { .. }) => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,656 @@
+//! A client which keeps an agent connected to the server, reconnecting and re-authenticating
+//! whenever the connection is lost.
+//!
+//! # Example
+//! ```rust,no_run
+//! # #[cfg(feature = "tungstenite")]
+//! async fn example() {
+//!     use ws_com_framework::client::{ClientOptions, ReconnectingClient};
+//!     use ws_com_framework::transport::tungstenite::WebSocketTransport;
+//!     use ws_com_framework::{Error, Message};
+//!
+//!     let connector = || async {
+//!         let (ws, _) = tokio_tungstenite::connect_async("ws://localhost:8080/ws")
+//!             .await
+//!             .map_err(|e| Error::TransportError(e.to_string()))?;
+//!         Ok(WebSocketTransport::new(ws))
+//!     };
+//!     let mut client =
+//!         ReconnectingClient::spawn(43, b"passcode".to_vec(), ClientOptions::default(), connector);
+//!
+//!     client.send(Message::Ok).await.unwrap();
+//!     while let Some(msg) = client.recv().await {
+//!         println!("received {:?}", msg);
+//!     }
+//! }
+//! ```
+
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::Instant;
+
+use crate::error::Error;
+use crate::message::{AgentInfo, Message, Passcode, PublicId};
+use crate::rotation::AgentPasscode;
+use crate::session::now_millis;
+
+/// Jittered exponential backoff between reconnection attempts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    /// Delay before the first reconnection attempt, doubled for every further attempt
+    pub initial: Duration,
+    /// Upper bound on the delay between attempts
+    pub max: Duration,
+    /// Fraction of each delay which is randomised, between `0.0` and `1.0`
+    pub jitter: f64,
+    /// Give up after this many consecutive failed attempts, or never if `None`
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+            jitter: 0.5,
+            max_attempts: None,
+        }
+    }
+}
+
+impl Backoff {
+    /// The delay before reconnection attempt `attempt`, counting from `1`.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exp = attempt.saturating_sub(1).min(31);
+        let delay = self.initial.saturating_mul(1 << exp).min(self.max);
+        let jitter = self.jitter.clamp(0.0, 1.0) * random_fraction();
+        delay.mul_f64(1.0 - jitter)
+    }
+}
+
+/// A random number in `[0, 1)`, from the randomly seeded std hasher.
+fn random_fraction() -> f64 {
+    let bits = RandomState::new().hash_one(0u8) >> 11;
+    bits as f64 / (1u64 << 53) as f64
+}
+
+/// Enrol a new agent over `conn` with a one-time `enrolment_code`, returning the `PublicId` and
+/// `Passcode` the server issued it.
+///
+/// Waits for the server's `AuthReq` and answers it with a `Message::RegisterReq`. The
+/// credentials should be persisted and used to spawn a `ReconnectingClient` from then on, as
+/// the code cannot be used again. Fails with `Error::EnrolmentFailed` if the server rejects the
+/// code.
+pub async fn enrol<T>(
+    conn: &mut T,
+    enrolment_code: impl Into<String>,
+    agent_info: AgentInfo,
+) -> Result<(PublicId, Passcode), Error>
+where
+    T: Stream<Item = Result<Message, Error>> + Sink<Message, Error = Error> + Unpin,
+{
+    let mut req = Some(Message::RegisterReq {
+        enrolment_code: enrolment_code.into(),
+        agent_info,
+    });
+    loop {
+        match conn.next().await {
+            Some(Ok(Message::AuthReq { .. })) => match req.take() {
+                Some(req) => conn.send(req).await?,
+                None => {
+                    return Err(Error::EnrolmentFailed(String::from(
+                        "the server asked to authenticate again",
+                    )))
+                }
+            },
+            Some(Ok(Message::RegisterRes {
+                public_id,
+                passcode,
+            })) => return Ok((public_id, passcode)),
+            Some(Ok(Message::Error { reason, .. })) if req.is_none() => {
+                return Err(Error::EnrolmentFailed(
+                    reason.unwrap_or_else(|| String::from("rejected by the server")),
+                ))
+            }
+            Some(Ok(_)) => {}
+            Some(Err(e)) => return Err(e),
+            None => {
+                return Err(Error::TransportError(String::from(
+                    "connection closed during enrolment",
+                )))
+            }
+        }
+    }
+}
+
+/// Configuration of a `ReconnectingClient`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClientOptions {
+    /// Delays between reconnection attempts
+    pub backoff: Backoff,
+    /// How long to wait for the server's `AuthReq` after connecting
+    pub handshake_timeout: Duration,
+    /// How many outgoing messages are buffered while disconnected before `send` waits
+    pub buffer_size: usize,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            backoff: Backoff::default(),
+            handshake_timeout: Duration::from_secs(10),
+            buffer_size: 1024,
+        }
+    }
+}
+
+/// A change in the state of a `ReconnectingClient`'s connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// Connection attempt `attempt` has started, counting from `1` since the last connection
+    Connecting {
+        /// The number of this attempt
+        attempt: u32,
+    },
+    /// The handshake completed, and buffered messages are being sent
+    Connected,
+    /// The connection was lost or could not be established
+    Disconnected {
+        /// Why the connection was lost
+        error: Error,
+        /// How long until the next attempt
+        retry_in: Duration,
+    },
+    /// The server sent a `Message::Redirect`, and the client is reconnecting to `url`
+    Redirected {
+        /// The url being connected to
+        url: String,
+    },
+    /// The agent's passcode was rotated, and should be persisted in place of the old one
+    PasscodeRotated {
+        /// The new passcode
+        passcode: Passcode,
+    },
+    /// Reconnection was abandoned after `Backoff::max_attempts` failed attempts
+    GaveUp {
+        /// Why the last attempt failed
+        error: Error,
+    },
+}
+
+/// A connection to the server which is re-established whenever it is lost.
+///
+/// A background task calls the connector, answers the server's `AuthReq` with the configured
+/// passcode, and then exchanges messages, buffering outgoing messages while disconnected. If the
+/// server sends a `Message::Goodbye` before closing the connection, the next attempt waits for at
+/// least its `reconnect_after`. Dropping the client closes the connection and stops the task.
+///
+/// A client started with `spawn_to` also follows a `Message::Redirect`, or the `redirect_url` of
+/// a `Message::Goodbye`, by passing the new url to its connector. After a redirect, the server's
+/// `AuthReq` is answered with a `Message::TokenAuth` carrying the redirect's token, falling back
+/// to the passcode if the server asks again. A client started with `spawn` has no url to change,
+/// so it returns these messages from `recv` without acting on them.
+///
+/// If the server hands out a session token in a `Message::AuthOk`, the client presents it in a
+/// `Message::TokenAuth` when it reconnects, until it expires or the server rejects it. Halfway
+/// through its lifetime the client sends a `Message::RefreshSession` to ask for a new one.
+/// Neither message is returned by `recv`.
+///
+/// The client also takes part in passcode rotation, see `rotation`. A
+/// `Message::RotatePasscodeReq` from the server is accepted without being returned by `recv`,
+/// and one sent with `send` proposes a new passcode. Either way, once the client switches
+/// passcode it emits `ConnectionEvent::PasscodeRotated`, and falls back to the old passcode if
+/// the server rejects the new one.
+#[derive(Debug)]
+pub struct ReconnectingClient {
+    outgoing: mpsc::Sender<Message>,
+    incoming: mpsc::Receiver<Result<Message, Error>>,
+    events: broadcast::Receiver<ConnectionEvent>,
+}
+
+impl ReconnectingClient {
+    /// Start connecting with `connector`, authenticating as `public_id` with `passcode`.
+    ///
+    /// Redirects from the server are not followed. Must be called from within a tokio runtime.
+    pub fn spawn<C, F, T>(
+        public_id: PublicId,
+        passcode: Passcode,
+        options: ClientOptions,
+        mut connector: C,
+    ) -> Self
+    where
+        C: FnMut() -> F + Send + 'static,
+        F: Future<Output = Result<T, Error>> + Send + 'static,
+        T: Stream<Item = Result<Message, Error>>
+            + Sink<Message, Error = Error>
+            + Unpin
+            + Send
+            + 'static,
+    {
+        Self::start(None, public_id, passcode, options, move |_: &str| {
+            connector()
+        })
+    }
+
+    /// Start connecting to `url` with `connector`, authenticating as `public_id` with `passcode`.
+    ///
+    /// The connector is passed the url to connect to, which changes when the server redirects
+    /// the client. Must be called from within a tokio runtime.
+    pub fn spawn_to<C, F, T>(
+        url: impl Into<String>,
+        public_id: PublicId,
+        passcode: Passcode,
+        options: ClientOptions,
+        connector: C,
+    ) -> Self
+    where
+        C: FnMut(&str) -> F + Send + 'static,
+        F: Future<Output = Result<T, Error>> + Send + 'static,
+        T: Stream<Item = Result<Message, Error>>
+            + Sink<Message, Error = Error>
+            + Unpin
+            + Send
+            + 'static,
+    {
+        Self::start(Some(url.into()), public_id, passcode, options, connector)
+    }
+
+    /// Start the background task, following redirects only if there is a `url` to change.
+    fn start<C, F, T>(
+        url: Option<String>,
+        public_id: PublicId,
+        passcode: Passcode,
+        options: ClientOptions,
+        connector: C,
+    ) -> Self
+    where
+        C: FnMut(&str) -> F + Send + 'static,
+        F: Future<Output = Result<T, Error>> + Send + 'static,
+        T: Stream<Item = Result<Message, Error>>
+            + Sink<Message, Error = Error>
+            + Unpin
+            + Send
+            + 'static,
+    {
+        let (outgoing, outgoing_rx) = mpsc::channel(options.buffer_size.max(1));
+        let (incoming_tx, incoming) = mpsc::channel(options.buffer_size.max(1));
+        let (events_tx, events) = broadcast::channel(64);
+
+        let driver = Driver {
+            connector,
+            follows_redirects: url.is_some(),
+            url: url.unwrap_or_default(),
+            token: None,
+            session: None,
+            public_id,
+            passcode: AgentPasscode::new(passcode),
+            auth_attempt: 0,
+            options,
+            outgoing: outgoing_rx,
+            incoming: incoming_tx,
+            events: events_tx,
+            unsent: VecDeque::new(),
+            reconnect_after: None,
+        };
+        tokio::spawn(driver.run());
+
+        Self {
+            outgoing,
+            incoming,
+            events,
+        }
+    }
+
+    /// Queue a message to be sent, waiting if the outgoing buffer is full.
+    ///
+    /// Fails only once the client has given up reconnecting.
+    pub async fn send(&self, msg: Message) -> Result<(), Error> {
+        self.outgoing
+            .send(msg)
+            .await
+            .map_err(|_| Error::TransportError(String::from("the client has stopped")))
+    }
+
+    /// Receive the next message from the server, other than the handshake.
+    ///
+    /// Messages which could not be decoded, or could not be sent for a reason other than the
+    /// connection being lost, are returned as errors. Returns `None` once the client has given
+    /// up reconnecting.
+    pub async fn recv(&mut self) -> Option<Result<Message, Error>> {
+        self.incoming.recv().await
+    }
+
+    /// Wait for the next change in connection state, from when the client was spawned.
+    ///
+    /// Returns `None` once the client has stopped. If events are not read, the oldest are
+    /// discarded.
+    pub async fn next_event(&mut self) -> Option<ConnectionEvent> {
+        loop {
+            match self.events.recv().await {
+                Ok(event) => return Some(event),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// A receiver of connection state changes from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.events.resubscribe()
+    }
+}
+
+/// Why `Driver::exchange` returned.
+enum Stop {
+    /// The client was dropped
+    Closed,
+    /// The connection was lost
+    Lost(Error),
+    /// The server redirected the client elsewhere
+    Redirected,
+}
+
+/// A session token issued by the server in a `Message::AuthOk`.
+struct Session {
+    token: Vec<u8>,
+    /// When the token expires, in milliseconds since epoch
+    expires_at: u64,
+    /// When to ask for a new token, or `None` once one has been asked for on this connection
+    refresh_at: Option<Instant>,
+}
+
+impl Session {
+    fn new(token: Vec<u8>, expires_at: u64) -> Self {
+        let mut session = Self {
+            token,
+            expires_at,
+            refresh_at: None,
+        };
+        session.schedule_refresh();
+        session
+    }
+
+    fn is_expired(&self) -> bool {
+        now_millis() >= self.expires_at
+    }
+
+    /// Ask for a new token halfway through the remaining lifetime of this one.
+    fn schedule_refresh(&mut self) {
+        let remaining = Duration::from_millis(self.expires_at.saturating_sub(now_millis()));
+        self.refresh_at = Some(Instant::now() + remaining / 2);
+    }
+}
+
+struct Driver<C> {
+    connector: C,
+    /// Where to connect to, passed to the connector
+    url: String,
+    /// Whether redirects change `url`, which is false for a client started with `spawn`
+    follows_redirects: bool,
+    /// The token to present in place of the passcode, from the last `Message::Redirect`
+    token: Option<Vec<u8>>,
+    /// The session token from the last `Message::AuthOk`, presented when reconnecting
+    session: Option<Session>,
+    public_id: PublicId,
+    passcode: AgentPasscode,
+    /// The number of times the passcode has been presented on this connection
+    auth_attempt: usize,
+    options: ClientOptions,
+    outgoing: mpsc::Receiver<Message>,
+    incoming: mpsc::Sender<Result<Message, Error>>,
+    events: broadcast::Sender<ConnectionEvent>,
+    /// Messages taken from `outgoing` which were not sent before the connection was lost
+    unsent: VecDeque<Message>,
+    /// The delay requested by the last `Message::Goodbye` from the server
+    reconnect_after: Option<Duration>,
+}
+
+impl<C, F, T> Driver<C>
+where
+    C: FnMut(&str) -> F,
+    F: Future<Output = Result<T, Error>>,
+    T: Stream<Item = Result<Message, Error>> + Sink<Message, Error = Error> + Unpin,
+{
+    async fn run(mut self) {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            self.emit(ConnectionEvent::Connecting { attempt });
+
+            let incoming = self.incoming.clone();
+            let connected = tokio::select! {
+                res = self.connect() => res,
+                _ = incoming.closed() => return,
+            };
+            let error = match connected {
+                Ok(mut conn) => {
+                    attempt = 0;
+                    self.emit(ConnectionEvent::Connected);
+                    match self.exchange(&mut conn).await {
+                        Stop::Closed => {
+                            let _ = conn.close().await;
+                            return;
+                        }
+                        Stop::Redirected => {
+                            let _ = conn.close().await;
+                            let url = self.url.clone();
+                            self.emit(ConnectionEvent::Redirected { url });
+                            continue;
+                        }
+                        Stop::Lost(e) => e,
+                    }
+                }
+                Err(e) => e,
+            };
+
+            if matches!(self.options.backoff.max_attempts, Some(max) if attempt >= max) {
+                self.emit(ConnectionEvent::GaveUp { error });
+                return;
+            }
+            let mut retry_in = self.options.backoff.delay(attempt);
+            if let Some(reconnect_after) = self.reconnect_after.take() {
+                retry_in = retry_in.max(reconnect_after);
+            }
+            self.emit(ConnectionEvent::Disconnected { error, retry_in });
+            tokio::select! {
+                _ = tokio::time::sleep(retry_in) => {}
+                _ = self.incoming.closed() => return,
+            }
+        }
+    }
+
+    fn emit(&self, event: ConnectionEvent) {
+        let _ = self.events.send(event);
+    }
+
+    /// Connect and complete the handshake.
+    async fn connect(&mut self) -> Result<T, Error> {
+        let mut conn = (self.connector)(&self.url).await?;
+        let timeout = self.options.handshake_timeout;
+        match tokio::time::timeout(timeout, self.handshake(&mut conn)).await {
+            Ok(res) => res.map(|_| conn),
+            Err(_) => Err(Error::TransportError(String::from(
+                "timed out waiting for the server to authenticate",
+            ))),
+        }
+    }
+
+    /// Wait for the server's `AuthReq`, and answer it.
+    async fn handshake(&mut self, conn: &mut T) -> Result<(), Error> {
+        loop {
+            match conn.next().await {
+                Some(Ok(Message::AuthReq { .. })) => {
+                    self.auth_attempt = 0;
+                    if self.session.as_ref().is_some_and(Session::is_expired) {
+                        self.session = None;
+                    }
+                    let token = self
+                        .token
+                        .take()
+                        .or_else(|| self.session.as_ref().map(|s| s.token.clone()));
+                    let res = match token {
+                        Some(token) => Message::TokenAuth {
+                            public_id: self.public_id,
+                            token,
+                        },
+                        None => self.auth_res(),
+                    };
+                    return conn.send(res).await;
+                }
+                Some(Ok(msg)) => {
+                    let _ = self.incoming.send(Ok(msg)).await;
+                }
+                Some(Err(e @ Error::TransportError(_))) => return Err(e),
+                Some(Err(e)) => {
+                    let _ = self.incoming.send(Err(e)).await;
+                }
+                None => {
+                    return Err(Error::TransportError(String::from(
+                        "connection closed during the handshake",
+                    )))
+                }
+            }
+        }
+    }
+
+    /// The next passcode to present on this connection, falling back to the previous passcode
+    /// if the current one was rejected.
+    fn auth_res(&mut self) -> Message {
+        let passcode = self.passcode.attempt(self.auth_attempt).clone();
+        self.auth_attempt += 1;
+        Message::AuthRes {
+            public_id: self.public_id,
+            passcode,
+        }
+    }
+
+    /// Send buffered messages, then exchange messages until the connection is lost.
+    async fn exchange(&mut self, conn: &mut T) -> Stop {
+        if let Some(session) = &mut self.session {
+            session.schedule_refresh();
+        }
+        while let Some(msg) = self.unsent.pop_front() {
+            if let Err(stop) = self.send(conn, msg).await {
+                return stop;
+            }
+        }
+
+        loop {
+            let refresh_at = self.session.as_ref().and_then(|s| s.refresh_at);
+            tokio::select! {
+                _ = tokio::time::sleep_until(refresh_at.unwrap_or_else(Instant::now)),
+                    if refresh_at.is_some() =>
+                {
+                    if let Some(session) = &mut self.session {
+                        session.refresh_at = None;
+                    }
+                    if let Err(stop) = self.send(conn, Message::RefreshSession).await {
+                        return stop;
+                    }
+                }
+                msg = self.outgoing.recv() => match msg {
+                    Some(msg) => {
+                        let msg = match msg {
+                            Message::RotatePasscodeReq { rotation_id, passcode } => {
+                                self.passcode.propose(rotation_id, passcode)
+                            }
+                            msg => msg,
+                        };
+                        if let Err(stop) = self.send(conn, msg).await {
+                            return stop;
+                        }
+                    }
+                    None => return Stop::Closed,
+                },
+                item = conn.next() => match item {
+                    Some(Ok(Message::AuthReq { .. })) => {
+                        // the server rejected our token or passcode
+                        self.session = None;
+                        if let Err(e) = conn.send(self.auth_res()).await {
+                            return Stop::Lost(e);
+                        }
+                    }
+                    Some(Ok(Message::AuthOk { session_token, expires_at })) => {
+                        self.session = Some(Session::new(session_token, expires_at));
+                    }
+                    Some(Ok(msg @ Message::RotatePasscodeReq { .. })) => {
+                        if let Err(stop) = self.rotate(conn, msg).await {
+                            return stop;
+                        }
+                    }
+                    Some(Ok(msg @ Message::RotatePasscodeRes { .. })) => {
+                        if let Err(stop) = self.rotate(conn, msg.clone()).await {
+                            return stop;
+                        }
+                        if self.incoming.send(Ok(msg)).await.is_err() {
+                            return Stop::Closed;
+                        }
+                    }
+                    Some(item) => {
+                        let redirected = match &item {
+                            Ok(Message::Redirect { url, token }) if self.follows_redirects => {
+                                self.url = url.clone();
+                                self.token = Some(token.clone());
+                                true
+                            }
+                            Ok(Message::Goodbye { reconnect_after, redirect_url, .. }) => {
+                                self.reconnect_after = reconnect_after.map(Duration::from_secs);
+                                if let Some(url) = redirect_url.as_ref().filter(|_| self.follows_redirects) {
+                                    self.url = url.clone();
+                                }
+                                false
+                            }
+                            _ => false,
+                        };
+                        if self.incoming.send(item).await.is_err() {
+                            return Stop::Closed;
+                        }
+                        if redirected {
+                            return Stop::Redirected;
+                        }
+                    }
+                    None => {
+                        return Stop::Lost(Error::TransportError(String::from(
+                            "connection closed by the server",
+                        )))
+                    }
+                },
+            }
+        }
+    }
+
+    /// Apply a passcode rotation message from the server, replying to it if required.
+    async fn rotate(&mut self, conn: &mut T, msg: Message) -> Result<(), Stop> {
+        let before = self.passcode.current().clone();
+        let reply = self.passcode.handle(&msg);
+        if *self.passcode.current() != before {
+            let passcode = self.passcode.current().clone();
+            self.emit(ConnectionEvent::PasscodeRotated { passcode });
+        }
+        match reply {
+            Some(reply) => self.send(conn, reply).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Send a message, keeping it to retry on the next connection if the connection was lost.
+    async fn send(&mut self, conn: &mut T, msg: Message) -> Result<(), Stop> {
+        match conn.send(msg.clone()).await {
+            Ok(()) => Ok(()),
+            Err(e @ Error::TransportError(_)) => {
+                self.unsent.push_front(msg);
+                Err(Stop::Lost(e))
+            }
+            Err(e) => match self.incoming.send(Err(e)).await {
+                Ok(()) => Ok(()),
+                Err(_) => Err(Stop::Closed),
+            },
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! The `Codec` bundles up how messages are encoded and decoded on a single connection.
+
+use bytes::Bytes;
+use prost::Message as _;
+
+use crate::compression::{self, Compression};
+use crate::encoding;
+use crate::error::Error;
+use crate::limits::Limits;
+use crate::message::websocket_message::protobuf_types::FspComm;
+use crate::message::Message;
+
+/// Encodes outgoing and decodes incoming messages for a connection, applying limits,
+/// compression, encryption and signing.
+///
+/// A codec holds the signing state of its connection, so both halves of a connection should
+/// share a single codec.
+///
+/// # Example
+/// ```rust
+/// use ws_com_framework::{Codec, Message};
+///
+/// let codec = Codec::default();
+/// let bytes = codec.encode(&Message::AuthReq { public_id: 43 }).unwrap();
+/// let msg = codec.decode(bytes.into()).unwrap();
+/// assert_eq!(msg, Message::AuthReq { public_id: 43 });
+/// ```
+#[derive(Debug)]
+#[cfg_attr(
+    not(any(feature = "encryption", feature = "signing")),
+    allow(missing_copy_implementations)
+)]
+pub struct Codec {
+    /// Limits checked before a message is sent, against the uncompressed message
+    pub encode_limits: Limits,
+    /// Limits checked when a message is received, against the decompressed message
+    pub decode_limits: Limits,
+    /// The algorithm used to compress large messages
+    pub compression: Compression,
+    /// Messages smaller than this many bytes are never compressed
+    pub compression_threshold: usize,
+    /// Encrypts every message sent and requires every message received to be encrypted
+    #[cfg(feature = "encryption")]
+    pub cipher: Option<crate::encryption::SessionCipher>,
+    /// Signs every message sent and requires every message received to be signed, rejecting
+    /// replayed messages
+    #[cfg(feature = "signing")]
+    pub signer: Option<crate::signing::SessionSigner>,
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Self {
+            encode_limits: Limits::default(),
+            decode_limits: Limits::default(),
+            compression: Compression::None,
+            compression_threshold: 1024,
+            #[cfg(feature = "encryption")]
+            cipher: None,
+            #[cfg(feature = "signing")]
+            signer: None,
+        }
+    }
+}
+
+impl Codec {
+    /// Encode a message to be sent, compressing it if it is large enough, then encrypting and
+    /// signing it if configured to.
+    pub fn encode(&self, msg: &Message) -> Result<Vec<u8>, Error> {
+        self.encode_on(0, msg)
+    }
+
+    /// Encode a message to be sent on a stream, as with `encode`, recording the stream's id in
+    /// the envelope. Stream 0 is used for messages which do not belong to a stream.
+    pub fn encode_on(&self, stream_id: u32, msg: &Message) -> Result<Vec<u8>, Error> {
+        msg.validate(&self.encode_limits)?;
+
+        let compress = self.compression != Compression::None
+            && msg.encoded_len() >= self.compression_threshold;
+        #[cfg(feature = "encryption")]
+        let encrypt = self.cipher.is_some();
+        #[cfg(not(feature = "encryption"))]
+        let encrypt = false;
+        #[cfg(feature = "signing")]
+        let sign = self.signer.is_some();
+        #[cfg(not(feature = "signing"))]
+        let sign = false;
+        if stream_id == 0 && !compress && !encrypt && !sign {
+            return Ok(msg.to_bytes());
+        }
+
+        let mut envelope = encoding::envelope(msg);
+        envelope.stream_id = stream_id;
+        if compress {
+            envelope = compression::compress(envelope, self.compression);
+        }
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            envelope = cipher.seal(envelope)?;
+        }
+        #[cfg(feature = "signing")]
+        if let Some(signer) = &self.signer {
+            envelope = signer.sign(envelope)?;
+        }
+        Ok(envelope.encode_to_vec())
+    }
+
+    /// Decode a received message, verifying, decrypting and decompressing it if required.
+    pub fn decode(&self, frame: Bytes) -> Result<Message, Error> {
+        self.decode_on(frame).map(|(_, msg)| msg)
+    }
+
+    /// Decode a received message, as with `decode`, along with the id of the stream it was sent
+    /// on.
+    pub fn decode_on(&self, frame: Bytes) -> Result<(u32, Message), Error> {
+        self.decode_limits.check_frame_size(frame.len())?;
+        #[allow(unused_mut)]
+        let mut envelope = FspComm::decode(frame)?;
+        #[cfg(feature = "signing")]
+        if let Some(signer) = &self.signer {
+            envelope = signer.verify(envelope)?;
+        }
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            envelope = cipher.open(envelope)?;
+        }
+        let stream_id = envelope.stream_id;
+        let msg = Message::decode_envelope_limited(envelope, &self.decode_limits)?;
+        Ok((stream_id, msg))
+    }
+}
@@ -0,0 +1,95 @@
+//! Optional compression of the envelope's value, for large messages such as long error
+//! reasons or status messages.
+//!
+//! Compressing messages requires the `compression` feature, and is done by a `Codec` for
+//! messages above its `compression_threshold`. Compressed messages are always recognised when
+//! decoding, but without the feature they are rejected with a `Error::CompressionError` rather
+//! than being decoded as garbage.
+
+use crate::error::Error;
+use crate::message::websocket_message::protobuf_types::fsp_comm::Compression as WireCompression;
+use crate::message::websocket_message::protobuf_types::FspComm;
+
+/// The algorithm used to compress large messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// Never compress messages
+    #[default]
+    None,
+    /// Compress messages with deflate
+    #[cfg(feature = "compression")]
+    Deflate,
+}
+
+/// Compress the value of `envelope` with the provided algorithm.
+///
+/// The envelope is returned unchanged if compressing would not make it any smaller.
+pub(crate) fn compress(envelope: FspComm, compression: Compression) -> FspComm {
+    match compression {
+        Compression::None => envelope,
+        #[cfg(feature = "compression")]
+        Compression::Deflate => {
+            use flate2::write::DeflateEncoder;
+            use std::io::Write;
+
+            let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder
+                .write_all(&envelope.value)
+                .expect("Should never fail, as Vec<u8> expands automatically");
+            let value = encoder
+                .finish()
+                .expect("Should never fail, as Vec<u8> expands automatically");
+            if value.len() >= envelope.value.len() {
+                return envelope;
+            }
+
+            FspComm {
+                value: value.into(),
+                compression: WireCompression::Deflate as i32,
+                ..envelope
+            }
+        }
+    }
+}
+
+/// Decompress the value of `envelope` if required, failing if the decompressed value would be
+/// larger than `limit` bytes.
+pub(crate) fn decompress(envelope: FspComm, limit: usize) -> Result<FspComm, Error> {
+    match WireCompression::from_i32(envelope.compression) {
+        Some(WireCompression::None) => Ok(envelope),
+        #[cfg(feature = "compression")]
+        Some(WireCompression::Deflate) => {
+            use flate2::read::DeflateDecoder;
+            use std::io::Read;
+
+            let mut value = Vec::new();
+            DeflateDecoder::new(&envelope.value[..])
+                .take((limit as u64).saturating_add(1))
+                .read_to_end(&mut value)
+                .map_err(|e| Error::CompressionError(format!("invalid deflate stream: {}", e)))?;
+            if value.len() > limit {
+                return Err(Error::CompressionError(format!(
+                    "decompressed message exceeds the maximum frame size of {} bytes",
+                    limit
+                )));
+            }
+
+            Ok(FspComm {
+                value: value.into(),
+                compression: WireCompression::None as i32,
+                ..envelope
+            })
+        }
+        #[cfg(not(feature = "compression"))]
+        Some(WireCompression::Deflate) => {
+            let _ = limit;
+            Err(Error::CompressionError(String::from(
+                "message is compressed with deflate, but the `compression` feature is not enabled",
+            )))
+        }
+        None => Err(Error::CompressionError(format!(
+            "unrecognised compression algorithm {}",
+            envelope.compression
+        ))),
+    }
+}
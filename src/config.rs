@@ -0,0 +1,150 @@
+//! Settings pushed to agents by the server.
+//!
+//! The server sends a `Message::ConfigUpdate` carrying typed settings and a version number,
+//! which increases with every update. The agent's `ConfigStore` declares the settings it
+//! understands and their types, and applies an update only if every entry in it is valid, so a
+//! bad update never leaves the agent half configured. Either way it replies with a
+//! `Message::ConfigAck` listing the keys which were rejected.
+//!
+//! # Example
+//! ```rust
+//! use ws_com_framework::config::ConfigStore;
+//! use ws_com_framework::message::{ConfigEntry, ConfigValue};
+//! use ws_com_framework::Message;
+//!
+//! let mut store = ConfigStore::new();
+//! store.declare("max_upload_bps", ConfigValue::Int(0));
+//! store.declare("uploads_enabled", ConfigValue::Bool(true));
+//!
+//! let update = Message::ConfigUpdate {
+//!     version: 1,
+//!     entries: vec![
+//!         ConfigEntry {
+//!             key: String::from("max_upload_bps"),
+//!             value: ConfigValue::Int(1_000_000),
+//!         },
+//!         ConfigEntry {
+//!             key: String::from("uploads_enabled"),
+//!             value: ConfigValue::Text(String::from("yes")),
+//!         },
+//!     ],
+//! };
+//!
+//! // the mistyped entry rejects the whole update
+//! let ack = store.apply(&update).unwrap();
+//! if let Message::ConfigAck { applied, rejected, .. } = ack {
+//!     assert!(!applied);
+//!     assert_eq!(rejected, ["uploads_enabled"]);
+//! }
+//! assert_eq!(store.get("max_upload_bps"), Some(&ConfigValue::Int(0)));
+//! ```
+
+use std::collections::BTreeMap;
+use std::mem::discriminant;
+
+use crate::message::{ConfigEntry, ConfigValue, Message};
+
+/// The agent's settings, updated by the server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigStore {
+    /// The version of the last update applied, 0 before any
+    version: u64,
+    values: BTreeMap<String, ConfigValue>,
+}
+
+impl ConfigStore {
+    /// Create a store with no settings, which rejects every key until it is declared.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept updates to `key`, with values of the same type as `default`, which it holds until
+    /// the first update.
+    pub fn declare(&mut self, key: impl Into<String>, default: ConfigValue) {
+        self.values.insert(key.into(), default);
+    }
+
+    /// The version of the last update applied, or 0 if none has been.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// The current value of a setting, or `None` if it was not declared.
+    pub fn get(&self, key: &str) -> Option<&ConfigValue> {
+        self.values.get(key)
+    }
+
+    /// Every setting and its current value, ordered by key.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &ConfigValue)> {
+        self.values.iter().map(|(key, value)| (key.as_str(), value))
+    }
+
+    /// Apply a `Message::ConfigUpdate`, returning the `Message::ConfigAck` to send, or `None`
+    /// for any other message.
+    ///
+    /// The update is applied only if every key has been declared and every value has the
+    /// declared type. An update with the current version has already been applied and is
+    /// acknowledged again, while one with an older version is rejected, as is one with version
+    /// 0, since versions start from 1.
+    pub fn apply(&mut self, update: &Message) -> Option<Message> {
+        let Message::ConfigUpdate { version, entries } = update else {
+            return None;
+        };
+        if *version == 0 {
+            return Some(self.ack(
+                false,
+                Vec::new(),
+                Some(String::from("version 0 is not a valid version")),
+            ));
+        }
+        if *version < self.version {
+            return Some(self.ack(
+                false,
+                Vec::new(),
+                Some(format!(
+                    "version {} is older than the current version {}",
+                    version, self.version
+                )),
+            ));
+        }
+        if *version == self.version {
+            return Some(self.ack(true, Vec::new(), None));
+        }
+
+        let rejected: Vec<String> = entries
+            .iter()
+            .filter(|entry| !self.accepts(entry))
+            .map(|entry| entry.key.clone())
+            .collect();
+        if !rejected.is_empty() {
+            let reason = format!(
+                "{} of {} entries have an unknown key or the wrong type",
+                rejected.len(),
+                entries.len()
+            );
+            return Some(self.ack(false, rejected, Some(reason)));
+        }
+
+        for entry in entries {
+            self.values.insert(entry.key.clone(), entry.value.clone());
+        }
+        self.version = *version;
+        Some(self.ack(true, Vec::new(), None))
+    }
+
+    fn accepts(&self, entry: &ConfigEntry) -> bool {
+        match self.values.get(&entry.key) {
+            Some(current) => discriminant(current) == discriminant(&entry.value),
+            None => false,
+        }
+    }
+
+    fn ack(&self, applied: bool, rejected: Vec<String>, reason: Option<String>) -> Message {
+        Message::ConfigAck {
+            version: self.version,
+            applied,
+            rejected,
+            reason,
+        }
+    }
+}
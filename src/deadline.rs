@@ -0,0 +1,79 @@
+//! Deadlines the server attaches to requests, so that a peer stops working on a request once the
+//! server has given up waiting for it.
+//!
+//! Deadlines are wall clock times in milliseconds since epoch, so the clocks of the agent and the
+//! server should be roughly in sync.
+//!
+//! # Example
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use ws_com_framework::deadline::{deadline_in, with_deadline};
+//! use ws_com_framework::error::ErrorKind;
+//! use ws_com_framework::Message;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! // the server gives the agent 50ms to reply
+//! let req = Message::StatusReq {
+//!     public_id: 43,
+//!     upload_id: 1,
+//!     deadline: Some(deadline_in(Duration::from_millis(50))),
+//! };
+//!
+//! // the agent's handler takes too long, so it is cancelled
+//! let reply = with_deadline(&req, async {
+//!     tokio::time::sleep(Duration::from_secs(10)).await;
+//!     Message::Ok
+//! })
+//! .await;
+//! assert!(matches!(reply, Message::Error { kind: ErrorKind::Timeout, .. }));
+//! # }
+//! ```
+
+use std::future::Future;
+use std::time::Duration;
+
+use crate::error::ErrorKind;
+use crate::message::Message;
+use crate::session::now_millis;
+
+/// The deadline `timeout` from now, in milliseconds since epoch, to attach to a request.
+pub fn deadline_in(timeout: Duration) -> u64 {
+    now_millis().saturating_add(timeout.as_millis().try_into().unwrap_or(u64::MAX))
+}
+
+/// How long is left until the request's deadline, `Duration::ZERO` if it has passed, or `None`
+/// if the request has no deadline.
+pub fn remaining(req: &Message) -> Option<Duration> {
+    req.deadline()
+        .map(|deadline| Duration::from_millis(deadline.saturating_sub(now_millis())))
+}
+
+/// Run `handler` to produce the reply to `req`, cancelling it if the request's deadline passes
+/// first.
+///
+/// A request whose deadline has already passed is not handled at all. In either case the reply
+/// is a `Message::Error` of kind `ErrorKind::Timeout`. Requests without a deadline are handled
+/// for as long as `handler` takes.
+pub async fn with_deadline<F>(req: &Message, handler: F) -> Message
+where
+    F: Future<Output = Message>,
+{
+    let Some(remaining) = remaining(req) else {
+        return handler.await;
+    };
+    if remaining.is_zero() {
+        return timeout_error();
+    }
+    tokio::time::timeout(remaining, handler)
+        .await
+        .unwrap_or_else(|_| timeout_error())
+}
+
+fn timeout_error() -> Message {
+    Message::Error {
+        kind: ErrorKind::Timeout,
+        reason: Some(String::from("the request's deadline passed")),
+    }
+}
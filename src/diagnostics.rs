@@ -0,0 +1,366 @@
+//! Pulling recent logs and a status snapshot from an agent on demand.
+//!
+//! The server sends a `Message::DiagnosticsReq` asking for the logs written since a point in
+//! time, and the agent answers with its report split into numbered `Message::DiagnosticsRes`
+//! parts, the last of which is marked as the end. The report is plain text: a `# status` section
+//! with the agent's `StatusReport`, followed by a `# logs` section with one line per log entry. If
+//! the report would exceed the requested size, the oldest lines are dropped first.
+//!
+//! On the agent, `LogBuffer` keeps the most recent log lines and `respond` answers a request. On
+//! the server, `DiagnosticsAssembler` issues requests and reassembles the parts.
+//!
+//! # Example
+//! ```rust
+//! use ws_com_framework::diagnostics::{respond, DiagnosticsAssembler, LogBuffer};
+//! use ws_com_framework::status::StatusReport;
+//!
+//! // on the agent
+//! let mut logs = LogBuffer::new(64 * 1024);
+//! logs.push_at(1_000, "started");
+//! logs.push_at(2_000, "shared report.pdf");
+//!
+//! // on the server
+//! let mut assembler = DiagnosticsAssembler::new();
+//! let req = assembler.request(1, 1_500, 0);
+//!
+//! let status = StatusReport { ready: true, uptime: 60, ..Default::default() };
+//! let parts = respond(&req, Some(&status), &logs, 16).unwrap();
+//! assert!(parts.len() > 1);
+//!
+//! let mut report = None;
+//! for part in parts {
+//!     report = assembler.receive(part).unwrap();
+//! }
+//! let (request_id, data) = report.unwrap();
+//! assert_eq!(request_id, 1);
+//! let text = String::from_utf8(data).unwrap();
+//! assert!(text.contains("uptime: 60"));
+//! assert!(text.contains("2000 shared report.pdf"));
+//! assert!(!text.contains("started"));
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+
+use crate::error::Error;
+use crate::message::Message;
+use crate::session::now_millis;
+use crate::status::StatusReport;
+
+/// The largest report a `DiagnosticsAssembler` accepts by default, whatever the request asked for.
+const MAX_REPORT_BYTES: usize = 16 * 1024 * 1024;
+
+/// The most recent log lines written by an agent, up to a total size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogBuffer {
+    /// Each line and when it was written, in milliseconds since epoch, oldest first
+    lines: VecDeque<(u64, String)>,
+    /// The total length of the lines held
+    bytes: usize,
+    capacity: usize,
+}
+
+impl LogBuffer {
+    /// Create an empty buffer holding up to `capacity` bytes of log lines.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            bytes: 0,
+            capacity,
+        }
+    }
+
+    /// Add a line written now.
+    pub fn push(&mut self, line: impl Into<String>) {
+        self.push_at(now_millis(), line);
+    }
+
+    /// Add a line written at `timestamp`, in milliseconds since epoch, dropping the oldest lines
+    /// until the buffer is within its capacity.
+    pub fn push_at(&mut self, timestamp: u64, line: impl Into<String>) {
+        let line = line.into();
+        self.bytes += line.len();
+        self.lines.push_back((timestamp, line));
+        while self.bytes > self.capacity {
+            match self.lines.pop_front() {
+                Some((_, line)) => self.bytes -= line.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// The total length of the lines held, in bytes.
+    pub fn len(&self) -> usize {
+        self.bytes
+    }
+
+    /// Whether the buffer holds no lines.
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// The lines written at or after `since`, oldest first.
+    pub fn since(&self, since: u64) -> impl Iterator<Item = (u64, &str)> {
+        self.lines
+            .iter()
+            .filter(move |(timestamp, _)| *timestamp >= since)
+            .map(|(timestamp, line)| (*timestamp, line.as_str()))
+    }
+}
+
+/// Build the text of a diagnostics report from `status` and the lines of `logs` written at or
+/// after `since`.
+///
+/// If `max_bytes` is not 0 and the report would be larger, the oldest lines are replaced with a
+/// note saying how many were left out, and if even the status does not fit it is cut short at
+/// the last whole character.
+pub fn collect(
+    since: u64,
+    max_bytes: u64,
+    status: Option<&StatusReport>,
+    logs: &LogBuffer,
+) -> Vec<u8> {
+    let mut header = String::new();
+    if let Some(status) = status {
+        write_status(&mut header, status);
+    }
+    let _ = writeln!(header, "# logs since {}", since);
+
+    let lines: Vec<String> = logs
+        .since(since)
+        .map(|(timestamp, line)| format!("{} {}\n", timestamp, line))
+        .collect();
+    let mut report = header.into_bytes();
+    let max_bytes = usize::try_from(max_bytes).unwrap_or(usize::MAX);
+    let budget = match max_bytes {
+        0 => usize::MAX,
+        max_bytes => max_bytes.saturating_sub(report.len()),
+    };
+
+    let total: usize = lines.iter().map(String::len).sum();
+    let kept = if total <= budget {
+        lines.len()
+    } else {
+        // make room for the note, which is no longer than this one
+        let note = omitted(lines.len()).len();
+        let mut room = budget.saturating_sub(note);
+        lines
+            .iter()
+            .rev()
+            .take_while(|line| match room.checked_sub(line.len()) {
+                Some(left) => {
+                    room = left;
+                    true
+                }
+                None => false,
+            })
+            .count()
+    };
+    if kept < lines.len() {
+        report.extend_from_slice(omitted(lines.len() - kept).as_bytes());
+    }
+    for line in &lines[lines.len() - kept..] {
+        report.extend_from_slice(line.as_bytes());
+    }
+
+    if max_bytes != 0 && max_bytes < report.len() {
+        // back up to the start of the character the limit falls in
+        let mut end = max_bytes;
+        while end > 0 && report[end] & 0xC0 == 0x80 {
+            end -= 1;
+        }
+        report.truncate(end);
+    }
+    report
+}
+
+/// Split a report into `Message::DiagnosticsRes` parts of at most `part_size` bytes, the last
+/// of which is marked as the end. An empty report is sent as a single empty part.
+pub fn split(request_id: u64, report: &[u8], part_size: usize) -> Vec<Message> {
+    let mut parts: Vec<Message> = report
+        .chunks(part_size.max(1))
+        .enumerate()
+        .map(|(part, data)| Message::DiagnosticsRes {
+            request_id,
+            part: part as u32,
+            data: data.to_vec(),
+            end: false,
+        })
+        .collect();
+    match parts.last_mut() {
+        Some(Message::DiagnosticsRes { end, .. }) => *end = true,
+        _ => parts.push(Message::DiagnosticsRes {
+            request_id,
+            part: 0,
+            data: Vec::new(),
+            end: true,
+        }),
+    }
+    parts
+}
+
+/// Answer a `Message::DiagnosticsReq`, returning the `Message::DiagnosticsRes` parts to send in
+/// order, or `None` for any other message.
+pub fn respond(
+    req: &Message,
+    status: Option<&StatusReport>,
+    logs: &LogBuffer,
+    part_size: usize,
+) -> Option<Vec<Message>> {
+    let Message::DiagnosticsReq {
+        request_id,
+        since,
+        max_bytes,
+    } = req
+    else {
+        return None;
+    };
+    let report = collect(*since, *max_bytes, status, logs);
+    Some(split(*request_id, &report, part_size))
+}
+
+/// Reassembles the diagnostics reports the server has requested from agents.
+///
+/// Reports are limited to the `max_bytes` of their request, and never exceed the assembler's own
+/// limit, even if the request asked for no limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiagnosticsAssembler {
+    pending: HashMap<u64, Partial>,
+    /// The largest report accepted for any request
+    max_report: usize,
+}
+
+impl Default for DiagnosticsAssembler {
+    fn default() -> Self {
+        Self {
+            pending: HashMap::new(),
+            max_report: MAX_REPORT_BYTES,
+        }
+    }
+}
+
+/// A report whose end has not yet been received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Partial {
+    /// The largest report accepted, 0 for no limit
+    max_bytes: u64,
+    next_part: u32,
+    data: Vec<u8>,
+}
+
+impl DiagnosticsAssembler {
+    /// Create an assembler with no requests in progress, accepting reports of up to 16 MiB.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept reports of up to `max_report` bytes, instead of 16 MiB.
+    pub fn with_max_report(mut self, max_report: usize) -> Self {
+        self.max_report = max_report;
+        self
+    }
+
+    /// Start a request, returning the `Message::DiagnosticsReq` to send.
+    ///
+    /// Any earlier request with the same `request_id` is abandoned.
+    pub fn request(&mut self, request_id: u64, since: u64, max_bytes: u64) -> Message {
+        self.pending.insert(
+            request_id,
+            Partial {
+                max_bytes,
+                next_part: 0,
+                data: Vec::new(),
+            },
+        );
+        Message::DiagnosticsReq {
+            request_id,
+            since,
+            max_bytes,
+        }
+    }
+
+    /// Whether a request is waiting for more parts.
+    pub fn is_pending(&self, request_id: u64) -> bool {
+        self.pending.contains_key(&request_id)
+    }
+
+    /// Abandon a request, returning whether it was in progress.
+    pub fn cancel(&mut self, request_id: u64) -> bool {
+        self.pending.remove(&request_id).is_some()
+    }
+
+    /// Add a `Message::DiagnosticsRes` to its report, returning the request id and the whole
+    /// report once its last part has arrived. Any other message is ignored.
+    ///
+    /// Fails with `Error::DiagnosticsError`, abandoning the request, if the part was not
+    /// requested, arrives out of order, or takes the report past its `max_bytes` or the
+    /// assembler's limit.
+    pub fn receive(&mut self, msg: Message) -> Result<Option<(u64, Vec<u8>)>, Error> {
+        let Message::DiagnosticsRes {
+            request_id,
+            part,
+            data,
+            end,
+        } = msg
+        else {
+            return Ok(None);
+        };
+        let error = |reason: String| Error::DiagnosticsError { request_id, reason };
+
+        let partial = self
+            .pending
+            .get_mut(&request_id)
+            .ok_or_else(|| error(String::from("was not requested")))?;
+        if part != partial.next_part {
+            let expected = partial.next_part;
+            self.pending.remove(&request_id);
+            return Err(error(format!(
+                "expected part {} but got {}",
+                expected, part
+            )));
+        }
+        let limit = match usize::try_from(partial.max_bytes) {
+            Ok(0) | Err(_) => self.max_report,
+            Ok(max_bytes) => max_bytes.min(self.max_report),
+        };
+        if partial.data.len().saturating_add(data.len()) > limit {
+            self.pending.remove(&request_id);
+            return Err(error(format!("exceeds the limit of {} bytes", limit)));
+        }
+        partial.data.extend_from_slice(&data);
+        partial.next_part += 1;
+
+        if !end {
+            return Ok(None);
+        }
+        let partial = self.pending.remove(&request_id).expect("checked above");
+        Ok(Some((request_id, partial.data)))
+    }
+}
+
+fn write_status(out: &mut String, status: &StatusReport) {
+    let _ = writeln!(out, "# status");
+    let _ = writeln!(out, "ready: {}", status.ready);
+    let _ = writeln!(out, "uptime: {}", status.uptime);
+    let optional = [
+        ("message", status.message.clone()),
+        ("active_shares", status.active_shares.map(|v| v.to_string())),
+        ("bytes_shared", status.bytes_shared.map(|v| v.to_string())),
+        (
+            "uploads_in_progress",
+            status.uploads_in_progress.map(|v| v.to_string()),
+        ),
+        ("free_disk", status.free_disk.map(|v| v.to_string())),
+        ("agent_version", status.agent_version.clone()),
+        ("os", status.os.clone()),
+    ];
+    for (name, value) in optional {
+        if let Some(value) = value {
+            let _ = writeln!(out, "{}: {}", name, value);
+        }
+    }
+}
+
+fn omitted(lines: usize) -> String {
+    format!("# {} earlier lines omitted\n", lines)
+}
@@ -0,0 +1,964 @@
+//! Encodes a borrowed `Message` straight into the protobuf3 wire format.
+//!
+//! The types in `websocket_message::protobuf_types` own their data, so going through them
+//! means moving every field into a new struct, encoding the inner message into one buffer and
+//! then copying that buffer into the `FspComm` envelope. Here we walk the fields of a
+//! `Message` in tag order and write the envelope and inner message in a single pass, producing
+//! exactly the bytes prost would.
+
+use std::collections::BTreeMap;
+
+use bytes::BufMut;
+use prost::encoding::{encode_key, encode_varint, encoded_len_varint, key_len, WireType};
+
+use crate::error::Error;
+use crate::message::websocket_message::protobuf_types::fsp_comm::Type;
+use crate::message::websocket_message::protobuf_types::FspComm;
+use crate::message::{AgentInfo, ConfigEntry, ConfigValue, Message};
+
+/// Maximum lengths of variable sized fields.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FieldLimits {
+    pub(crate) max_string_len: usize,
+    pub(crate) max_bytes_len: usize,
+}
+
+/// Fail if a field of `size` bytes is longer than `limit`.
+fn check_len(field: &'static str, size: usize, limit: usize) -> Result<(), Error> {
+    if size > limit {
+        return Err(Error::FieldTooLarge { field, size, limit });
+    }
+    Ok(())
+}
+
+/// A single protobuf3 field, following the proto3 rule that default values are not written.
+pub(crate) trait Field {
+    /// Length of this field on the wire, including its key.
+    fn encoded_len(&self, tag: u32) -> usize;
+    /// Write this field, including its key, into the buffer.
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B);
+    /// Check this field against the provided limits, only variable sized fields can fail.
+    fn check(&self, _name: &'static str, _limits: &FieldLimits) -> Result<(), Error> {
+        Ok(())
+    }
+    /// Check the contents of a length delimited field with this field's tag against the
+    /// provided limits, before it is decoded. `index` counts the earlier occurrences of the
+    /// field, for repeated fields.
+    fn check_encoded(
+        &self,
+        _name: &'static str,
+        _index: usize,
+        _value: &[u8],
+        _limits: &FieldLimits,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// A field read from the wire without being decoded.
+enum WireValue<'a> {
+    Varint(u64),
+    Delimited(&'a [u8]),
+}
+
+/// Call `f` with the tag and value of every varint and length delimited field of an encoded
+/// message, skipping over any other fields.
+fn for_each_field<'a, F>(mut buf: &'a [u8], mut f: F) -> Result<(), Error>
+where
+    F: FnMut(u32, WireValue<'a>) -> Result<(), Error>,
+{
+    use prost::encoding::{decode_key, decode_varint, skip_field, DecodeContext};
+
+    while !buf.is_empty() {
+        let (tag, wire_type) = decode_key(&mut buf)?;
+        match wire_type {
+            WireType::Varint => f(tag, WireValue::Varint(decode_varint(&mut buf)?))?,
+            WireType::LengthDelimited => {
+                let len = decode_varint(&mut buf)?;
+                if len > buf.len() as u64 {
+                    return Err(Error::ByteDecodeError(String::from("buffer underflow")));
+                }
+                let (value, rest) = buf.split_at(len as usize);
+                buf = rest;
+                f(tag, WireValue::Delimited(value))?;
+            }
+            _ => skip_field(wire_type, tag, &mut buf, DecodeContext::default())?,
+        }
+    }
+    Ok(())
+}
+
+/// Check the length delimited fields of an encoded nested message which holds only strings,
+/// named by their tag.
+fn check_encoded_strings(
+    value: &[u8],
+    names: &[(u32, &'static str)],
+    limits: &FieldLimits,
+) -> Result<(), Error> {
+    for_each_field(value, |tag, value| match value {
+        WireValue::Delimited(value) => match names.iter().find(|(t, _)| *t == tag) {
+            Some((_, name)) => check_len(name, value.len(), limits.max_string_len),
+            None => Ok(()),
+        },
+        WireValue::Varint(_) => Ok(()),
+    })
+}
+
+impl Field for u32 {
+    fn encoded_len(&self, tag: u32) -> usize {
+        if *self == 0 {
+            return 0;
+        }
+        prost::encoding::uint32::encoded_len(tag, self)
+    }
+
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+        if *self != 0 {
+            prost::encoding::uint32::encode(tag, self, buf);
+        }
+    }
+}
+
+impl Field for u64 {
+    fn encoded_len(&self, tag: u32) -> usize {
+        if *self == 0 {
+            return 0;
+        }
+        prost::encoding::uint64::encoded_len(tag, self)
+    }
+
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+        if *self != 0 {
+            prost::encoding::uint64::encode(tag, self, buf);
+        }
+    }
+}
+
+impl Field for i32 {
+    fn encoded_len(&self, tag: u32) -> usize {
+        if *self == 0 {
+            return 0;
+        }
+        prost::encoding::int32::encoded_len(tag, self)
+    }
+
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+        if *self != 0 {
+            prost::encoding::int32::encode(tag, self, buf);
+        }
+    }
+}
+
+impl Field for bool {
+    fn encoded_len(&self, tag: u32) -> usize {
+        if !*self {
+            return 0;
+        }
+        prost::encoding::bool::encoded_len(tag, self)
+    }
+
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+        if *self {
+            prost::encoding::bool::encode(tag, self, buf);
+        }
+    }
+}
+
+impl Field for [u8] {
+    fn encoded_len(&self, tag: u32) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+        key_len(tag) + encoded_len_varint(self.len() as u64) + self.len()
+    }
+
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+        if !self.is_empty() {
+            encode_key(tag, WireType::LengthDelimited, buf);
+            encode_varint(self.len() as u64, buf);
+            buf.put_slice(self);
+        }
+    }
+
+    fn check(&self, name: &'static str, limits: &FieldLimits) -> Result<(), Error> {
+        check_len(name, self.len(), limits.max_bytes_len)
+    }
+
+    fn check_encoded(
+        &self,
+        name: &'static str,
+        _index: usize,
+        value: &[u8],
+        limits: &FieldLimits,
+    ) -> Result<(), Error> {
+        check_len(name, value.len(), limits.max_bytes_len)
+    }
+}
+
+impl Field for Vec<u8> {
+    fn encoded_len(&self, tag: u32) -> usize {
+        self[..].encoded_len(tag)
+    }
+
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+        self[..].encode(tag, buf)
+    }
+
+    fn check(&self, name: &'static str, limits: &FieldLimits) -> Result<(), Error> {
+        self[..].check(name, limits)
+    }
+
+    fn check_encoded(
+        &self,
+        name: &'static str,
+        index: usize,
+        value: &[u8],
+        limits: &FieldLimits,
+    ) -> Result<(), Error> {
+        self[..].check_encoded(name, index, value, limits)
+    }
+}
+
+impl Field for String {
+    fn encoded_len(&self, tag: u32) -> usize {
+        self.as_bytes().encoded_len(tag)
+    }
+
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+        self.as_bytes().encode(tag, buf)
+    }
+
+    fn check(&self, name: &'static str, limits: &FieldLimits) -> Result<(), Error> {
+        check_len(name, self.len(), limits.max_string_len)
+    }
+
+    fn check_encoded(
+        &self,
+        name: &'static str,
+        _index: usize,
+        value: &[u8],
+        limits: &FieldLimits,
+    ) -> Result<(), Error> {
+        check_len(name, value.len(), limits.max_string_len)
+    }
+}
+
+/// Optional fields are always written when present, even if they hold a default value.
+impl Field for Option<String> {
+    fn encoded_len(&self, tag: u32) -> usize {
+        match self {
+            Some(v) => prost::encoding::string::encoded_len(tag, v),
+            None => 0,
+        }
+    }
+
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+        if let Some(v) = self {
+            prost::encoding::string::encode(tag, v, buf);
+        }
+    }
+
+    fn check(&self, name: &'static str, limits: &FieldLimits) -> Result<(), Error> {
+        match self {
+            Some(v) => v.check(name, limits),
+            None => Ok(()),
+        }
+    }
+
+    fn check_encoded(
+        &self,
+        name: &'static str,
+        _index: usize,
+        value: &[u8],
+        limits: &FieldLimits,
+    ) -> Result<(), Error> {
+        check_len(name, value.len(), limits.max_string_len)
+    }
+}
+
+/// Optional fields are always written when present, even if they hold a default value.
+impl Field for Option<u32> {
+    fn encoded_len(&self, tag: u32) -> usize {
+        match self {
+            Some(v) => prost::encoding::uint32::encoded_len(tag, v),
+            None => 0,
+        }
+    }
+
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+        if let Some(v) = self {
+            prost::encoding::uint32::encode(tag, v, buf);
+        }
+    }
+}
+
+/// Optional fields are always written when present, even if they hold a default value.
+impl Field for Option<u64> {
+    fn encoded_len(&self, tag: u32) -> usize {
+        match self {
+            Some(v) => prost::encoding::uint64::encoded_len(tag, v),
+            None => 0,
+        }
+    }
+
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+        if let Some(v) = self {
+            prost::encoding::uint64::encode(tag, v, buf);
+        }
+    }
+}
+
+/// A nested message, which is always written since `Message::RegisterReq` always sets it.
+impl Field for AgentInfo {
+    fn encoded_len(&self, tag: u32) -> usize {
+        let len = agent_info_len(self);
+        key_len(tag) + encoded_len_varint(len as u64) + len
+    }
+
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+        let len = agent_info_len(self);
+        encode_key(tag, WireType::LengthDelimited, buf);
+        encode_varint(len as u64, buf);
+        self.hostname.encode(1, buf);
+        self.version.encode(2, buf);
+        self.os.encode(3, buf);
+    }
+
+    fn check(&self, _name: &'static str, limits: &FieldLimits) -> Result<(), Error> {
+        self.hostname.check("hostname", limits)?;
+        self.version.check("version", limits)?;
+        self.os.check("os", limits)
+    }
+
+    fn check_encoded(
+        &self,
+        _name: &'static str,
+        _index: usize,
+        value: &[u8],
+        limits: &FieldLimits,
+    ) -> Result<(), Error> {
+        check_encoded_strings(value, &[(1, "hostname"), (2, "version"), (3, "os")], limits)
+    }
+}
+
+/// Length of the fields of an `AgentInfo`, without its key and length prefix.
+fn agent_info_len(info: &AgentInfo) -> usize {
+    info.hostname.encoded_len(1) + info.version.encoded_len(2) + info.os.encoded_len(3)
+}
+
+/// Repeated strings, every one of which is written even if empty.
+impl Field for [String] {
+    fn encoded_len(&self, tag: u32) -> usize {
+        prost::encoding::string::encoded_len_repeated(tag, self)
+    }
+
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+        for v in self {
+            prost::encoding::string::encode(tag, v, buf);
+        }
+    }
+
+    fn check(&self, name: &'static str, limits: &FieldLimits) -> Result<(), Error> {
+        self.iter().try_for_each(|v| v.check(name, limits))
+    }
+
+    fn check_encoded(
+        &self,
+        name: &'static str,
+        _index: usize,
+        value: &[u8],
+        limits: &FieldLimits,
+    ) -> Result<(), Error> {
+        check_len(name, value.len(), limits.max_string_len)
+    }
+}
+
+impl Field for Vec<String> {
+    fn encoded_len(&self, tag: u32) -> usize {
+        self[..].encoded_len(tag)
+    }
+
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+        self[..].encode(tag, buf)
+    }
+
+    fn check(&self, name: &'static str, limits: &FieldLimits) -> Result<(), Error> {
+        self[..].check(name, limits)
+    }
+
+    fn check_encoded(
+        &self,
+        name: &'static str,
+        index: usize,
+        value: &[u8],
+        limits: &FieldLimits,
+    ) -> Result<(), Error> {
+        self[..].check_encoded(name, index, value, limits)
+    }
+}
+
+/// Repeated settings, as carried by `Message::ConfigUpdate`. The value of each is a `oneof`,
+/// so it is written even if it holds a default value.
+impl Field for [ConfigEntry] {
+    fn encoded_len(&self, tag: u32) -> usize {
+        self.iter()
+            .map(|entry| {
+                let len = config_entry_len(entry);
+                key_len(tag) + encoded_len_varint(len as u64) + len
+            })
+            .sum()
+    }
+
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+        for entry in self {
+            encode_key(tag, WireType::LengthDelimited, buf);
+            encode_varint(config_entry_len(entry) as u64, buf);
+            entry.key.encode(1, buf);
+            match &entry.value {
+                ConfigValue::Bool(v) => prost::encoding::bool::encode(2, v, buf),
+                ConfigValue::Int(v) => prost::encoding::int64::encode(3, v, buf),
+                ConfigValue::Text(v) => prost::encoding::string::encode(4, v, buf),
+            }
+        }
+    }
+
+    fn check(&self, _name: &'static str, limits: &FieldLimits) -> Result<(), Error> {
+        self.iter().try_for_each(|entry| {
+            entry.key.check("key", limits)?;
+            match &entry.value {
+                ConfigValue::Text(v) => v.check("text_value", limits),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn check_encoded(
+        &self,
+        _name: &'static str,
+        _index: usize,
+        value: &[u8],
+        limits: &FieldLimits,
+    ) -> Result<(), Error> {
+        check_encoded_strings(value, &[(1, "key"), (4, "text_value")], limits)
+    }
+}
+
+impl Field for Vec<ConfigEntry> {
+    fn encoded_len(&self, tag: u32) -> usize {
+        self[..].encoded_len(tag)
+    }
+
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+        self[..].encode(tag, buf)
+    }
+
+    fn check(&self, name: &'static str, limits: &FieldLimits) -> Result<(), Error> {
+        self[..].check(name, limits)
+    }
+
+    fn check_encoded(
+        &self,
+        name: &'static str,
+        index: usize,
+        value: &[u8],
+        limits: &FieldLimits,
+    ) -> Result<(), Error> {
+        self[..].check_encoded(name, index, value, limits)
+    }
+}
+
+/// Length of the fields of a `ConfigEntry`, without its key and length prefix.
+fn config_entry_len(entry: &ConfigEntry) -> usize {
+    entry.key.encoded_len(1)
+        + match &entry.value {
+            ConfigValue::Bool(v) => prost::encoding::bool::encoded_len(2, v),
+            ConfigValue::Int(v) => prost::encoding::int64::encoded_len(3, v),
+            ConfigValue::Text(v) => prost::encoding::string::encoded_len(4, v),
+        }
+}
+
+/// Repeated envelopes, as carried by `Message::Batch`.
+impl Field for [Message] {
+    fn encoded_len(&self, tag: u32) -> usize {
+        self.iter()
+            .map(|msg| {
+                let len = encoded_len(msg);
+                key_len(tag) + encoded_len_varint(len as u64) + len
+            })
+            .sum()
+    }
+
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+        for msg in self {
+            encode_key(tag, WireType::LengthDelimited, buf);
+            encode_varint(encoded_len(msg) as u64, buf);
+            encode(msg, buf);
+        }
+    }
+
+    fn check(&self, _name: &'static str, limits: &FieldLimits) -> Result<(), Error> {
+        for (index, msg) in self.iter().enumerate() {
+            let res = match msg {
+                Message::Batch { .. } => Err(Error::ByteEncodeError(String::from(
+                    "batches cannot be nested",
+                ))),
+                _ => check_fields(msg, limits),
+            };
+            res.map_err(|e| Error::BatchEntryError {
+                index,
+                error: Box::new(e),
+            })?;
+        }
+        Ok(())
+    }
+
+    fn check_encoded(
+        &self,
+        _name: &'static str,
+        index: usize,
+        value: &[u8],
+        limits: &FieldLimits,
+    ) -> Result<(), Error> {
+        let (mut ty, mut compression, mut inner) = (0, 0, &[][..]);
+        for_each_field(value, |tag, value| {
+            match (tag, value) {
+                (1, WireValue::Varint(v)) => ty = v as i32,
+                (2, WireValue::Delimited(v)) => inner = v,
+                (3, WireValue::Varint(v)) => compression = v as i32,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        // nested batches and compressed entries are rejected when the entry is decoded
+        if ty == Type::Batch as i32 || compression != 0 {
+            return Ok(());
+        }
+        check_encoded_fields(ty, inner, limits).map_err(|e| Error::BatchEntryError {
+            index,
+            error: Box::new(e),
+        })
+    }
+}
+
+impl Field for Vec<Message> {
+    fn encoded_len(&self, tag: u32) -> usize {
+        self[..].encoded_len(tag)
+    }
+
+    fn encode<B: BufMut>(&self, tag: u32, buf: &mut B) {
+        self[..].encode(tag, buf)
+    }
+
+    fn check(&self, name: &'static str, limits: &FieldLimits) -> Result<(), Error> {
+        self[..].check(name, limits)
+    }
+
+    fn check_encoded(
+        &self,
+        name: &'static str,
+        index: usize,
+        value: &[u8],
+        limits: &FieldLimits,
+    ) -> Result<(), Error> {
+        self[..].check_encoded(name, index, value, limits)
+    }
+}
+
+/// Visits each field of a message, in tag order.
+pub(crate) trait Visitor {
+    /// Called once for every field of the message.
+    fn field<F: Field + ?Sized>(&mut self, tag: u32, name: &'static str, value: &F);
+}
+
+/// Sums the encoded length of every field visited.
+struct LenVisitor(usize);
+
+impl Visitor for LenVisitor {
+    fn field<F: Field + ?Sized>(&mut self, tag: u32, _: &'static str, value: &F) {
+        self.0 += value.encoded_len(tag);
+    }
+}
+
+/// Writes every field visited into the buffer.
+struct EncodeVisitor<'a, B>(&'a mut B);
+
+impl<B: BufMut> Visitor for EncodeVisitor<'_, B> {
+    fn field<F: Field + ?Sized>(&mut self, tag: u32, _: &'static str, value: &F) {
+        value.encode(tag, self.0);
+    }
+}
+
+/// Checks every field visited, stopping at the first one over its limit.
+struct CheckVisitor<'a> {
+    limits: &'a FieldLimits,
+    result: Result<(), Error>,
+}
+
+impl Visitor for CheckVisitor<'_> {
+    fn field<F: Field + ?Sized>(&mut self, _: u32, name: &'static str, value: &F) {
+        if self.result.is_ok() {
+            self.result = value.check(name, self.limits);
+        }
+    }
+}
+
+/// Checks the contents of a length delimited field against the field visited with its tag.
+struct EncodedCheckVisitor<'a> {
+    tag: u32,
+    index: usize,
+    value: &'a [u8],
+    limits: &'a FieldLimits,
+    result: Result<(), Error>,
+}
+
+impl Visitor for EncodedCheckVisitor<'_> {
+    fn field<F: Field + ?Sized>(&mut self, tag: u32, name: &'static str, value: &F) {
+        if tag == self.tag {
+            self.result = value.check_encoded(name, self.index, self.value, self.limits);
+        }
+    }
+}
+
+/// The envelope type for this message.
+pub(crate) fn message_type(msg: &Message) -> Type {
+    match msg {
+        Message::Ok => Type::Ok,
+        Message::Error { .. } => Type::Error,
+        Message::UploadTo { .. } => Type::UploadTo,
+        Message::MetadataReq { .. } => Type::MetadataReq,
+        Message::MetadataRes { .. } => Type::MetadataRes,
+        Message::AuthReq { .. } => Type::Authreq,
+        Message::AuthRes { .. } => Type::Auth,
+        Message::StatusReq { .. } => Type::StatusReq,
+        Message::StatusRes { .. } => Type::StatusRes,
+        Message::Batch { .. } => Type::Batch,
+        Message::StreamData { .. } => Type::StreamData,
+        Message::WindowUpdate { .. } => Type::WindowUpdate,
+        Message::Goodbye { .. } => Type::Goodbye,
+        Message::Redirect { .. } => Type::Redirect,
+        Message::TokenAuth { .. } => Type::TokenAuth,
+        Message::AuthOk { .. } => Type::AuthOk,
+        Message::RefreshSession => Type::RefreshSession,
+        Message::RotatePasscodeReq { .. } => Type::RotatePasscodeReq,
+        Message::RotatePasscodeRes { .. } => Type::RotatePasscodeRes,
+        Message::RegisterReq { .. } => Type::RegisterReq,
+        Message::RegisterRes { .. } => Type::RegisterRes,
+        Message::ConfigUpdate { .. } => Type::ConfigUpdate,
+        Message::ConfigAck { .. } => Type::ConfigAck,
+        Message::DiagnosticsReq { .. } => Type::DiagnosticsReq,
+        Message::DiagnosticsRes { .. } => Type::DiagnosticsRes,
+        Message::StreamReset { .. } => Type::StreamReset,
+    }
+}
+
+/// Visit the fields of the inner message, which is carried in `FspComm.value`.
+///
+/// The tags here must match those in `message.proto`.
+pub(crate) fn visit_fields<V: Visitor>(msg: &Message, v: &mut V) {
+    match msg {
+        Message::Ok => {}
+        Message::Error { kind, reason } => {
+            v.field(1, "kind", &(*kind as i32));
+            v.field(3, "reason", reason);
+        }
+        Message::UploadTo {
+            file_id,
+            upload_url,
+            deadline,
+        } => {
+            v.field(1, "file_id", file_id);
+            v.field(2, "upload_url", upload_url);
+            v.field(3, "deadline", deadline);
+        }
+        Message::MetadataReq {
+            file_id,
+            upload_id,
+            deadline,
+        } => {
+            v.field(1, "file_id", file_id);
+            v.field(2, "upload_id", upload_id);
+            v.field(3, "deadline", deadline);
+        }
+        Message::MetadataRes {
+            file_id,
+            exp,
+            crt,
+            file_size,
+            username,
+            file_name,
+            upload_id,
+        } => {
+            v.field(1, "file_id", file_id);
+            v.field(2, "exp", exp);
+            v.field(3, "crt", crt);
+            v.field(4, "file_size", file_size);
+            v.field(5, "username", username);
+            v.field(6, "file_name", file_name);
+            v.field(7, "upload_id", upload_id);
+        }
+        Message::AuthReq { public_id } => {
+            v.field(1, "public_id", public_id);
+        }
+        Message::AuthRes {
+            public_id,
+            passcode,
+        } => {
+            v.field(1, "public_id", public_id);
+            v.field(2, "passcode", passcode);
+        }
+        Message::StatusReq {
+            public_id,
+            upload_id,
+            deadline,
+        } => {
+            v.field(1, "public_id", public_id);
+            v.field(2, "upload_id", upload_id);
+            v.field(3, "deadline", deadline);
+        }
+        Message::StatusRes {
+            public_id,
+            ready,
+            uptime,
+            upload_id,
+            message,
+            active_shares,
+            bytes_shared,
+            uploads_in_progress,
+            free_disk,
+            agent_version,
+            os,
+        } => {
+            v.field(1, "public_id", public_id);
+            v.field(2, "ready", ready);
+            v.field(3, "uptime", uptime);
+            v.field(4, "upload_id", upload_id);
+            v.field(5, "message", message);
+            v.field(6, "active_shares", active_shares);
+            v.field(7, "bytes_shared", bytes_shared);
+            v.field(8, "uploads_in_progress", uploads_in_progress);
+            v.field(9, "free_disk", free_disk);
+            v.field(10, "agent_version", agent_version);
+            v.field(11, "os", os);
+        }
+        Message::Batch { messages } => {
+            v.field(1, "messages", messages);
+        }
+        Message::StreamData { data, end } => {
+            v.field(1, "data", data);
+            v.field(2, "end", end);
+        }
+        Message::WindowUpdate { increment } => {
+            v.field(1, "increment", increment);
+        }
+        Message::Goodbye {
+            reason,
+            reconnect_after,
+            redirect_url,
+        } => {
+            v.field(1, "reason", reason);
+            v.field(2, "reconnect_after", reconnect_after);
+            v.field(3, "redirect_url", redirect_url);
+        }
+        Message::Redirect { url, token } => {
+            v.field(1, "url", url);
+            v.field(2, "token", token);
+        }
+        Message::TokenAuth { public_id, token } => {
+            v.field(1, "public_id", public_id);
+            v.field(2, "token", token);
+        }
+        Message::AuthOk {
+            session_token,
+            expires_at,
+        } => {
+            v.field(1, "session_token", session_token);
+            v.field(2, "expires_at", expires_at);
+        }
+        Message::RefreshSession => {}
+        Message::RotatePasscodeReq {
+            rotation_id,
+            passcode,
+        } => {
+            v.field(1, "rotation_id", rotation_id);
+            v.field(2, "passcode", passcode);
+        }
+        Message::RotatePasscodeRes {
+            rotation_id,
+            accepted,
+            reason,
+        } => {
+            v.field(1, "rotation_id", rotation_id);
+            v.field(2, "accepted", accepted);
+            v.field(3, "reason", reason);
+        }
+        Message::RegisterReq {
+            enrolment_code,
+            agent_info,
+        } => {
+            v.field(1, "enrolment_code", enrolment_code);
+            v.field(2, "agent_info", agent_info);
+        }
+        Message::RegisterRes {
+            public_id,
+            passcode,
+        } => {
+            v.field(1, "public_id", public_id);
+            v.field(2, "passcode", passcode);
+        }
+        Message::ConfigUpdate { version, entries } => {
+            v.field(1, "version", version);
+            v.field(2, "entries", entries);
+        }
+        Message::ConfigAck {
+            version,
+            applied,
+            rejected,
+            reason,
+        } => {
+            v.field(1, "version", version);
+            v.field(2, "applied", applied);
+            v.field(3, "rejected", rejected);
+            v.field(4, "reason", reason);
+        }
+        Message::DiagnosticsReq {
+            request_id,
+            since,
+            max_bytes,
+        } => {
+            v.field(1, "request_id", request_id);
+            v.field(2, "since", since);
+            v.field(3, "max_bytes", max_bytes);
+        }
+        Message::DiagnosticsRes {
+            request_id,
+            part,
+            data,
+            end,
+        } => {
+            v.field(1, "request_id", request_id);
+            v.field(2, "part", part);
+            v.field(3, "data", data);
+            v.field(4, "end", end);
+        }
+        Message::StreamReset { reason } => {
+            v.field(1, "reason", reason);
+        }
+    }
+}
+
+/// Length of the inner message, without the envelope.
+pub(crate) fn inner_len(msg: &Message) -> usize {
+    let mut len = LenVisitor(0);
+    visit_fields(msg, &mut len);
+    len.0
+}
+
+/// Length of an `FspComm` envelope of type `ty`, carrying an inner message of `inner` bytes.
+fn envelope_len(ty: Type, inner: usize) -> usize {
+    let value = if inner == 0 {
+        0
+    } else {
+        key_len(2) + encoded_len_varint(inner as u64) + inner
+    };
+    (ty as i32).encoded_len(1) + value
+}
+
+/// Write an `FspComm` envelope of type `ty`, where `write_inner` writes the `inner` bytes of
+/// the message it carries.
+fn encode_envelope<B: BufMut>(
+    ty: Type,
+    inner: usize,
+    buf: &mut B,
+    write_inner: impl FnOnce(&mut B),
+) {
+    (ty as i32).encode(1, buf);
+    if inner != 0 {
+        encode_key(2, WireType::LengthDelimited, buf);
+        encode_varint(inner as u64, buf);
+        write_inner(buf);
+    }
+}
+
+/// Length of the message once wrapped in its `FspComm` envelope.
+pub(crate) fn encoded_len(msg: &Message) -> usize {
+    envelope_len(message_type(msg), inner_len(msg))
+}
+
+/// Write the message, wrapped in its `FspComm` envelope, into the buffer.
+pub(crate) fn encode<B: BufMut>(msg: &Message, buf: &mut B) {
+    encode_envelope(message_type(msg), inner_len(msg), buf, |buf| {
+        visit_fields(msg, &mut EncodeVisitor(buf))
+    });
+}
+
+/// Build the `FspComm` envelope for a message, so that its value can be compressed or
+/// encrypted before it is sent.
+pub(crate) fn envelope(msg: &Message) -> FspComm {
+    let mut value = Vec::with_capacity(inner_len(msg));
+    visit_fields(msg, &mut EncodeVisitor(&mut value));
+    FspComm {
+        r#type: message_type(msg) as i32,
+        value: value.into(),
+        ..Default::default()
+    }
+}
+
+/// Length of `messages` once encoded as a batch, the same as for an equivalent
+/// `Message::Batch`.
+pub(crate) fn batch_encoded_len(messages: &[Message]) -> usize {
+    envelope_len(Type::Batch, messages.encoded_len(1))
+}
+
+/// Write `messages` into the buffer as a batch, without first collecting them into a
+/// `Message::Batch`.
+pub(crate) fn encode_batch<B: BufMut>(messages: &[Message], buf: &mut B) {
+    encode_envelope(Type::Batch, messages.encoded_len(1), buf, |buf| {
+        messages.encode(1, buf)
+    });
+}
+
+/// Check every field of the message against the provided limits.
+pub(crate) fn check_fields(msg: &Message, limits: &FieldLimits) -> Result<(), Error> {
+    let mut check = CheckVisitor {
+        limits,
+        result: Ok(()),
+    };
+    visit_fields(msg, &mut check);
+    check.result
+}
+
+/// Check the fields of an encoded inner message of type `ty` against the provided limits
+/// before it is decoded, so a field over its limit is never copied out of the frame.
+pub(crate) fn check_encoded_fields(
+    ty: i32,
+    value: &[u8],
+    limits: &FieldLimits,
+) -> Result<(), Error> {
+    // an empty value decodes to the message with every field at its default, which is enough to
+    // find the field with each tag
+    let template = match Message::try_from(FspComm {
+        r#type: ty,
+        ..Default::default()
+    }) {
+        Ok(template) => template,
+        // unknown types are rejected when the message is decoded
+        Err(_) => return Ok(()),
+    };
+    let mut seen: BTreeMap<u32, usize> = BTreeMap::new();
+    for_each_field(value, |tag, value| {
+        let WireValue::Delimited(value) = value else {
+            return Ok(());
+        };
+        let index = seen.entry(tag).or_default();
+        let mut check = EncodedCheckVisitor {
+            tag,
+            index: *index,
+            value,
+            limits,
+            result: Ok(()),
+        };
+        *index += 1;
+        visit_fields(&template, &mut check);
+        check.result
+    })
+}
@@ -0,0 +1,187 @@
+//! Authenticated encryption of messages between an agent and the server.
+//!
+//! Websocket connections to the server are often terminated by a reverse proxy, which would
+//! otherwise see every message in plaintext. A `SessionCipher` encrypts the value of each
+//! envelope with XChaCha20-Poly1305, using keys derived from the agent's `Passcode` that only
+//! the agent and the server know.
+//!
+//! Each end of a connection also picks a random salt, and the key for each direction is derived
+//! from the receiver's salt, so messages captured on an earlier connection cannot be decrypted
+//! on a later one. The server sends its salt with the `Message::AuthReq`, which is the only
+//! message sent unencrypted, and the agent sends its salt with its reply. This includes the
+//! `Message::AuthRes`, which means the passcode itself is never sent in the clear, and the
+//! server knows the agent holds the correct passcode as soon as that message decrypts.
+
+use std::sync::RwLock;
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+use crate::error::Error;
+use crate::message::websocket_message::protobuf_types::fsp_comm::Type;
+use crate::message::websocket_message::protobuf_types::FspComm;
+use crate::message::PublicId;
+use crate::session::{carries_salt, derive_key, session_salt, Role, SESSION_SALT_LEN};
+
+/// Salt used when deriving keys, which ties them to this protocol.
+const KDF_SALT: &[u8] = b"ws-com-framework session cipher v1";
+
+/// Encrypts outgoing and decrypts incoming messages for one end of a connection.
+///
+/// Each direction uses its own key, so a message can never be reflected back to its sender.
+/// A new cipher should be created for every connection.
+pub struct SessionCipher {
+    role: Role,
+    public_id: PublicId,
+    passcode: Vec<u8>,
+    /// Our salt for this connection, which the peer's key is derived from
+    salt: [u8; SESSION_SALT_LEN],
+    open: XChaCha20Poly1305,
+    /// Derived from the peer's salt, once it has been received
+    seal: RwLock<Option<XChaCha20Poly1305>>,
+}
+
+impl Clone for SessionCipher {
+    fn clone(&self) -> Self {
+        Self {
+            passcode: self.passcode.clone(),
+            open: self.open.clone(),
+            seal: RwLock::new(
+                self.seal
+                    .read()
+                    .expect("session cipher lock poisoned")
+                    .clone(),
+            ),
+            ..*self
+        }
+    }
+}
+
+impl std::fmt::Debug for SessionCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionCipher").finish_non_exhaustive()
+    }
+}
+
+impl SessionCipher {
+    /// Derive the cipher for `role`'s end of a connection with the agent `public_id`, which
+    /// authenticates with `passcode`.
+    ///
+    /// Messages can be received straight away, but only the `Message::AuthReq` can be sent
+    /// until the peer's salt has been received in the handshake.
+    pub fn new(role: Role, public_id: PublicId, passcode: &[u8]) -> Self {
+        let salt = session_salt();
+        let peer = match role {
+            Role::Agent => Role::Server,
+            Role::Server => Role::Agent,
+        };
+        let open = derive_key(KDF_SALT, peer, public_id, passcode, &salt);
+        Self {
+            role,
+            public_id,
+            passcode: passcode.to_vec(),
+            salt,
+            open: XChaCha20Poly1305::new(&open.into()),
+            seal: RwLock::new(None),
+        }
+    }
+
+    /// Encrypt the value of an outgoing envelope, or attach our salt to an `AuthReq`.
+    pub(crate) fn seal(&self, mut envelope: FspComm) -> Result<FspComm, Error> {
+        if carries_salt(envelope.r#type) {
+            envelope.encryption_salt = self.salt.to_vec().into();
+        }
+        // sent before the peer's salt is known, so there is no key to encrypt it with
+        if envelope.r#type == Type::Authreq as i32 {
+            return Ok(envelope);
+        }
+
+        let seal = self.seal.read().expect("session cipher lock poisoned");
+        let seal = seal.as_ref().ok_or_else(|| {
+            Error::EncryptionError(String::from(
+                "cannot encrypt a message before the peer's salt is received in the handshake",
+            ))
+        })?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let value = seal
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: &envelope.value,
+                    aad: &associated_data(&envelope),
+                },
+            )
+            .expect("Should never fail, as encrypting into a Vec<u8> is infallible");
+        Ok(FspComm {
+            value: value.into(),
+            nonce: nonce.to_vec().into(),
+            ..envelope
+        })
+    }
+
+    /// Decrypt the value of a received envelope, failing if it has been tampered with.
+    pub(crate) fn open(&self, envelope: FspComm) -> Result<FspComm, Error> {
+        if envelope.r#type == Type::Authreq as i32 && envelope.nonce.is_empty() {
+            self.receive_salt(&envelope.encryption_salt)?;
+            return Ok(envelope);
+        }
+        if envelope.nonce.is_empty() {
+            return Err(Error::EncryptionError(String::from(
+                "received an unencrypted message on an encrypted session",
+            )));
+        }
+        if envelope.nonce.len() != 24 {
+            return Err(Error::EncryptionError(format!(
+                "invalid nonce length {}",
+                envelope.nonce.len()
+            )));
+        }
+        let value = self
+            .open
+            .decrypt(
+                XNonce::from_slice(&envelope.nonce),
+                Payload {
+                    msg: &envelope.value,
+                    aad: &associated_data(&envelope),
+                },
+            )
+            .map_err(|_| {
+                Error::EncryptionError(String::from(
+                    "unable to decrypt message, it may have been tampered with",
+                ))
+            })?;
+        if carries_salt(envelope.r#type) {
+            self.receive_salt(&envelope.encryption_salt)?;
+        }
+        Ok(FspComm {
+            value: value.into(),
+            nonce: Default::default(),
+            ..envelope
+        })
+    }
+
+    /// Derive the key for the messages we send from the peer's salt.
+    fn receive_salt(&self, salt: &[u8]) -> Result<(), Error> {
+        if salt.len() != SESSION_SALT_LEN {
+            return Err(Error::EncryptionError(format!(
+                "invalid session salt length {}",
+                salt.len()
+            )));
+        }
+        let key = derive_key(KDF_SALT, self.role, self.public_id, &self.passcode, salt);
+        *self.seal.write().expect("session cipher lock poisoned") =
+            Some(XChaCha20Poly1305::new(&key.into()));
+        Ok(())
+    }
+}
+
+/// The unencrypted fields of the envelope, which are authenticated alongside the value so that
+/// they cannot be changed in transit.
+fn associated_data(envelope: &FspComm) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(12 + envelope.encryption_salt.len());
+    aad.extend_from_slice(&envelope.r#type.to_be_bytes());
+    aad.extend_from_slice(&envelope.compression.to_be_bytes());
+    aad.extend_from_slice(&envelope.stream_id.to_be_bytes());
+    aad.extend_from_slice(&envelope.encryption_salt);
+    aad
+}
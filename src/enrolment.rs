@@ -0,0 +1,167 @@
+//! Enrolling new agents with one-time codes.
+//!
+//! Rather than being provisioned with its credentials, an agent can be given a short enrolment
+//! code. When it first connects, it answers the server's `AuthReq` with a `Message::RegisterReq`
+//! carrying the code, and the server replies with a `Message::RegisterRes` carrying the agent's
+//! new `PublicId` and `Passcode`, which it authenticates with from then on. Each code can only be
+//! redeemed once, and expires if it is not used in time.
+//!
+//! `EnrolmentCodes` issues and redeems codes on the server, and `client::enrol` enrols an agent.
+//!
+//! # Example
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use ws_com_framework::enrolment::EnrolmentCodes;
+//! use ws_com_framework::error::ErrorKind;
+//! use ws_com_framework::{AgentInfo, Message};
+//!
+//! let codes = EnrolmentCodes::new();
+//! let code = codes.issue(Duration::from_secs(3600));
+//!
+//! // the agent answers the server's `AuthReq` with the code
+//! let req = Message::RegisterReq {
+//!     enrolment_code: code,
+//!     agent_info: AgentInfo::default(),
+//! };
+//! let res = codes.register(&req, |_info, _passcode| 43);
+//! assert!(matches!(res, Message::RegisterRes { public_id: 43, .. }));
+//!
+//! // the code cannot be used again
+//! let res = codes.register(&req, |_info, _passcode| 44);
+//! assert!(matches!(res, Message::Error { kind: ErrorKind::InvalidEnrolmentCode, .. }));
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::error::{Error, ErrorKind};
+use crate::message::{AgentInfo, Message, Passcode, PublicId};
+use crate::session::now_millis;
+
+/// Characters of an enrolment code, Crockford's base32 which avoids easily confused letters.
+const CODE_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// The number of characters in an enrolment code, each carrying 5 random bits.
+const CODE_LEN: usize = 16;
+
+/// Generate a new random passcode for an agent.
+pub fn generate_passcode() -> Passcode {
+    random_bytes::<32>().to_vec()
+}
+
+/// One-time enrolment codes which have been issued and not yet redeemed.
+///
+/// Cloning gives another handle to the same codes.
+#[derive(Clone, Default)]
+pub struct EnrolmentCodes {
+    /// When each code expires, in milliseconds since epoch
+    codes: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl std::fmt::Debug for EnrolmentCodes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EnrolmentCodes").finish_non_exhaustive()
+    }
+}
+
+impl EnrolmentCodes {
+    /// Create an empty set of codes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Issue a new random code which can be redeemed once within `ttl`, formatted in groups of
+    /// four characters such as `7K2M-Q9XD-4HTB-0WNE`.
+    pub fn issue(&self, ttl: Duration) -> String {
+        let ttl = ttl.as_millis().try_into().unwrap_or(u64::MAX);
+        let bytes = random_bytes::<CODE_LEN>();
+        let code: String = bytes
+            .iter()
+            .map(|b| char::from(CODE_ALPHABET[usize::from(b % 32)]))
+            .collect();
+
+        let now = now_millis();
+        let mut codes = self.lock();
+        codes.retain(|_, expires_at| *expires_at > now);
+        codes.insert(code.clone(), now.saturating_add(ttl));
+        drop(codes);
+
+        code.as_bytes()
+            .chunks(4)
+            .map(|group| std::str::from_utf8(group).expect("codes are ascii"))
+            .collect::<Vec<_>>()
+            .join("-")
+    }
+
+    /// Redeem a code, so that it cannot be used again.
+    ///
+    /// Codes are matched ignoring case, spaces and dashes. Fails with `Error::EnrolmentFailed`
+    /// if the code was never issued, has already been redeemed, or has expired.
+    pub fn redeem(&self, code: &str) -> Result<(), Error> {
+        let code: String = code
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+        match self.lock().remove(&code) {
+            Some(expires_at) if now_millis() < expires_at => Ok(()),
+            Some(expires_at) => Err(Error::EnrolmentFailed(format!(
+                "code expired at {}",
+                expires_at
+            ))),
+            None => Err(Error::EnrolmentFailed(String::from(
+                "unknown or already used code",
+            ))),
+        }
+    }
+
+    /// Answer a `Message::RegisterReq`, redeeming its code and generating the agent a passcode.
+    ///
+    /// `allocate` is passed the agent's description and new passcode, and should store them
+    /// and return the agent's new `PublicId`. Returns the `Message::RegisterRes` to send, or a
+    /// `Message::Error` of kind `ErrorKind::InvalidEnrolmentCode` if the code cannot be
+    /// redeemed.
+    pub fn register<F>(&self, req: &Message, allocate: F) -> Message
+    where
+        F: FnOnce(&AgentInfo, &Passcode) -> PublicId,
+    {
+        let Message::RegisterReq {
+            enrolment_code,
+            agent_info,
+        } = req
+        else {
+            return Message::Error {
+                kind: ErrorKind::Unknown,
+                reason: Some(String::from("expected a registration request")),
+            };
+        };
+        if let Err(e) = self.redeem(enrolment_code) {
+            let reason = match e {
+                Error::EnrolmentFailed(reason) => reason,
+                e => e.to_string(),
+            };
+            return Message::Error {
+                kind: ErrorKind::InvalidEnrolmentCode,
+                reason: Some(reason),
+            };
+        }
+        let passcode = generate_passcode();
+        let public_id = allocate(agent_info, &passcode);
+        Message::RegisterRes {
+            public_id,
+            passcode,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, u64>> {
+        self.codes.lock().expect("enrolment codes lock poisoned")
+    }
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    getrandom::getrandom(&mut bytes).expect("the operating system failed to generate randomness");
+    bytes
+}
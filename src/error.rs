@@ -12,6 +12,10 @@ pub enum ErrorKind {
     FileDoesntExist = 2,
     /// You should have authenticated before
     InvalidSession = 3,
+    /// The request's deadline passed before it could be completed
+    Timeout = 4,
+    /// The enrolment code was unknown, expired or already used
+    InvalidEnrolmentCode = 5,
 }
 
 impl From<i32> for ErrorKind {
@@ -20,6 +24,8 @@ impl From<i32> for ErrorKind {
             1 => Self::FailedFileUpload,
             2 => Self::FileDoesntExist,
             3 => Self::InvalidSession,
+            4 => Self::Timeout,
+            5 => Self::InvalidEnrolmentCode,
             _ => Self::Unknown,
         }
     }
@@ -33,6 +39,113 @@ pub enum Error {
 
     /// Unable to encode provided message to send
     ByteEncodeError(String),
+
+    /// The message is larger than the maximum frame size
+    FrameTooLarge {
+        /// Size of the encoded message in bytes
+        size: usize,
+        /// The maximum frame size in bytes
+        limit: usize,
+    },
+
+    /// A string or bytes field is longer than its maximum length
+    FieldTooLarge {
+        /// Name of the offending field
+        field: &'static str,
+        /// Length of the field in bytes
+        size: usize,
+        /// The maximum length of the field in bytes
+        limit: usize,
+    },
+
+    /// A compressed message could not be decompressed
+    CompressionError(String),
+
+    /// An encrypted message could not be decrypted, or an unencrypted message was received
+    /// where an encrypted one was expected
+    EncryptionError(String),
+
+    /// A message's signature is missing, or does not match its contents
+    InvalidSignature(String),
+
+    /// A signed message with this sequence number has already been received
+    DuplicateMessage {
+        /// The sequence number of the message
+        sequence: u64,
+    },
+
+    /// A signed message was sent too long ago, or too far in the future
+    StaleMessage {
+        /// When the message was sent, in milliseconds since epoch
+        timestamp: u64,
+        /// When the message was received, in milliseconds since epoch
+        now: u64,
+    },
+
+    /// A token presented by an agent is malformed, was not issued with this key, belongs to
+    /// another agent, or has expired
+    InvalidToken(String),
+    /// An agent's enrolment was rejected, such as for an unknown, expired or already used
+    /// enrolment code
+    EnrolmentFailed(String),
+    /// The underlying websocket failed, or sent something other than a message
+    TransportError(String),
+
+    /// No agent with this `PublicId` is connected, or it disconnected before replying
+    NotConnected {
+        /// The `PublicId` of the agent
+        public_id: u64,
+    },
+
+    /// An agent with this `PublicId` is already connected
+    AlreadyConnected {
+        /// The `PublicId` of the agent
+        public_id: u64,
+    },
+
+    /// An outbound queue overflowed with `Overflow::Disconnect`
+    QueueFull {
+        /// The number of frames queued when it overflowed
+        frames: usize,
+        /// The total size of the frames queued when it overflowed, in bytes
+        bytes: usize,
+    },
+
+    /// An agent did not accept or reply to a message in time
+    Timeout {
+        /// The `PublicId` of the agent
+        public_id: u64,
+    },
+
+    /// The peer broke the rules of a multiplexed stream, such as by sending more data than
+    /// its window allows
+    StreamError {
+        /// The id of the stream
+        stream_id: u32,
+        /// Which rule was broken
+        reason: String,
+    },
+    /// The parts of a diagnostics report sent by an agent could not be reassembled
+    DiagnosticsError {
+        /// The `request_id` of the report
+        request_id: u64,
+        /// Why the report could not be reassembled
+        reason: String,
+    },
+    /// An entry of a `Message::Batch` could not be encoded or decoded
+    BatchEntryError {
+        /// Position of the failing entry in the batch
+        index: usize,
+        /// Why the entry failed
+        error: Box<Error>,
+    },
+}
+
+/// Lets `?` be used on conversions which can no longer fail, such as `Message` into `Vec<u8>`.
+impl From<std::convert::Infallible> for Error {
+    fn from(err: std::convert::Infallible) -> Self {
+        match err {}
+    }
 }
 
 impl From<prost::DecodeError> for Error {
@@ -46,6 +159,53 @@ impl std::fmt::Display for Error {
         match self {
             Error::ByteDecodeError(e) => write!(f, "failed to decode bytes as valid message {}", e),
             Error::ByteEncodeError(e) => write!(f, "failed to encode bytes as valid message {}", e),
+            Error::FrameTooLarge { size, limit } => write!(
+                f,
+                "message of {} bytes exceeds the maximum frame size of {} bytes",
+                size, limit
+            ),
+            Error::FieldTooLarge { field, size, limit } => write!(
+                f,
+                "field `{}` of {} bytes exceeds the maximum length of {} bytes",
+                field, size, limit
+            ),
+            Error::CompressionError(e) => write!(f, "failed to decompress message {}", e),
+            Error::EncryptionError(e) => write!(f, "failed to decrypt message {}", e),
+            Error::InvalidSignature(e) => write!(f, "invalid message signature {}", e),
+            Error::DuplicateMessage { sequence } => write!(
+                f,
+                "message with sequence number {} has already been received",
+                sequence
+            ),
+            Error::StaleMessage { timestamp, now } => write!(
+                f,
+                "message sent at {} is too far from the current time {}",
+                timestamp, now
+            ),
+            Error::InvalidToken(e) => write!(f, "invalid token {}", e),
+            Error::EnrolmentFailed(e) => write!(f, "enrolment failed {}", e),
+            Error::TransportError(e) => write!(f, "websocket transport error {}", e),
+            Error::NotConnected { public_id } => {
+                write!(f, "agent {} is not connected", public_id)
+            }
+            Error::AlreadyConnected { public_id } => {
+                write!(f, "agent {} is already connected", public_id)
+            }
+            Error::QueueFull { frames, bytes } => write!(
+                f,
+                "outbound queue is full with {} frames of {} bytes",
+                frames, bytes
+            ),
+            Error::Timeout { public_id } => write!(f, "agent {} did not reply in time", public_id),
+            Error::StreamError { stream_id, reason } => {
+                write!(f, "stream {} error {}", stream_id, reason)
+            }
+            Error::DiagnosticsError { request_id, reason } => {
+                write!(f, "diagnostics report {} error {}", request_id, reason)
+            }
+            Error::BatchEntryError { index, error } => {
+                write!(f, "batch entry {} is invalid: {}", index, error)
+            }
         }
     }
 }
@@ -68,5 +228,92 @@ mod test_err {
             format!("{}", err),
             "failed to encode bytes as valid message test"
         );
+
+        let err = super::Error::FrameTooLarge {
+            size: 20,
+            limit: 10,
+        };
+        assert_eq!(
+            format!("{}", err),
+            "message of 20 bytes exceeds the maximum frame size of 10 bytes"
+        );
+
+        let err = super::Error::FieldTooLarge {
+            field: "file_name",
+            size: 20,
+            limit: 10,
+        };
+        assert_eq!(
+            format!("{}", err),
+            "field `file_name` of 20 bytes exceeds the maximum length of 10 bytes"
+        );
+
+        let err = super::Error::CompressionError(String::from("test"));
+        assert_eq!(format!("{}", err), "failed to decompress message test");
+
+        let err = super::Error::EncryptionError(String::from("test"));
+        assert_eq!(format!("{}", err), "failed to decrypt message test");
+
+        let err = super::Error::InvalidSignature(String::from("test"));
+        assert_eq!(format!("{}", err), "invalid message signature test");
+
+        let err = super::Error::DuplicateMessage { sequence: 4 };
+        assert_eq!(
+            format!("{}", err),
+            "message with sequence number 4 has already been received"
+        );
+
+        let err = super::Error::StaleMessage {
+            timestamp: 10,
+            now: 20,
+        };
+        assert_eq!(
+            format!("{}", err),
+            "message sent at 10 is too far from the current time 20"
+        );
+
+        let err = super::Error::InvalidToken(String::from("test"));
+        assert_eq!(format!("{}", err), "invalid token test");
+        let err = super::Error::EnrolmentFailed(String::from("test"));
+        assert_eq!(format!("{}", err), "enrolment failed test");
+        let err = super::Error::TransportError(String::from("test"));
+        assert_eq!(format!("{}", err), "websocket transport error test");
+
+        let err = super::Error::NotConnected { public_id: 43 };
+        assert_eq!(format!("{}", err), "agent 43 is not connected");
+
+        let err = super::Error::AlreadyConnected { public_id: 43 };
+        assert_eq!(format!("{}", err), "agent 43 is already connected");
+
+        let err = super::Error::QueueFull {
+            frames: 2,
+            bytes: 10,
+        };
+        assert_eq!(
+            format!("{}", err),
+            "outbound queue is full with 2 frames of 10 bytes"
+        );
+
+        let err = super::Error::Timeout { public_id: 43 };
+        assert_eq!(format!("{}", err), "agent 43 did not reply in time");
+
+        let err = super::Error::StreamError {
+            stream_id: 3,
+            reason: String::from("test"),
+        };
+        assert_eq!(format!("{}", err), "stream 3 error test");
+        let err = super::Error::DiagnosticsError {
+            request_id: 7,
+            reason: String::from("test"),
+        };
+        assert_eq!(format!("{}", err), "diagnostics report 7 error test");
+        let err = super::Error::BatchEntryError {
+            index: 3,
+            error: Box::new(super::Error::ByteDecodeError(String::from("test"))),
+        };
+        assert_eq!(
+            format!("{}", err),
+            "batch entry 3 is invalid: failed to decode bytes as valid message test"
+        );
     }
 }
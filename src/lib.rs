@@ -1,7 +1,7 @@
 //! Ws-com-framework converts messages to and from binary for sending down web sockets.
 //!
-//! The `Message` type implements `TryFrom` and `TryInto` for `Vec<u8>`, and is designed to be
-//! matched against for processing/responding to requests.
+//! The `Message` type converts infallibly into `Vec<u8>`, can be decoded with `TryFrom<Vec<u8>>`,
+//! and is designed to be matched against for processing/responding to requests.
 //!
 //! # Example
 //! ```rust
@@ -13,7 +13,7 @@
 //!     let message: Message = Message::AuthReq {
 //!         public_id: 43
 //!     };
-//!     tx.send(message.try_into().unwrap()).unwrap();
+//!     tx.send(message.into()).unwrap();
 //!
 //!     while let Some(v) = rx.recv().await {
 //!         let recv_message = Message::try_from(v).unwrap();
@@ -35,9 +35,53 @@
     deprecated
 )]
 
+#[cfg(feature = "server")]
+pub mod broadcast;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod codec;
+pub mod compression;
+pub mod config;
+#[cfg(any(feature = "client", feature = "server"))]
+pub mod deadline;
+pub mod diagnostics;
+mod encoding;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+#[cfg(feature = "enrolment")]
+pub mod enrolment;
 pub mod error;
+pub mod limits;
 pub mod message;
+pub mod mux;
+#[cfg(feature = "server")]
+pub mod queue;
+#[cfg(feature = "server")]
+pub mod registry;
+pub mod rotation;
+pub mod session;
+#[cfg(feature = "server")]
+pub mod shutdown;
+#[cfg(feature = "signing")]
+pub mod signing;
+pub mod status;
+#[cfg(feature = "tokens")]
+pub mod token;
+pub mod transport;
 
 //Re-export relevant types
+#[cfg(feature = "client")]
+pub use client::ReconnectingClient;
+pub use codec::Codec;
+pub use compression::Compression;
+#[cfg(feature = "encryption")]
+pub use encryption::SessionCipher;
 pub use error::Error;
-pub use message::{FileId, Message, Passcode, PublicId, UploadId};
+pub use limits::Limits;
+pub use message::{AgentInfo, FileId, Message, Passcode, PublicId, UploadId};
+#[cfg(feature = "server")]
+pub use registry::ConnectionRegistry;
+#[cfg(feature = "signing")]
+pub use signing::SessionSigner;
+#[cfg(feature = "tokens")]
+pub use token::TokenIssuer;
@@ -0,0 +1,51 @@
+//! Limits on the size of messages and their fields, so that a peer cannot make us allocate
+//! or send arbitrarily large buffers.
+
+use crate::encoding::FieldLimits;
+use crate::error::Error;
+
+/// Limits on the size of a message, enforced by `Message::to_bytes_checked` before a message is
+/// encoded and by `Message::decode_limited` when decoding a received message.
+///
+/// When decoding, the frame size is checked before anything is decoded, so no single allocation
+/// made while decoding can be larger than `max_frame_size`. Each field is then checked on the
+/// wire before it is decoded, so no string or bytes field larger than its limit is ever allocated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum size of an encoded message in bytes, including its envelope
+    pub max_frame_size: usize,
+    /// Maximum length in bytes of any string field
+    pub max_string_len: usize,
+    /// Maximum length in bytes of any bytes field
+    pub max_bytes_len: usize,
+}
+
+impl Limits {
+    /// Fail if a frame of `size` bytes is larger than the maximum frame size.
+    pub(crate) fn check_frame_size(&self, size: usize) -> Result<(), Error> {
+        if size > self.max_frame_size {
+            return Err(Error::FrameTooLarge {
+                size,
+                limit: self.max_frame_size,
+            });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn field_limits(&self) -> FieldLimits {
+        FieldLimits {
+            max_string_len: self.max_string_len,
+            max_bytes_len: self.max_bytes_len,
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_frame_size: 1024 * 1024,
+            max_string_len: 64 * 1024,
+            max_bytes_len: 64 * 1024,
+        }
+    }
+}
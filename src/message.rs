@@ -2,7 +2,11 @@
 //!
 //! Internally it also provides conversions between the Message type to/from bytes.
 
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::encoding;
 use crate::error::{Error, ErrorKind};
+use crate::limits::Limits;
 
 /*
 Note: These types could be stack allocated, but the receiving buff heap allocates them
@@ -22,6 +26,37 @@ pub type Passcode = Vec<u8>;
 /// 8 bytes representing a public upload id on the server
 pub type UploadId = u64;
 
+/// Describes an agent enrolling with the server, sent in a `Message::RegisterReq`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct AgentInfo {
+    /// The name of the machine the agent runs on
+    pub hostname: String,
+    /// The version of the agent
+    pub version: String,
+    /// The operating system the agent runs on
+    pub os: String,
+}
+
+/// The value of a setting pushed by the server in a `Message::ConfigUpdate`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ConfigValue {
+    /// A flag
+    Bool(bool),
+    /// A whole number, such as a bandwidth limit
+    Int(i64),
+    /// A piece of text
+    Text(String),
+}
+
+/// A single setting pushed by the server, see `config::ConfigStore`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ConfigEntry {
+    /// The name of the setting
+    pub key: String,
+    /// The new value of the setting
+    pub value: ConfigValue,
+}
+
 /// A macro for converting a provided type into bytes for sending over a stream
 macro_rules! into_bytes {
     ($data:tt) => {{
@@ -31,7 +66,7 @@ macro_rules! into_bytes {
             .encode(&mut buf)
             .expect("Should never fail, as Vec<u8> expands automatically");
 
-        buf
+        buf.into()
     }};
 }
 
@@ -39,12 +74,18 @@ macro_rules! into_bytes {
 pub mod websocket_message {
     use crate::error::ErrorKind;
 
+    use self::protobuf_types::fsp_comm::{
+        AgentInfo, AuthOk, Batch, ConfigAck, ConfigEntry, ConfigUpdate, DiagnosticsReq,
+        DiagnosticsRes, Goodbye, Redirect, RefreshSession, RegisterReq, RegisterRes,
+        RotatePasscodeReq, RotatePasscodeRes, StatusReq, StatusRes, StreamData, StreamReset,
+        TokenAuth, WindowUpdate,
+    };
     use self::protobuf_types::fsp_comm::{
         Auth, AuthReq, Error as CommError, MetadataReq, MetadataRes, UploadTo,
     };
-    use self::protobuf_types::fsp_comm::{StatusReq, StatusRes};
     use self::protobuf_types::FspComm;
     use super::Message as ExternalMessage;
+    use bytes::Bytes;
     use prost::Message;
 
     #[allow(clippy::all, missing_docs, missing_copy_implementations)]
@@ -109,11 +150,180 @@ pub mod websocket_message {
         }
     }
 
+    impl TryFrom<Vec<u8>> for Batch {
+        type Error = super::Error;
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Self::decode(&value[..])?)
+        }
+    }
+
+    impl TryFrom<Vec<u8>> for StreamData {
+        type Error = super::Error;
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Self::decode(&value[..])?)
+        }
+    }
+
+    impl TryFrom<Vec<u8>> for WindowUpdate {
+        type Error = super::Error;
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Self::decode(&value[..])?)
+        }
+    }
+
+    impl TryFrom<Vec<u8>> for Goodbye {
+        type Error = super::Error;
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Self::decode(&value[..])?)
+        }
+    }
+
+    impl TryFrom<Vec<u8>> for Redirect {
+        type Error = super::Error;
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Self::decode(&value[..])?)
+        }
+    }
+
+    impl TryFrom<Vec<u8>> for TokenAuth {
+        type Error = super::Error;
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Self::decode(&value[..])?)
+        }
+    }
+
+    impl TryFrom<Vec<u8>> for AuthOk {
+        type Error = super::Error;
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Self::decode(&value[..])?)
+        }
+    }
+
+    impl TryFrom<Vec<u8>> for RotatePasscodeReq {
+        type Error = super::Error;
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Self::decode(&value[..])?)
+        }
+    }
+
+    impl TryFrom<Vec<u8>> for RotatePasscodeRes {
+        type Error = super::Error;
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Self::decode(&value[..])?)
+        }
+    }
+
+    impl TryFrom<Vec<u8>> for RegisterReq {
+        type Error = super::Error;
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Self::decode(&value[..])?)
+        }
+    }
+
+    impl TryFrom<Vec<u8>> for RegisterRes {
+        type Error = super::Error;
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Self::decode(&value[..])?)
+        }
+    }
+
+    impl TryFrom<Vec<u8>> for ConfigUpdate {
+        type Error = super::Error;
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Self::decode(&value[..])?)
+        }
+    }
+
+    impl TryFrom<Vec<u8>> for ConfigAck {
+        type Error = super::Error;
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Self::decode(&value[..])?)
+        }
+    }
+
+    impl TryFrom<Vec<u8>> for DiagnosticsReq {
+        type Error = super::Error;
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Self::decode(&value[..])?)
+        }
+    }
+
+    impl TryFrom<Vec<u8>> for DiagnosticsRes {
+        type Error = super::Error;
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Self::decode(&value[..])?)
+        }
+    }
+
+    impl TryFrom<Vec<u8>> for StreamReset {
+        type Error = super::Error;
+        fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Self::decode(&value[..])?)
+        }
+    }
+
+    impl From<super::ConfigEntry> for ConfigEntry {
+        fn from(value: super::ConfigEntry) -> Self {
+            use self::protobuf_types::fsp_comm::config_entry::Value;
+            Self {
+                key: value.key,
+                value: Some(match value.value {
+                    super::ConfigValue::Bool(v) => Value::BoolValue(v),
+                    super::ConfigValue::Int(v) => Value::IntValue(v),
+                    super::ConfigValue::Text(v) => Value::TextValue(v),
+                }),
+            }
+        }
+    }
+
+    impl TryFrom<ConfigEntry> for super::ConfigEntry {
+        type Error = super::Error;
+        fn try_from(value: ConfigEntry) -> Result<Self, Self::Error> {
+            use self::protobuf_types::fsp_comm::config_entry::Value;
+            let config_value = match value.value {
+                Some(Value::BoolValue(v)) => super::ConfigValue::Bool(v),
+                Some(Value::IntValue(v)) => super::ConfigValue::Int(v),
+                Some(Value::TextValue(v)) => super::ConfigValue::Text(v),
+                None => {
+                    return Err(super::Error::ByteDecodeError(format!(
+                        "config entry {} has no value",
+                        value.key
+                    )))
+                }
+            };
+            Ok(Self {
+                key: value.key,
+                value: config_value,
+            })
+        }
+    }
+
+    impl From<super::AgentInfo> for AgentInfo {
+        fn from(value: super::AgentInfo) -> Self {
+            Self {
+                hostname: value.hostname,
+                version: value.version,
+                os: value.os,
+            }
+        }
+    }
+
+    impl From<AgentInfo> for super::AgentInfo {
+        fn from(value: AgentInfo) -> Self {
+            Self {
+                hostname: value.hostname,
+                version: value.version,
+                os: value.os,
+            }
+        }
+    }
+
     impl From<CommError> for FspComm {
         fn from(itm: CommError) -> Self {
             Self {
                 r#type: 1,
                 value: into_bytes!(itm),
+                ..Default::default()
             }
         }
     }
@@ -123,6 +333,7 @@ pub mod websocket_message {
             Self {
                 r#type: 2,
                 value: into_bytes!(itm),
+                ..Default::default()
             }
         }
     }
@@ -132,6 +343,7 @@ pub mod websocket_message {
             Self {
                 r#type: 3,
                 value: into_bytes!(itm),
+                ..Default::default()
             }
         }
     }
@@ -141,6 +353,7 @@ pub mod websocket_message {
             Self {
                 r#type: 4,
                 value: into_bytes!(itm),
+                ..Default::default()
             }
         }
     }
@@ -150,6 +363,7 @@ pub mod websocket_message {
             Self {
                 r#type: 5,
                 value: into_bytes!(itm),
+                ..Default::default()
             }
         }
     }
@@ -159,6 +373,7 @@ pub mod websocket_message {
             Self {
                 r#type: 6,
                 value: into_bytes!(itm),
+                ..Default::default()
             }
         }
     }
@@ -168,6 +383,7 @@ pub mod websocket_message {
             Self {
                 r#type: 7,
                 value: into_bytes!(value),
+                ..Default::default()
             }
         }
     }
@@ -177,6 +393,177 @@ pub mod websocket_message {
             Self {
                 r#type: 8,
                 value: into_bytes!(value),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl From<Batch> for FspComm {
+        fn from(value: Batch) -> Self {
+            Self {
+                r#type: 9,
+                value: into_bytes!(value),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl From<StreamData> for FspComm {
+        fn from(value: StreamData) -> Self {
+            Self {
+                r#type: 10,
+                value: into_bytes!(value),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl From<WindowUpdate> for FspComm {
+        fn from(value: WindowUpdate) -> Self {
+            Self {
+                r#type: 11,
+                value: into_bytes!(value),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl From<Goodbye> for FspComm {
+        fn from(value: Goodbye) -> Self {
+            Self {
+                r#type: 12,
+                value: into_bytes!(value),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl From<Redirect> for FspComm {
+        fn from(value: Redirect) -> Self {
+            Self {
+                r#type: 13,
+                value: into_bytes!(value),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl From<TokenAuth> for FspComm {
+        fn from(value: TokenAuth) -> Self {
+            Self {
+                r#type: 14,
+                value: into_bytes!(value),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl From<AuthOk> for FspComm {
+        fn from(value: AuthOk) -> Self {
+            Self {
+                r#type: 15,
+                value: into_bytes!(value),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl From<RefreshSession> for FspComm {
+        fn from(_: RefreshSession) -> Self {
+            Self {
+                r#type: 16,
+                value: Bytes::new(),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl From<RotatePasscodeReq> for FspComm {
+        fn from(value: RotatePasscodeReq) -> Self {
+            Self {
+                r#type: 17,
+                value: into_bytes!(value),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl From<RotatePasscodeRes> for FspComm {
+        fn from(value: RotatePasscodeRes) -> Self {
+            Self {
+                r#type: 18,
+                value: into_bytes!(value),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl From<RegisterReq> for FspComm {
+        fn from(value: RegisterReq) -> Self {
+            Self {
+                r#type: 19,
+                value: into_bytes!(value),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl From<RegisterRes> for FspComm {
+        fn from(value: RegisterRes) -> Self {
+            Self {
+                r#type: 20,
+                value: into_bytes!(value),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl From<ConfigUpdate> for FspComm {
+        fn from(value: ConfigUpdate) -> Self {
+            Self {
+                r#type: 21,
+                value: into_bytes!(value),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl From<ConfigAck> for FspComm {
+        fn from(value: ConfigAck) -> Self {
+            Self {
+                r#type: 22,
+                value: into_bytes!(value),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl From<DiagnosticsReq> for FspComm {
+        fn from(value: DiagnosticsReq) -> Self {
+            Self {
+                r#type: 23,
+                value: into_bytes!(value),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl From<DiagnosticsRes> for FspComm {
+        fn from(value: DiagnosticsRes) -> Self {
+            Self {
+                r#type: 24,
+                value: into_bytes!(value),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl From<StreamReset> for FspComm {
+        fn from(value: StreamReset) -> Self {
+            Self {
+                r#type: 25,
+                value: into_bytes!(value),
+                ..Default::default()
             }
         }
     }
@@ -188,30 +575,39 @@ pub mod websocket_message {
         }
     }
 
-    impl TryFrom<ExternalMessage> for FspComm {
-        type Error = super::Error;
-        fn try_from(msg: ExternalMessage) -> Result<Self, super::Error> {
+    impl From<ExternalMessage> for FspComm {
+        fn from(msg: ExternalMessage) -> Self {
             match msg {
-                ExternalMessage::Ok => Ok(Self {
+                ExternalMessage::Ok => Self {
                     r#type: 0,
-                    value: Vec::with_capacity(0),
-                }),
-                ExternalMessage::Error { kind, reason } => Ok(CommError {
+                    value: Bytes::new(),
+                    ..Default::default()
+                },
+                ExternalMessage::Error { kind, reason } => CommError {
                     r#type: kind as i32,
                     reason,
                 }
-                .into()),
+                .into(),
                 ExternalMessage::UploadTo {
                     file_id,
                     upload_url,
-                } => Ok(UploadTo {
+                    deadline,
+                } => UploadTo {
                     file_id,
                     upload_url,
+                    deadline,
                 }
-                .into()),
-                ExternalMessage::MetadataReq { file_id, upload_id } => {
-                    Ok(MetadataReq { file_id, upload_id }.into())
+                .into(),
+                ExternalMessage::MetadataReq {
+                    file_id,
+                    upload_id,
+                    deadline,
+                } => MetadataReq {
+                    file_id,
+                    upload_id,
+                    deadline,
                 }
+                .into(),
                 ExternalMessage::MetadataRes {
                     file_id,
                     exp,
@@ -220,7 +616,7 @@ pub mod websocket_message {
                     username,
                     file_name,
                     upload_id,
-                } => Ok(MetadataRes {
+                } => MetadataRes {
                     file_id,
                     exp,
                     crt,
@@ -229,38 +625,165 @@ pub mod websocket_message {
                     file_name,
                     upload_id,
                 }
-                .into()),
-                ExternalMessage::AuthReq { public_id } => Ok(AuthReq { public_id }.into()),
+                .into(),
+                ExternalMessage::AuthReq { public_id } => AuthReq { public_id }.into(),
                 ExternalMessage::AuthRes {
                     public_id,
                     passcode,
-                } => Ok(Auth {
+                } => Auth {
                     public_id,
-                    passcode,
+                    passcode: passcode.into(),
                 }
-                .into()),
+                .into(),
                 ExternalMessage::StatusReq {
                     public_id,
                     upload_id,
-                } => Ok(StatusReq {
+                    deadline,
+                } => StatusReq {
                     public_id,
                     upload_id,
+                    deadline,
                 }
-                .into()),
+                .into(),
                 ExternalMessage::StatusRes {
                     public_id,
                     ready,
                     uptime,
                     message,
                     upload_id,
-                } => Ok(StatusRes {
+                    active_shares,
+                    bytes_shared,
+                    uploads_in_progress,
+                    free_disk,
+                    agent_version,
+                    os,
+                } => StatusRes {
                     public_id,
                     ready,
                     uptime,
                     message,
                     upload_id,
+                    active_shares,
+                    bytes_shared,
+                    uploads_in_progress,
+                    free_disk,
+                    agent_version,
+                    os,
+                }
+                .into(),
+                ExternalMessage::Batch { messages } => Batch {
+                    messages: messages.into_iter().map(Self::from).collect(),
+                }
+                .into(),
+                ExternalMessage::StreamData { data, end } => StreamData {
+                    data: data.into(),
+                    end,
+                }
+                .into(),
+                ExternalMessage::WindowUpdate { increment } => WindowUpdate { increment }.into(),
+                ExternalMessage::Goodbye {
+                    reason,
+                    reconnect_after,
+                    redirect_url,
+                } => Goodbye {
+                    reason,
+                    reconnect_after,
+                    redirect_url,
+                }
+                .into(),
+                ExternalMessage::Redirect { url, token } => Redirect {
+                    url,
+                    token: token.into(),
+                }
+                .into(),
+                ExternalMessage::TokenAuth { public_id, token } => TokenAuth {
+                    public_id,
+                    token: token.into(),
+                }
+                .into(),
+                ExternalMessage::AuthOk {
+                    session_token,
+                    expires_at,
+                } => AuthOk {
+                    session_token: session_token.into(),
+                    expires_at,
+                }
+                .into(),
+                ExternalMessage::RefreshSession => RefreshSession {}.into(),
+                ExternalMessage::RotatePasscodeReq {
+                    rotation_id,
+                    passcode,
+                } => RotatePasscodeReq {
+                    rotation_id,
+                    passcode: passcode.into(),
+                }
+                .into(),
+                ExternalMessage::RotatePasscodeRes {
+                    rotation_id,
+                    accepted,
+                    reason,
+                } => RotatePasscodeRes {
+                    rotation_id,
+                    accepted,
+                    reason,
+                }
+                .into(),
+                ExternalMessage::RegisterReq {
+                    enrolment_code,
+                    agent_info,
+                } => RegisterReq {
+                    enrolment_code,
+                    agent_info: Some(agent_info.into()),
+                }
+                .into(),
+                ExternalMessage::RegisterRes {
+                    public_id,
+                    passcode,
+                } => RegisterRes {
+                    public_id,
+                    passcode: passcode.into(),
+                }
+                .into(),
+                ExternalMessage::ConfigUpdate { version, entries } => ConfigUpdate {
+                    version,
+                    entries: entries.into_iter().map(ConfigEntry::from).collect(),
+                }
+                .into(),
+                ExternalMessage::ConfigAck {
+                    version,
+                    applied,
+                    rejected,
+                    reason,
+                } => ConfigAck {
+                    version,
+                    applied,
+                    rejected,
+                    reason,
+                }
+                .into(),
+                ExternalMessage::DiagnosticsReq {
+                    request_id,
+                    since,
+                    max_bytes,
+                } => DiagnosticsReq {
+                    request_id,
+                    since,
+                    max_bytes,
                 }
-                .into()),
+                .into(),
+                ExternalMessage::DiagnosticsRes {
+                    request_id,
+                    part,
+                    data,
+                    end,
+                } => DiagnosticsRes {
+                    request_id,
+                    part,
+                    data: data.into(),
+                    end,
+                }
+                .into(),
+                ExternalMessage::StreamReset { reason } => StreamReset { reason }.into(),
             }
         }
     }
@@ -268,32 +791,41 @@ pub mod websocket_message {
     impl TryFrom<FspComm> for ExternalMessage {
         type Error = super::Error;
         fn try_from(value: FspComm) -> Result<Self, super::Error> {
+            if !value.nonce.is_empty() {
+                return Err(super::Error::EncryptionError(String::from(
+                    "message is encrypted, but no session cipher is configured",
+                )));
+            }
+            let limit = crate::limits::Limits::default().max_frame_size;
+            let value = crate::compression::decompress(value, limit)?;
             if let Some(ty) = protobuf_types::fsp_comm::Type::from_i32(value.r#type) {
                 match ty {
                     protobuf_types::fsp_comm::Type::Ok => Ok(ExternalMessage::Ok),
                     protobuf_types::fsp_comm::Type::Error => {
-                        let tmp: CommError = value.value.try_into()?;
+                        let tmp = CommError::decode(value.value)?;
                         Ok(ExternalMessage::Error {
                             kind: ErrorKind::from(tmp.r#type),
                             reason: tmp.reason,
                         })
                     }
                     protobuf_types::fsp_comm::Type::UploadTo => {
-                        let tmp: UploadTo = value.value.try_into()?;
+                        let tmp = UploadTo::decode(value.value)?;
                         Ok(ExternalMessage::UploadTo {
                             file_id: tmp.file_id,
                             upload_url: tmp.upload_url,
+                            deadline: tmp.deadline,
                         })
                     }
                     protobuf_types::fsp_comm::Type::MetadataReq => {
-                        let tmp: MetadataReq = value.value.try_into()?;
+                        let tmp = MetadataReq::decode(value.value)?;
                         Ok(ExternalMessage::MetadataReq {
                             file_id: tmp.file_id,
                             upload_id: tmp.upload_id,
+                            deadline: tmp.deadline,
                         })
                     }
                     protobuf_types::fsp_comm::Type::MetadataRes => {
-                        let tmp: MetadataRes = value.value.try_into()?;
+                        let tmp = MetadataRes::decode(value.value)?;
                         Ok(ExternalMessage::MetadataRes {
                             file_id: tmp.file_id,
                             exp: tmp.exp,
@@ -305,35 +837,185 @@ pub mod websocket_message {
                         })
                     }
                     protobuf_types::fsp_comm::Type::Authreq => {
-                        let tmp: AuthReq = value.value.try_into()?;
+                        let tmp = AuthReq::decode(value.value)?;
                         Ok(ExternalMessage::AuthReq {
                             public_id: tmp.public_id,
                         })
                     }
                     protobuf_types::fsp_comm::Type::Auth => {
-                        let tmp: Auth = value.value.try_into()?;
+                        let tmp = Auth::decode(value.value)?;
                         Ok(ExternalMessage::AuthRes {
                             public_id: tmp.public_id,
-                            passcode: tmp.passcode,
+                            passcode: tmp.passcode.to_vec(),
                         })
                     }
                     protobuf_types::fsp_comm::Type::StatusReq => {
-                        let tmp: StatusReq = value.value.try_into()?;
+                        let tmp = StatusReq::decode(value.value)?;
                         Ok(ExternalMessage::StatusReq {
                             public_id: tmp.public_id,
                             upload_id: tmp.upload_id,
+                            deadline: tmp.deadline,
                         })
                     }
                     protobuf_types::fsp_comm::Type::StatusRes => {
-                        let tmp: StatusRes = value.value.try_into()?;
+                        let tmp = StatusRes::decode(value.value)?;
                         Ok(ExternalMessage::StatusRes {
                             public_id: tmp.public_id,
                             ready: tmp.ready,
                             uptime: tmp.uptime,
                             message: tmp.message,
                             upload_id: tmp.upload_id,
+                            active_shares: tmp.active_shares,
+                            bytes_shared: tmp.bytes_shared,
+                            uploads_in_progress: tmp.uploads_in_progress,
+                            free_disk: tmp.free_disk,
+                            agent_version: tmp.agent_version,
+                            os: tmp.os,
+                        })
+                    }
+                    protobuf_types::fsp_comm::Type::Batch => {
+                        let tmp = Batch::decode(value.value)?;
+                        let messages = tmp
+                            .messages
+                            .into_iter()
+                            .enumerate()
+                            .map(|(index, entry)| {
+                                let msg = if entry.r#type
+                                    == protobuf_types::fsp_comm::Type::Batch as i32
+                                {
+                                    Err(super::Error::ByteDecodeError(String::from(
+                                        "batches cannot be nested",
+                                    )))
+                                } else if entry.compression != 0 {
+                                    Err(super::Error::ByteDecodeError(String::from(
+                                        "batch entries cannot be compressed",
+                                    )))
+                                } else {
+                                    ExternalMessage::try_from(entry)
+                                };
+                                msg.map_err(|e| super::Error::BatchEntryError {
+                                    index,
+                                    error: Box::new(e),
+                                })
+                            })
+                            .collect::<Result<_, _>>()?;
+                        Ok(ExternalMessage::Batch { messages })
+                    }
+                    protobuf_types::fsp_comm::Type::StreamData => {
+                        let tmp = StreamData::decode(value.value)?;
+                        Ok(ExternalMessage::StreamData {
+                            data: tmp.data.to_vec(),
+                            end: tmp.end,
+                        })
+                    }
+                    protobuf_types::fsp_comm::Type::WindowUpdate => {
+                        let tmp = WindowUpdate::decode(value.value)?;
+                        Ok(ExternalMessage::WindowUpdate {
+                            increment: tmp.increment,
+                        })
+                    }
+                    protobuf_types::fsp_comm::Type::Goodbye => {
+                        let tmp = Goodbye::decode(value.value)?;
+                        Ok(ExternalMessage::Goodbye {
+                            reason: tmp.reason,
+                            reconnect_after: tmp.reconnect_after,
+                            redirect_url: tmp.redirect_url,
+                        })
+                    }
+                    protobuf_types::fsp_comm::Type::Redirect => {
+                        let tmp = Redirect::decode(value.value)?;
+                        Ok(ExternalMessage::Redirect {
+                            url: tmp.url,
+                            token: tmp.token.to_vec(),
+                        })
+                    }
+                    protobuf_types::fsp_comm::Type::TokenAuth => {
+                        let tmp = TokenAuth::decode(value.value)?;
+                        Ok(ExternalMessage::TokenAuth {
+                            public_id: tmp.public_id,
+                            token: tmp.token.to_vec(),
+                        })
+                    }
+                    protobuf_types::fsp_comm::Type::AuthOk => {
+                        let tmp = AuthOk::decode(value.value)?;
+                        Ok(ExternalMessage::AuthOk {
+                            session_token: tmp.session_token.to_vec(),
+                            expires_at: tmp.expires_at,
                         })
                     }
+                    protobuf_types::fsp_comm::Type::RefreshSession => {
+                        Ok(ExternalMessage::RefreshSession)
+                    }
+                    protobuf_types::fsp_comm::Type::RotatePasscodeReq => {
+                        let tmp = RotatePasscodeReq::decode(value.value)?;
+                        Ok(ExternalMessage::RotatePasscodeReq {
+                            rotation_id: tmp.rotation_id,
+                            passcode: tmp.passcode.to_vec(),
+                        })
+                    }
+                    protobuf_types::fsp_comm::Type::RotatePasscodeRes => {
+                        let tmp = RotatePasscodeRes::decode(value.value)?;
+                        Ok(ExternalMessage::RotatePasscodeRes {
+                            rotation_id: tmp.rotation_id,
+                            accepted: tmp.accepted,
+                            reason: tmp.reason,
+                        })
+                    }
+                    protobuf_types::fsp_comm::Type::RegisterReq => {
+                        let tmp = RegisterReq::decode(value.value)?;
+                        Ok(ExternalMessage::RegisterReq {
+                            enrolment_code: tmp.enrolment_code,
+                            agent_info: tmp.agent_info.unwrap_or_default().into(),
+                        })
+                    }
+                    protobuf_types::fsp_comm::Type::RegisterRes => {
+                        let tmp = RegisterRes::decode(value.value)?;
+                        Ok(ExternalMessage::RegisterRes {
+                            public_id: tmp.public_id,
+                            passcode: tmp.passcode.to_vec(),
+                        })
+                    }
+                    protobuf_types::fsp_comm::Type::ConfigUpdate => {
+                        let tmp = ConfigUpdate::decode(value.value)?;
+                        Ok(ExternalMessage::ConfigUpdate {
+                            version: tmp.version,
+                            entries: tmp
+                                .entries
+                                .into_iter()
+                                .map(super::ConfigEntry::try_from)
+                                .collect::<Result<_, _>>()?,
+                        })
+                    }
+                    protobuf_types::fsp_comm::Type::ConfigAck => {
+                        let tmp = ConfigAck::decode(value.value)?;
+                        Ok(ExternalMessage::ConfigAck {
+                            version: tmp.version,
+                            applied: tmp.applied,
+                            rejected: tmp.rejected,
+                            reason: tmp.reason,
+                        })
+                    }
+                    protobuf_types::fsp_comm::Type::DiagnosticsReq => {
+                        let tmp = DiagnosticsReq::decode(value.value)?;
+                        Ok(ExternalMessage::DiagnosticsReq {
+                            request_id: tmp.request_id,
+                            since: tmp.since,
+                            max_bytes: tmp.max_bytes,
+                        })
+                    }
+                    protobuf_types::fsp_comm::Type::DiagnosticsRes => {
+                        let tmp = DiagnosticsRes::decode(value.value)?;
+                        Ok(ExternalMessage::DiagnosticsRes {
+                            request_id: tmp.request_id,
+                            part: tmp.part,
+                            data: tmp.data.to_vec(),
+                            end: tmp.end,
+                        })
+                    }
+                    protobuf_types::fsp_comm::Type::StreamReset => {
+                        let tmp = StreamReset::decode(value.value)?;
+                        Ok(ExternalMessage::StreamReset { reason: tmp.reason })
+                    }
                 }
             } else {
                 Err(super::Error::ByteDecodeError(String::from(
@@ -365,6 +1047,8 @@ pub enum Message {
         file_id: FileId,
         /// The url that the file should be POSTed to in a streaming fashion
         upload_url: String,
+        /// When the server stops waiting for the upload, in milliseconds past epoch
+        deadline: Option<u64>,
     },
     /// Reuqest the peer to upload the provided `FileId` metadata
     MetadataReq {
@@ -372,6 +1056,8 @@ pub enum Message {
         file_id: FileId,
         /// The upload_id to attach when returning with MetadataRes
         upload_id: UploadId,
+        /// When the server stops waiting for a reply, in milliseconds past epoch
+        deadline: Option<u64>,
     },
     /// The metadata about a share sent from an agent
     MetadataRes {
@@ -410,6 +1096,8 @@ pub enum Message {
         public_id: PublicId,
         /// The `UploadId` of the request this is associated with
         upload_id: UploadId,
+        /// When the server stops waiting for a reply, in milliseconds past epoch
+        deadline: Option<u64>,
     },
     /// Response to a `Message::StatusReq` containing the status of the peer
     StatusRes {
@@ -423,17 +1111,160 @@ pub enum Message {
         upload_id: UploadId,
         /// Optional uptime message from the peer
         message: Option<String>,
+        /// The number of files the peer is sharing
+        active_shares: Option<u32>,
+        /// The total size of the files the peer is sharing, in bytes
+        bytes_shared: Option<u64>,
+        /// The number of uploads the peer has in progress
+        uploads_in_progress: Option<u32>,
+        /// Free space on the disk holding the shared files, in bytes
+        free_disk: Option<u64>,
+        /// The version of the peer's software
+        agent_version: Option<String>,
+        /// The operating system the peer runs on
+        os: Option<String>,
+    },
+    /// Several messages sent together in a single frame. Entries are delivered in order,
+    /// and cannot themselves be batches.
+    Batch {
+        /// The messages in this batch
+        messages: Vec<Message>,
+    },
+    /// A chunk of data sent on a stream, see `mux::Multiplexer`
+    StreamData {
+        /// The bytes of this chunk
+        data: Vec<u8>,
+        /// Whether this is the last chunk the sender will send on the stream
+        end: bool,
+    },
+    /// Allows the peer to send `increment` more bytes of `Message::StreamData` on a stream
+    WindowUpdate {
+        /// The number of bytes added to the stream's window
+        increment: u32,
+    },
+    /// Sent by the server before closing the connection because it is shutting down
+    Goodbye {
+        /// A human readable reason for closing the connection
+        reason: Option<String>,
+        /// How long the agent should wait before reconnecting, in seconds
+        reconnect_after: Option<u64>,
+        /// Where the agent should reconnect to, if not the same server
+        redirect_url: Option<String>,
+    },
+    /// Instructs the agent to reconnect to another server, and answer its `Message::AuthReq`
+    /// with a `Message::TokenAuth` carrying `token`
+    Redirect {
+        /// The url of the server to reconnect to
+        url: String,
+        /// A token issued by the server, see `token::TokenIssuer`
+        token: Vec<u8>,
+    },
+    /// Response from peer to a `Message::AuthReq`, presenting a token issued by the server in
+    /// place of its `Passcode`
+    TokenAuth {
+        /// The `PublicId` of the peer being authenticated
+        public_id: PublicId,
+        /// The token issued to the peer
+        token: Vec<u8>,
+    },
+    /// Sent by the server once the peer has authenticated, with a token it can present in a
+    /// `Message::TokenAuth` in place of its `Passcode` when it reconnects
+    AuthOk {
+        /// A token issued by the server, see `token::TokenIssuer`
+        session_token: Vec<u8>,
+        /// When the token expires, in milliseconds past epoch
+        expires_at: u64,
+    },
+    /// Ask the server for a new session token before the current one expires, which is
+    /// answered with a `Message::AuthOk`
+    RefreshSession,
+    /// Propose a new `Passcode` for the agent, either issued by the server or chosen by the
+    /// agent, see `rotation`
+    RotatePasscodeReq {
+        /// Chosen by the proposer, and echoed in the `Message::RotatePasscodeRes`
+        rotation_id: u64,
+        /// The proposed passcode
+        passcode: Passcode,
+    },
+    /// Accept or reject a proposed `Passcode`
+    RotatePasscodeRes {
+        /// The `rotation_id` of the `Message::RotatePasscodeReq`
+        rotation_id: u64,
+        /// Whether the proposed passcode was accepted
+        accepted: bool,
+        /// Why the passcode was rejected
+        reason: Option<String>,
+    },
+    /// Sent by an agent without credentials in place of a `Message::AuthRes`, to enrol with
+    /// the server using a one-time code
+    RegisterReq {
+        /// The one-time code the agent was provisioned with
+        enrolment_code: String,
+        /// Describes the agent
+        agent_info: AgentInfo,
+    },
+    /// The credentials issued to a newly enrolled agent, which it authenticates with from then
+    /// on
+    RegisterRes {
+        /// The agent's new public id
+        public_id: PublicId,
+        /// The agent's new passcode
+        passcode: Passcode,
+    },
+    /// Settings pushed by the server, which the agent applies together or not at all, see
+    /// `config::ConfigStore`
+    ConfigUpdate {
+        /// Increases with every update, starting from 1, so that stale updates are not applied
+        version: u64,
+        /// The settings to change
+        entries: Vec<ConfigEntry>,
+    },
+    /// Acknowledges a `Message::ConfigUpdate`
+    ConfigAck {
+        /// The version of the agent's settings after the update
+        version: u64,
+        /// Whether the update was applied
+        applied: bool,
+        /// The keys which caused the update to be rejected
+        rejected: Vec<String>,
+        /// Why the update was rejected
+        reason: Option<String>,
+    },
+    /// Ask the peer for its recent logs and a snapshot of its status, which it sends back in
+    /// `Message::DiagnosticsRes` parts, see `diagnostics`
+    DiagnosticsReq {
+        /// Chosen by the server, and echoed in every part of the response
+        request_id: u64,
+        /// Only include logs from this time on, in milliseconds past epoch
+        since: u64,
+        /// The largest report the peer should send in bytes, or 0 for no limit
+        max_bytes: u64,
+    },
+    /// One part of the peer's diagnostics report
+    DiagnosticsRes {
+        /// The `request_id` of the `Message::DiagnosticsReq`
+        request_id: u64,
+        /// The position of this part in the report, counting from 0
+        part: u32,
+        /// The bytes of this part
+        data: Vec<u8>,
+        /// Whether this is the last part of the report
+        end: bool,
+    },
+    /// Abandons a stream, sent either by the peer sending data on it or the peer receiving it,
+    /// see `mux::Multiplexer::reset`
+    StreamReset {
+        /// A human readable reason for abandoning the stream
+        reason: Option<String>,
     },
 }
 
 impl Message {
     /// Attempt to convert the provided type into a valid protobuf3 strestaticm.
     /// Validates that types are of the correct length before conversion.
-    #[deprecated(since = "1.0.0", note = "please use `TryFrom` instead")]
+    #[deprecated(since = "1.0.0", note = "please use `Message::to_bytes` instead")]
     pub fn into_bytes(self) -> Result<Vec<u8>, Error> {
-        use websocket_message::protobuf_types::FspComm;
-        let tmp: FspComm = self.try_into()?;
-        Ok(into_bytes!(tmp))
+        Ok(self.to_bytes())
     }
 
     /// Attempt to decode a prost byte stream into this type. Note that the
@@ -446,6 +1277,145 @@ impl Message {
     }
 }
 
+impl Message {
+    /// Decode a message from a `Bytes` buffer, such as the payload of a received websocket frame.
+    ///
+    /// Unlike `TryFrom<Vec<u8>>`, the envelope's payload is decoded in place rather than being
+    /// copied out into its own buffer first. Compressed messages are decompressed up to the
+    /// default `Limits::max_frame_size`, and no other limits are checked, so prefer
+    /// `decode_limited` for messages from an untrusted peer.
+    pub fn decode_bytes(input: Bytes) -> Result<Self, Error> {
+        use prost::Message as _;
+        use websocket_message::protobuf_types::FspComm;
+        FspComm::decode(input)?.try_into()
+    }
+
+    /// Decode a message from a `Bytes` buffer, rejecting it if it does not fit within the
+    /// provided limits.
+    ///
+    /// Use this rather than `decode_bytes` for messages received from an untrusted peer. The
+    /// frame size is checked before any decoding takes place, and again after decompression,
+    /// and the length of every field is checked before it is decoded.
+    pub fn decode_limited(input: Bytes, limits: &Limits) -> Result<Self, Error> {
+        use prost::Message as _;
+        use websocket_message::protobuf_types::FspComm;
+
+        limits.check_frame_size(input.len())?;
+        Self::decode_envelope_limited(FspComm::decode(input)?, limits)
+    }
+
+    /// Decompress, decode and check a received envelope against the provided limits.
+    pub(crate) fn decode_envelope_limited(
+        envelope: websocket_message::protobuf_types::FspComm,
+        limits: &Limits,
+    ) -> Result<Self, Error> {
+        let envelope = crate::compression::decompress(envelope, limits.max_frame_size)?;
+        encoding::check_encoded_fields(envelope.r#type, &envelope.value, &limits.field_limits())?;
+        Self::try_from(envelope)
+    }
+
+    /// Encode this message onto the end of `buf`, growing it if required.
+    ///
+    /// The message is written directly into `buf` without any intermediate allocations, so a
+    /// single buffer can be reused for every message sent down a connection.
+    pub fn encode_into(&self, buf: &mut BytesMut) {
+        buf.reserve(self.encoded_len());
+        encoding::encode(self, buf);
+    }
+
+    /// The number of bytes this message will take up once encoded, including its envelope.
+    ///
+    /// This is cheap to compute, and can be used to check a message against a size limit
+    /// before encoding it.
+    pub fn encoded_len(&self) -> usize {
+        encoding::encoded_len(self)
+    }
+
+    /// Encode this message into any `BufMut`, such as a reused per-connection write buffer.
+    ///
+    /// Fails without writing anything if `buf` does not have `encoded_len()` bytes of
+    /// remaining capacity.
+    pub fn encode_to(&self, buf: &mut impl BufMut) -> Result<(), Error> {
+        let required = self.encoded_len();
+        let remaining = buf.remaining_mut();
+        if required > remaining {
+            return Err(Error::ByteEncodeError(format!(
+                "insufficient buffer capacity, {} bytes required but only {} remaining",
+                required, remaining
+            )));
+        }
+        encoding::encode(self, buf);
+        Ok(())
+    }
+
+    /// Encode this message into a new `Vec<u8>`, ready to be sent.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        encoding::encode(self, &mut buf);
+        buf
+    }
+
+    /// Check that this message fits within the provided limits, without encoding it.
+    pub fn validate(&self, limits: &Limits) -> Result<(), Error> {
+        encoding::check_fields(self, &limits.field_limits())?;
+        limits.check_frame_size(self.encoded_len())
+    }
+
+    /// Encode `messages` into a single frame, as a `Message::Batch`, without needing to
+    /// collect them into one first.
+    pub fn batch_to_bytes(messages: &[Message]) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(encoding::batch_encoded_len(messages));
+        encoding::encode_batch(messages, &mut buf);
+        buf
+    }
+
+    /// Decode a received frame into the messages it carries, in order. A `Message::Batch` is
+    /// unpacked into its entries, while any other message is returned on its own.
+    ///
+    /// If an entry of a batch fails to decode, the error is a `Error::BatchEntryError`
+    /// holding the position of that entry.
+    pub fn decode_batch(input: Bytes) -> Result<Vec<Message>, Error> {
+        Ok(Self::decode_bytes(input)?.into_messages())
+    }
+
+    /// Unpack a `Message::Batch` into its entries, or wrap any other message in a `Vec`.
+    pub fn into_messages(self) -> Vec<Message> {
+        match self {
+            Message::Batch { messages } => messages,
+            msg => vec![msg],
+        }
+    }
+
+    /// Encode this message into a new `Vec<u8>`, failing if it does not fit within the
+    /// provided limits.
+    pub fn to_bytes_checked(&self, limits: &Limits) -> Result<Vec<u8>, Error> {
+        self.validate(limits)?;
+        Ok(self.to_bytes())
+    }
+
+    /// When the server stops waiting for this request to be completed, in milliseconds since
+    /// epoch, for requests which carry a deadline.
+    pub fn deadline(&self) -> Option<u64> {
+        match self {
+            Message::UploadTo { deadline, .. }
+            | Message::MetadataReq { deadline, .. }
+            | Message::StatusReq { deadline, .. } => *deadline,
+            _ => None,
+        }
+    }
+
+    /// The `UploadId` which ties a request to its response, for messages which carry one.
+    pub fn upload_id(&self) -> Option<UploadId> {
+        match self {
+            Message::MetadataReq { upload_id, .. }
+            | Message::MetadataRes { upload_id, .. }
+            | Message::StatusReq { upload_id, .. }
+            | Message::StatusRes { upload_id, .. } => Some(*upload_id),
+            _ => None,
+        }
+    }
+}
+
 impl TryFrom<Vec<u8>> for Message {
     type Error = Error;
     #[allow(deprecated)] //TEMP
@@ -462,10 +1432,14 @@ impl TryFrom<&[u8]> for Message {
     }
 }
 
-impl TryFrom<Message> for Vec<u8> {
-    type Error = Error;
-    #[allow(deprecated)] //TEMP
-    fn try_from(value: Message) -> Result<Self, Error> {
-        value.into_bytes()
+impl From<Vec<Message>> for Message {
+    fn from(messages: Vec<Message>) -> Self {
+        Message::Batch { messages }
+    }
+}
+
+impl From<Message> for Vec<u8> {
+    fn from(value: Message) -> Self {
+        value.to_bytes()
     }
 }
@@ -0,0 +1,408 @@
+//! Sharing a single connection between several transfers at once.
+//!
+//! Each transfer is sent on its own stream, identified by the `stream_id` of the envelopes
+//! carrying it. Data on a stream is split into `Message::StreamData` chunks, which are sent in
+//! turn with chunks from every other stream, so a large file cannot hold up a smaller one.
+//! Messages which are not stream data, such as metadata requests, are sent ahead of any chunks.
+//!
+//! Streams carry data in one direction, from the peer which opened them. The receiver grants
+//! the sender a window of bytes it may send, and extends it with `Message::WindowUpdate` as it
+//! consumes the data, so a sender can never have more than a window's worth of data buffered
+//! at the receiver for each stream. Either end can abandon a stream with `Message::StreamReset`,
+//! after which any of its data still in flight is discarded.
+//!
+//! The `Multiplexer` only tracks the state of each stream, it is up to the caller to send the
+//! messages it produces on their stream, and to feed it the messages received along with theirs,
+//! such as with the `send_on` and `next_on` methods of the transports, or with
+//! `Codec::encode_on` and `Codec::decode_on`.
+//!
+//! # Example
+//! ```rust
+//! use ws_com_framework::mux::{Multiplexer, MuxEvent, MuxOptions};
+//! use ws_com_framework::session::Role;
+//! use ws_com_framework::{Codec, Message};
+//!
+//! let codec = Codec::default();
+//! let mut agent = Multiplexer::new(Role::Agent, MuxOptions::default());
+//! let mut server = Multiplexer::new(Role::Server, MuxOptions::default());
+//!
+//! let stream_id = agent.open();
+//! agent.send_data(stream_id, vec![1; 1_000_000], true).unwrap();
+//! agent.send_message(0, Message::StatusReq { public_id: 43, upload_id: 1, deadline: None });
+//!
+//! // the connection's writer sends everything the multiplexer produces
+//! let mut received = Vec::new();
+//! while let Some((stream_id, msg)) = agent.next_outgoing() {
+//!     let frame = codec.encode_on(stream_id, &msg).unwrap();
+//!     let (stream_id, msg) = codec.decode_on(frame.into()).unwrap();
+//!     match server.receive(stream_id, msg).unwrap() {
+//!         Some(MuxEvent::Data { stream_id, data, .. }) => {
+//!             server.consume(stream_id, data.len());
+//!             received.extend(data);
+//!         }
+//!         Some(MuxEvent::Message { .. }) => assert!(received.is_empty()),
+//!         Some(MuxEvent::Reset { .. }) | None => {}
+//!     }
+//!     // hand the window updates back to the agent
+//!     while let Some((stream_id, msg)) = server.next_outgoing() {
+//!         agent.receive(stream_id, msg).unwrap();
+//!     }
+//! }
+//! assert_eq!(received.len(), 1_000_000);
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::error::Error;
+use crate::message::Message;
+use crate::session::Role;
+
+/// Identifies a stream within a connection. Stream 0 carries messages which do not belong to
+/// a stream, streams opened by the agent have odd ids, and those opened by the server even ids.
+pub type StreamId = u32;
+
+/// Flow control settings of a `Multiplexer`, which should match on both ends of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MuxOptions {
+    /// The number of bytes a peer may send on a new stream before it must wait for a
+    /// `Message::WindowUpdate`
+    pub initial_window: u32,
+    /// The largest chunk of data sent in a single `Message::StreamData`
+    pub max_chunk: usize,
+}
+
+impl Default for MuxOptions {
+    fn default() -> Self {
+        Self {
+            initial_window: 256 * 1024,
+            max_chunk: 16 * 1024,
+        }
+    }
+}
+
+/// Something received from the peer which the caller should act on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MuxEvent {
+    /// A chunk of data on a stream. Pass its length to `Multiplexer::consume` once it has been
+    /// processed, to allow the peer to send more.
+    Data {
+        /// The stream the data was sent on
+        stream_id: StreamId,
+        /// The bytes of the chunk
+        data: Vec<u8>,
+        /// Whether this was the last chunk of the stream
+        end: bool,
+    },
+    /// The peer abandoned a stream. Any data it was sending on the stream will not arrive, and
+    /// any data queued to be sent on it has been dropped.
+    Reset {
+        /// The stream which was abandoned
+        stream_id: StreamId,
+        /// Why the peer abandoned it
+        reason: Option<String>,
+    },
+    /// Any message other than stream data, a window update or a reset
+    Message {
+        /// The stream the message was sent on, 0 if it does not belong to a stream
+        stream_id: StreamId,
+        /// The message
+        message: Message,
+    },
+}
+
+/// A stream this end is sending data on.
+#[derive(Debug)]
+struct Outgoing {
+    /// Bytes which may still be sent before a window update is required
+    window: u64,
+    /// Data waiting to be sent, each with how much of it has already been sent and whether it
+    /// ends the stream
+    pending: VecDeque<(Vec<u8>, usize, bool)>,
+    /// Whether the stream is waiting in `Multiplexer::ready`
+    scheduled: bool,
+    /// Whether the end of the stream has been queued, after which no more data can be sent
+    ended: bool,
+}
+
+impl Outgoing {
+    fn can_send(&self) -> bool {
+        match self.pending.front() {
+            // a final empty chunk ends the stream without using any of the window
+            Some((data, sent, _)) => self.window > 0 || *sent == data.len(),
+            None => false,
+        }
+    }
+}
+
+/// A stream the peer is sending data on.
+#[derive(Debug)]
+struct Incoming {
+    /// Bytes the peer may still send before it requires a window update
+    window: u64,
+    /// Bytes consumed since the last window update was sent
+    consumed: u64,
+}
+
+/// Tracks the streams of a single connection, scheduling what is sent next and enforcing flow
+/// control on what is received.
+#[derive(Debug)]
+pub struct Multiplexer {
+    options: MuxOptions,
+    /// The id of the next stream this end opens
+    next_id: StreamId,
+    /// The highest stream id the peer has opened
+    peer_highest: StreamId,
+    outgoing: HashMap<StreamId, Outgoing>,
+    incoming: HashMap<StreamId, Incoming>,
+    /// Messages sent ahead of any stream data
+    control: VecDeque<(StreamId, Message)>,
+    /// Streams with data they are able to send, in the order they take turns
+    ready: VecDeque<StreamId>,
+}
+
+impl Multiplexer {
+    /// Create a multiplexer for our end of a connection, with no open streams.
+    pub fn new(role: Role, options: MuxOptions) -> Self {
+        Self {
+            options,
+            next_id: match role {
+                Role::Agent => 1,
+                Role::Server => 2,
+            },
+            peer_highest: 0,
+            outgoing: HashMap::new(),
+            incoming: HashMap::new(),
+            control: VecDeque::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Open a new stream to send data to the peer on, returning its id.
+    pub fn open(&mut self) -> StreamId {
+        let stream_id = self.next_id;
+        self.next_id += 2;
+        self.outgoing.insert(
+            stream_id,
+            Outgoing {
+                window: u64::from(self.options.initial_window),
+                pending: VecDeque::new(),
+                scheduled: false,
+                ended: false,
+            },
+        );
+        stream_id
+    }
+
+    /// Queue data to be sent on a stream opened with `open`, ending the stream if `end` is set.
+    ///
+    /// Fails if the stream was not opened by this end, or has already been ended.
+    pub fn send_data(
+        &mut self,
+        stream_id: StreamId,
+        data: Vec<u8>,
+        end: bool,
+    ) -> Result<(), Error> {
+        let stream = match self.outgoing.get_mut(&stream_id) {
+            Some(stream) if !stream.ended => stream,
+            _ => {
+                return Err(Error::StreamError {
+                    stream_id,
+                    reason: String::from("stream is not open for sending"),
+                })
+            }
+        };
+        stream.ended = end;
+        if !data.is_empty() || end {
+            stream.pending.push_back((data, 0, end));
+        }
+        if !stream.scheduled && stream.can_send() {
+            stream.scheduled = true;
+            self.ready.push_back(stream_id);
+        }
+        Ok(())
+    }
+
+    /// Queue a message which is not stream data, such as a request, to be sent ahead of any
+    /// stream data. It is sent on `stream_id`, which may be 0.
+    pub fn send_message(&mut self, stream_id: StreamId, msg: Message) {
+        self.control.push_back((stream_id, msg));
+    }
+
+    /// Abandon a stream, whether this end is sending or receiving on it, and queue a
+    /// `Message::StreamReset` to tell the peer.
+    ///
+    /// Data still queued to be sent on the stream is dropped, and data the peer sends on it from
+    /// now on is discarded. Fails if the stream is not open.
+    pub fn reset(&mut self, stream_id: StreamId, reason: Option<String>) -> Result<(), Error> {
+        if self.outgoing.remove(&stream_id).is_some() {
+            self.ready.retain(|&id| id != stream_id);
+        } else if self.incoming.remove(&stream_id).is_none() {
+            return Err(Error::StreamError {
+                stream_id,
+                reason: String::from("stream is not open"),
+            });
+        }
+        self.control
+            .push_back((stream_id, Message::StreamReset { reason }));
+        Ok(())
+    }
+
+    /// The next message to send and the stream to send it on, or `None` if there is nothing
+    /// which can be sent until more data is queued or the peer extends a window.
+    ///
+    /// Messages queued with `send_message` and window updates come first, then a chunk from
+    /// each stream with data in turn.
+    pub fn next_outgoing(&mut self) -> Option<(StreamId, Message)> {
+        if let Some(next) = self.control.pop_front() {
+            return Some(next);
+        }
+
+        let stream_id = self.ready.pop_front()?;
+        let stream = self
+            .outgoing
+            .get_mut(&stream_id)
+            .expect("ready streams are open");
+        let (data, sent, end) = stream
+            .pending
+            .front_mut()
+            .expect("ready streams have data to send");
+        let len = (data.len() - *sent)
+            .min(self.options.max_chunk)
+            .min(usize::try_from(stream.window).unwrap_or(usize::MAX));
+        let chunk = data[*sent..*sent + len].to_vec();
+        *sent += len;
+        let last = *sent == data.len();
+        let end = last && *end;
+        if last {
+            stream.pending.pop_front();
+        }
+        stream.window -= len as u64;
+
+        if end {
+            self.outgoing.remove(&stream_id);
+        } else if stream.can_send() {
+            self.ready.push_back(stream_id);
+        } else {
+            stream.scheduled = false;
+        }
+        Some((stream_id, Message::StreamData { data: chunk, end }))
+    }
+
+    /// Whether `next_outgoing` has anything to send.
+    pub fn has_outgoing(&self) -> bool {
+        !self.control.is_empty() || !self.ready.is_empty()
+    }
+
+    /// The number of bytes which may still be sent on a stream before the peer must extend its
+    /// window, or `None` if this end is not sending on the stream.
+    pub fn send_window(&self, stream_id: StreamId) -> Option<u64> {
+        self.outgoing.get(&stream_id).map(|stream| stream.window)
+    }
+
+    /// Handle a message received from the peer on `stream_id`.
+    ///
+    /// Window updates are applied and return `None`, while stream data, resets and any other
+    /// messages are returned as a `MuxEvent`. Data on a stream which has been reset or has ended
+    /// is dropped, also returning `None`. Fails with `Error::StreamError` if the peer sends data
+    /// outside of its window, or on a stream it cannot send on, after which the connection
+    /// should be closed.
+    pub fn receive(
+        &mut self,
+        stream_id: StreamId,
+        msg: Message,
+    ) -> Result<Option<MuxEvent>, Error> {
+        match msg {
+            Message::WindowUpdate { increment } => {
+                // updates for streams which have since ended are ignored
+                if let Some(stream) = self.outgoing.get_mut(&stream_id) {
+                    stream.window += u64::from(increment);
+                    if !stream.scheduled && stream.can_send() {
+                        stream.scheduled = true;
+                        self.ready.push_back(stream_id);
+                    }
+                }
+                Ok(None)
+            }
+            // stream ids only increase, so this is data in flight when the stream was reset
+            Message::StreamData { .. } if self.has_ended(stream_id) => Ok(None),
+            Message::StreamData { data, end } => {
+                let stream = self.incoming_stream(stream_id)?;
+                let len = data.len() as u64;
+                if len > stream.window {
+                    return Err(Error::StreamError {
+                        stream_id,
+                        reason: format!(
+                            "{} bytes of data exceeds the window of {} bytes",
+                            len, stream.window
+                        ),
+                    });
+                }
+                stream.window -= len;
+                if end {
+                    self.incoming.remove(&stream_id);
+                }
+                Ok(Some(MuxEvent::Data {
+                    stream_id,
+                    data,
+                    end,
+                }))
+            }
+            Message::StreamReset { reason } => {
+                if self.opened_by_peer(stream_id) {
+                    // the reset may overtake data sent before it, which is then discarded
+                    self.incoming.remove(&stream_id);
+                    self.peer_highest = self.peer_highest.max(stream_id);
+                } else if self.outgoing.remove(&stream_id).is_some() {
+                    self.ready.retain(|&id| id != stream_id);
+                }
+                Ok(Some(MuxEvent::Reset { stream_id, reason }))
+            }
+            message => Ok(Some(MuxEvent::Message { stream_id, message })),
+        }
+    }
+
+    /// Record that `len` bytes of data received on a stream have been processed, sending the
+    /// peer a window update once half of the initial window has been consumed.
+    pub fn consume(&mut self, stream_id: StreamId, len: usize) {
+        let threshold = u64::from(self.options.initial_window / 2).max(1);
+        let Some(stream) = self.incoming.get_mut(&stream_id) else {
+            return;
+        };
+        stream.consumed += len as u64;
+        if stream.consumed >= threshold {
+            let increment = u32::try_from(stream.consumed).unwrap_or(u32::MAX);
+            stream.consumed -= u64::from(increment);
+            stream.window += u64::from(increment);
+            self.control
+                .push_back((stream_id, Message::WindowUpdate { increment }));
+        }
+    }
+
+    /// The state of a stream the peer is sending on, opening it if this is its first chunk.
+    fn incoming_stream(&mut self, stream_id: StreamId) -> Result<&mut Incoming, Error> {
+        if !self.opened_by_peer(stream_id) {
+            return Err(Error::StreamError {
+                stream_id,
+                reason: String::from("data sent on a stream the peer did not open"),
+            });
+        }
+        self.peer_highest = self.peer_highest.max(stream_id);
+        let window = u64::from(self.options.initial_window);
+        Ok(self.incoming.entry(stream_id).or_insert(Incoming {
+            window,
+            consumed: 0,
+        }))
+    }
+
+    /// Whether `stream_id` is a stream the peer opened which has since ended or been reset.
+    fn has_ended(&self, stream_id: StreamId) -> bool {
+        self.opened_by_peer(stream_id)
+            && stream_id <= self.peer_highest
+            && !self.incoming.contains_key(&stream_id)
+    }
+
+    /// Whether `stream_id` belongs to a stream the peer opens, rather than this end.
+    fn opened_by_peer(&self, stream_id: StreamId) -> bool {
+        stream_id != 0 && stream_id % 2 != self.next_id % 2
+    }
+}
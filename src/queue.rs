@@ -0,0 +1,372 @@
+//! A bounded queue of encoded frames waiting to be sent on a connection, so a slow peer cannot
+//! make the sender buffer without limit.
+//!
+//! Control messages are sent ahead of bulk ones, and what happens when the queue is full is
+//! configured with an `Overflow` policy.
+//!
+//! Frames are queued already encoded, so with signing enabled a control frame overtaking bulk
+//! frames arrives with a higher sequence number than they have. At most `MAX_OVERTAKE` control
+//! frames are sent ahead of any one bulk frame, which keeps the reordering well inside the 64
+//! sequence numbers a peer's replay window accepts out of order.
+//!
+//! # Example
+//! ```rust
+//! use ws_com_framework::queue::{outbound_queue, QueueOptions};
+//! use ws_com_framework::Message;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let (tx, mut rx) = outbound_queue(QueueOptions::default());
+//!
+//! tx.send_message(&Message::StatusReq { public_id: 43, upload_id: 1, deadline: None }).await.unwrap();
+//! tx.send_message(&Message::Ok).await.unwrap();
+//!
+//! // the connection's writer task
+//! assert_eq!(rx.recv().await.unwrap(), Message::Ok.to_bytes());
+//! # }
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use bytes::Bytes;
+use tokio::sync::Notify;
+
+use crate::error::Error;
+use crate::message::Message;
+
+/// What happens when a frame is sent to a full queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Wait until the receiver makes room
+    #[default]
+    Block,
+    /// Discard the oldest bulk frames to make room. Control frames are never discarded, so if only
+    /// control frames are queued this waits like `Block`.
+    DropOldest,
+    /// Close the queue, failing with `Error::QueueFull`, so the connection can be dropped
+    Disconnect,
+}
+
+/// Which frames are sent first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Short replies, handshake messages and stream flow control, sent ahead of bulk frames
+    /// unless a bulk frame has already been overtaken `MAX_OVERTAKE` times
+    Control,
+    /// Requests and data, sent in order once there are no control frames waiting
+    Bulk,
+}
+
+impl Priority {
+    /// The priority a message is queued with by `OutboundSender::send_message`.
+    pub fn of(msg: &Message) -> Self {
+        match msg {
+            Message::Ok
+            | Message::Error { .. }
+            | Message::AuthReq { .. }
+            | Message::AuthRes { .. }
+            | Message::TokenAuth { .. }
+            | Message::AuthOk { .. }
+            | Message::RefreshSession
+            | Message::RotatePasscodeReq { .. }
+            | Message::RotatePasscodeRes { .. }
+            | Message::RegisterReq { .. }
+            | Message::RegisterRes { .. }
+            | Message::Goodbye { .. }
+            | Message::Redirect { .. }
+            | Message::WindowUpdate { .. }
+            | Message::StreamReset { .. } => Priority::Control,
+            _ => Priority::Bulk,
+        }
+    }
+}
+
+/// The most control frames sent ahead of a bulk frame queued before them.
+pub const MAX_OVERTAKE: u64 = 32;
+
+/// Limits of an outbound queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueOptions {
+    /// The maximum number of frames waiting to be sent
+    pub max_frames: usize,
+    /// The maximum total size of frames waiting to be sent, in bytes. A single larger frame is
+    /// still accepted by an empty queue.
+    pub max_bytes: usize,
+    /// What happens when a frame does not fit
+    pub overflow: Overflow,
+}
+
+impl Default for QueueOptions {
+    fn default() -> Self {
+        Self {
+            max_frames: 256,
+            max_bytes: 4 * 1024 * 1024,
+            overflow: Overflow::Block,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    /// Woken when a frame is queued, or the queue closes
+    queued: Notify,
+    /// Woken when room is made, or the queue closes
+    space: Notify,
+    options: QueueOptions,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    control: VecDeque<Bytes>,
+    /// Bulk frames, with the number of control frames queued before each of them
+    bulk: VecDeque<(Bytes, u64)>,
+    /// The number of control frames ever queued
+    controls_pushed: u64,
+    /// The number of control frames ever sent
+    controls_popped: u64,
+    bytes: usize,
+    dropped: u64,
+    senders: usize,
+    receiver_dropped: bool,
+    /// The frames and bytes queued when the queue overflowed with `Overflow::Disconnect`
+    overflowed: Option<(usize, usize)>,
+}
+
+impl State {
+    fn len(&self) -> usize {
+        self.control.len() + self.bulk.len()
+    }
+
+    fn fits(&self, size: usize, options: &QueueOptions) -> bool {
+        self.len() == 0
+            || (self.len() < options.max_frames && self.bytes + size <= options.max_bytes)
+    }
+
+    fn push(&mut self, frame: Bytes, priority: Priority) {
+        self.bytes += frame.len();
+        match priority {
+            Priority::Control => {
+                self.control.push_back(frame);
+                self.controls_pushed += 1;
+            }
+            Priority::Bulk => self.bulk.push_back((frame, self.controls_pushed)),
+        }
+    }
+
+    fn pop(&mut self) -> Option<Bytes> {
+        // control frames queued after the oldest bulk frame, which have already been sent
+        let overtaken = self.bulk.front().map_or(0, |(_, pushed)| {
+            self.controls_popped.saturating_sub(*pushed)
+        });
+        let frame = if overtaken < MAX_OVERTAKE {
+            self.control.pop_front()
+        } else {
+            None
+        };
+        let frame = match frame {
+            Some(frame) => {
+                self.controls_popped += 1;
+                frame
+            }
+            None => self.bulk.pop_front().map(|(frame, _)| frame).or_else(|| {
+                let frame = self.control.pop_front()?;
+                self.controls_popped += 1;
+                Some(frame)
+            })?,
+        };
+        self.bytes -= frame.len();
+        Some(frame)
+    }
+
+    /// Discard the oldest bulk frame, returning false if there are none.
+    fn drop_oldest(&mut self) -> bool {
+        match self.bulk.pop_front() {
+            Some((frame, _)) => {
+                self.bytes -= frame.len();
+                self.dropped += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn closed_error(&self) -> Option<Error> {
+        if let Some((frames, bytes)) = self.overflowed {
+            Some(Error::QueueFull { frames, bytes })
+        } else if self.receiver_dropped {
+            Some(Error::TransportError(String::from(
+                "the outbound queue's receiver was dropped",
+            )))
+        } else {
+            None
+        }
+    }
+}
+
+/// Create a queue with the provided limits, returning the sending and receiving halves.
+///
+/// Control frames overtake bulk frames, but no bulk frame waits behind more than `MAX_OVERTAKE`
+/// control frames queued after it, so signed frames stay within the peer's replay window.
+pub fn outbound_queue(options: QueueOptions) -> (OutboundSender, OutboundReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            senders: 1,
+            ..State::default()
+        }),
+        queued: Notify::new(),
+        space: Notify::new(),
+        options,
+    });
+    (
+        OutboundSender {
+            shared: shared.clone(),
+        },
+        OutboundReceiver { shared },
+    )
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("queue lock poisoned")
+    }
+}
+
+/// The sending half of an outbound queue, which can be cloned to send from several tasks.
+#[derive(Debug)]
+pub struct OutboundSender {
+    shared: Arc<Shared>,
+}
+
+impl OutboundSender {
+    /// Encode a message and queue it with `Priority::of(msg)`.
+    pub async fn send_message(&self, msg: &Message) -> Result<(), Error> {
+        self.send(Bytes::from(msg.to_bytes()), Priority::of(msg))
+            .await
+    }
+
+    /// Queue an encoded frame, handling a full queue according to its `Overflow` policy.
+    ///
+    /// Fails with `Error::QueueFull` if the queue overflowed with `Overflow::Disconnect`, now or
+    /// earlier, and with `Error::TransportError` if the receiver was dropped.
+    pub async fn send(&self, frame: Bytes, priority: Priority) -> Result<(), Error> {
+        let options = &self.shared.options;
+        loop {
+            let space = self.shared.space.notified();
+            tokio::pin!(space);
+            space.as_mut().enable();
+
+            {
+                let mut state = self.shared.lock();
+                if let Some(err) = state.closed_error() {
+                    return Err(err);
+                }
+                if !state.fits(frame.len(), options) {
+                    match options.overflow {
+                        Overflow::Block => {}
+                        Overflow::DropOldest => {
+                            while !state.fits(frame.len(), options) && state.drop_oldest() {}
+                        }
+                        Overflow::Disconnect => {
+                            let (frames, bytes) = (state.len(), state.bytes);
+                            state.overflowed = Some((frames, bytes));
+                            drop(state);
+                            self.shared.queued.notify_one();
+                            self.shared.space.notify_waiters();
+                            return Err(Error::QueueFull { frames, bytes });
+                        }
+                    }
+                }
+                if state.fits(frame.len(), options) {
+                    state.push(frame, priority);
+                    drop(state);
+                    self.shared.queued.notify_one();
+                    return Ok(());
+                }
+            }
+            space.await;
+        }
+    }
+
+    /// The number of frames waiting to be sent.
+    pub fn len(&self) -> usize {
+        self.shared.lock().len()
+    }
+
+    /// Whether no frames are waiting to be sent.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The total size of the frames waiting to be sent, in bytes.
+    pub fn bytes(&self) -> usize {
+        self.shared.lock().bytes
+    }
+
+    /// The number of frames discarded by `Overflow::DropOldest`.
+    pub fn dropped(&self) -> u64 {
+        self.shared.lock().dropped
+    }
+}
+
+impl Clone for OutboundSender {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for OutboundSender {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.senders -= 1;
+        }
+        self.shared.queued.notify_one();
+    }
+}
+
+/// The receiving half of an outbound queue, read by the task writing to the connection.
+#[derive(Debug)]
+pub struct OutboundReceiver {
+    shared: Arc<Shared>,
+}
+
+impl OutboundReceiver {
+    /// The next frame to send, control frames first.
+    ///
+    /// Returns `None` once every sender has been dropped and the queue is empty, or as soon as
+    /// the queue overflows with `Overflow::Disconnect`, after which the connection should be
+    /// closed.
+    pub async fn recv(&mut self) -> Option<Bytes> {
+        loop {
+            let queued = self.shared.queued.notified();
+            {
+                let mut state = self.shared.lock();
+                if state.overflowed.is_some() {
+                    return None;
+                }
+                if let Some(frame) = state.pop() {
+                    drop(state);
+                    self.shared.space.notify_waiters();
+                    return Some(frame);
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+            }
+            queued.await;
+        }
+    }
+}
+
+impl Drop for OutboundReceiver {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.receiver_dropped = true;
+        }
+        self.shared.space.notify_waiters();
+    }
+}
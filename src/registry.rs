@@ -0,0 +1,345 @@
+//! Tracking of authenticated agent connections on the server, so that messages can be routed to
+//! an agent by its `PublicId`.
+//!
+//! # Example
+//! ```rust
+//! use ws_com_framework::registry::{ConnectionRegistry, DuplicateLogin};
+//! use ws_com_framework::Message;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let registry = ConnectionRegistry::new(DuplicateLogin::KickOld);
+//!
+//! // once the agent has authenticated, in the task handling its connection
+//! let mut session = registry.register(43).unwrap();
+//!
+//! // anywhere else in the server
+//! let req = Message::StatusReq { public_id: 43, upload_id: 1, deadline: None };
+//! let reply = tokio::spawn({
+//!     let registry = registry.clone();
+//!     async move { registry.request(43, req).await }
+//! });
+//!
+//! // the connection task sends what it receives from the session to the agent, and
+//! // dispatches what it receives from the agent back to the session
+//! let req = session.recv().await.unwrap();
+//! let res = Message::StatusRes {
+//!     public_id: 43,
+//!     ready: true,
+//!     uptime: 10,
+//!     upload_id: req.upload_id().unwrap(),
+//!     message: None,
+//!     active_shares: None,
+//!     bytes_shared: None,
+//!     uploads_in_progress: None,
+//!     free_disk: None,
+//!     agent_version: None,
+//!     os: None,
+//! };
+//! assert_eq!(session.dispatch(res.clone()), None);
+//! assert_eq!(reply.await.unwrap().unwrap(), res);
+//! # }
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::deadline::remaining;
+use crate::error::Error;
+use crate::message::{Message, PublicId, UploadId};
+
+/// How many messages can be queued for an agent before sending to it waits.
+const QUEUE_SIZE: usize = 64;
+
+/// How long `ConnectionRegistry::request` waits for a reply by default.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What happens when an agent registers while a session with its `PublicId` already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateLogin {
+    /// End the existing session, and register the new one
+    #[default]
+    KickOld,
+    /// Keep the existing session, and fail to register the new one
+    RejectNew,
+}
+
+/// The authenticated agent sessions on a server, keyed by `PublicId`.
+///
+/// Cloning a registry gives another handle to the same sessions.
+#[derive(Debug, Clone)]
+pub struct ConnectionRegistry {
+    sessions: Arc<Mutex<Sessions>>,
+    duplicate_login: DuplicateLogin,
+    request_timeout: Duration,
+}
+
+impl Default for ConnectionRegistry {
+    fn default() -> Self {
+        Self::new(DuplicateLogin::default())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Sessions {
+    by_public_id: HashMap<PublicId, Entry>,
+    next_id: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    id: u64,
+    tx: mpsc::Sender<Message>,
+    replies: Arc<Mutex<Replies>>,
+}
+
+/// Requests waiting for a reply from an agent, each numbered in the order it was sent.
+#[derive(Debug, Default)]
+struct Replies {
+    /// Requests whose reply carries the same `UploadId`, or is a `Message::Error`, in the order
+    /// they were sent for each `UploadId`
+    by_upload_id: HashMap<UploadId, VecDeque<(u64, oneshot::Sender<Message>)>>,
+    /// Requests answered by `Message::Ok` or `Message::Error`, in the order they were sent
+    in_order: VecDeque<(u64, oneshot::Sender<Message>)>,
+    next_seq: u64,
+}
+
+impl Replies {
+    /// Forget requests whose caller has stopped waiting.
+    fn prune(&mut self) {
+        self.by_upload_id.retain(|_, waiting| {
+            waiting.retain(|(_, tx)| !tx.is_closed());
+            !waiting.is_empty()
+        });
+        self.in_order.retain(|(_, tx)| !tx.is_closed());
+    }
+
+    /// The oldest request waiting for the reply with `upload_id`.
+    fn take_upload(&mut self, upload_id: UploadId) -> Option<(u64, oneshot::Sender<Message>)> {
+        let waiting = self.by_upload_id.get_mut(&upload_id)?;
+        let next = waiting.pop_front();
+        if waiting.is_empty() {
+            self.by_upload_id.remove(&upload_id);
+        }
+        next
+    }
+
+    /// The oldest request waiting for a `Message::Error`, of either kind.
+    fn oldest(&mut self) -> Option<oneshot::Sender<Message>> {
+        let in_order = self.in_order.front().map(|(seq, _)| *seq);
+        let by_upload_id = self
+            .by_upload_id
+            .iter()
+            .filter_map(|(upload_id, waiting)| Some((waiting.front()?.0, *upload_id)))
+            .min();
+        match (in_order, by_upload_id) {
+            (Some(a), Some((b, upload_id))) if b < a => self.take_upload(upload_id),
+            (None, Some((_, upload_id))) => self.take_upload(upload_id),
+            _ => self.in_order.pop_front(),
+        }
+        .map(|(_, tx)| tx)
+    }
+}
+
+impl ConnectionRegistry {
+    /// Create an empty registry, handling duplicate logins as specified.
+    pub fn new(duplicate_login: DuplicateLogin) -> Self {
+        Self {
+            sessions: Arc::default(),
+            duplicate_login,
+            request_timeout: REQUEST_TIMEOUT,
+        }
+    }
+
+    /// Wait at most `request_timeout` for the reply to a request without an earlier deadline,
+    /// instead of 30 seconds.
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    /// Record an authenticated agent's session.
+    ///
+    /// If the agent is already connected, either the existing session is ended, or this fails
+    /// with `Error::AlreadyConnected`, depending on the registry's `DuplicateLogin`.
+    pub fn register(&self, public_id: PublicId) -> Result<AgentSession, Error> {
+        let mut sessions = self.lock();
+        if self.duplicate_login == DuplicateLogin::RejectNew
+            && sessions.by_public_id.contains_key(&public_id)
+        {
+            return Err(Error::AlreadyConnected { public_id });
+        }
+
+        let id = sessions.next_id;
+        sessions.next_id += 1;
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        let replies = Arc::<Mutex<Replies>>::default();
+        // replacing an existing entry drops its sender, which ends that session
+        sessions.by_public_id.insert(
+            public_id,
+            Entry {
+                id,
+                tx,
+                replies: replies.clone(),
+            },
+        );
+
+        Ok(AgentSession {
+            public_id,
+            id,
+            rx,
+            replies,
+            sessions: self.sessions.clone(),
+        })
+    }
+
+    /// Whether an agent with this `PublicId` is connected.
+    pub fn is_connected(&self, public_id: PublicId) -> bool {
+        self.lock().by_public_id.contains_key(&public_id)
+    }
+
+    /// The `PublicId`s of all connected agents.
+    pub fn connected(&self) -> Vec<PublicId> {
+        self.lock().by_public_id.keys().copied().collect()
+    }
+
+    /// The number of connected agents.
+    pub fn len(&self) -> usize {
+        self.lock().by_public_id.len()
+    }
+
+    /// Whether no agents are connected.
+    pub fn is_empty(&self) -> bool {
+        self.lock().by_public_id.is_empty()
+    }
+
+    /// Queue a message to be sent to an agent, waiting if its queue is full.
+    pub async fn send(&self, public_id: PublicId, msg: Message) -> Result<(), Error> {
+        let tx = self.entry(public_id, |entry| entry.tx.clone())?;
+        tx.send(msg)
+            .await
+            .map_err(|_| Error::NotConnected { public_id })
+    }
+
+    /// Send a request to an agent, and wait for its reply.
+    ///
+    /// A request carrying an `UploadId` is answered by the reply with the same `UploadId`, with
+    /// several requests for the same `UploadId` answered in the order they were sent. Any other
+    /// request is answered by the next `Message::Ok` not already claimed by an earlier
+    /// request. A `Message::Error` answers the oldest request still waiting, of either kind.
+    ///
+    /// Fails with `Error::Timeout` if there is no reply by the request's deadline, or within
+    /// the registry's request timeout if that is sooner, and with `Error::NotConnected` if the
+    /// agent disconnects before replying.
+    pub async fn request(&self, public_id: PublicId, msg: Message) -> Result<Message, Error> {
+        let limit =
+            remaining(&msg).map_or(self.request_timeout, |left| left.min(self.request_timeout));
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let tx = self.entry(public_id, |entry| {
+            let mut replies = entry.replies.lock().expect("registry lock poisoned");
+            replies.prune();
+            let seq = replies.next_seq;
+            replies.next_seq += 1;
+            match msg.upload_id() {
+                Some(upload_id) => {
+                    let waiting = replies.by_upload_id.entry(upload_id).or_default();
+                    waiting.push_back((seq, reply_tx));
+                }
+                None => replies.in_order.push_back((seq, reply_tx)),
+            }
+            entry.tx.clone()
+        })?;
+
+        // dropping the reply receiver, on any early return or if the caller gives up, closes
+        // its slot so that `dispatch` passes over it
+        let reply = async move {
+            let sent = tx.send(msg).await;
+            // a session replaced while the reply is awaited must still see its channel close
+            drop(tx);
+            sent.map_err(|_| Error::NotConnected { public_id })?;
+            reply_rx
+                .await
+                .map_err(|_| Error::NotConnected { public_id })
+        };
+        tokio::time::timeout(limit, reply)
+            .await
+            .unwrap_or(Err(Error::Timeout { public_id }))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Sessions> {
+        self.sessions.lock().expect("registry lock poisoned")
+    }
+
+    fn entry<T>(&self, public_id: PublicId, f: impl FnOnce(&Entry) -> T) -> Result<T, Error> {
+        self.lock()
+            .by_public_id
+            .get(&public_id)
+            .map(f)
+            .ok_or(Error::NotConnected { public_id })
+    }
+}
+
+/// An agent's registration in a `ConnectionRegistry`, held by the task handling its connection.
+///
+/// The task sends every message from `recv` to the agent, and passes every message received
+/// from the agent to `dispatch`. Dropping the session removes it from the registry.
+#[derive(Debug)]
+pub struct AgentSession {
+    public_id: PublicId,
+    id: u64,
+    rx: mpsc::Receiver<Message>,
+    replies: Arc<Mutex<Replies>>,
+    sessions: Arc<Mutex<Sessions>>,
+}
+
+impl AgentSession {
+    /// The `PublicId` of the agent.
+    pub fn public_id(&self) -> PublicId {
+        self.public_id
+    }
+
+    /// The next message to send to the agent.
+    ///
+    /// Returns `None` once the session has been replaced by a newer login, after which the
+    /// connection should be closed.
+    pub async fn recv(&mut self) -> Option<Message> {
+        self.rx.recv().await
+    }
+
+    /// Route a message received from the agent to the request waiting for it.
+    ///
+    /// Returns the message if it is not a reply to a request made through the registry, so
+    /// the server can handle it itself. Requests whose caller has stopped waiting are skipped.
+    pub fn dispatch(&self, msg: Message) -> Option<Message> {
+        let mut replies = self.replies.lock().expect("registry lock poisoned");
+        replies.prune();
+        let waiting = match &msg {
+            Message::MetadataRes { upload_id, .. } | Message::StatusRes { upload_id, .. } => {
+                replies.take_upload(*upload_id).map(|(_, tx)| tx)
+            }
+            Message::Ok => replies.in_order.pop_front().map(|(_, tx)| tx),
+            Message::Error { .. } => replies.oldest(),
+            _ => None,
+        };
+        match waiting {
+            Some(tx) => tx.send(msg).err(),
+            None => Some(msg),
+        }
+    }
+}
+
+impl Drop for AgentSession {
+    fn drop(&mut self) {
+        let Ok(mut sessions) = self.sessions.lock() else {
+            return;
+        };
+        // a newer login may already have replaced this session
+        if matches!(sessions.by_public_id.get(&self.public_id), Some(entry) if entry.id == self.id)
+        {
+            sessions.by_public_id.remove(&self.public_id);
+        }
+    }
+}
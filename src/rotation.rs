@@ -0,0 +1,270 @@
+//! Rotating an agent's `Passcode` over an authenticated connection, without either side being
+//! locked out if the connection drops part way through.
+//!
+//! Either side proposes a new passcode in a `Message::RotatePasscodeReq`, which the other side
+//! accepts or rejects with a `Message::RotatePasscodeRes`. The rotation is committed in two
+//! phases:
+//!
+//! 1. The server holds the new passcode as pending from the moment it knows of it, and accepts
+//!    both the old and the new passcode while it is pending.
+//! 2. The agent switches to the new passcode once both sides know of it, on receiving the
+//!    server's request or the server's acceptance of its own, keeping the old passcode to fall
+//!    back on if the new one is rejected. The server commits the rotation when the agent accepts
+//!    its request, or authenticates with the new passcode, and abandons it if the agent
+//!    authenticates with the old one.
+//!
+//! The server handles one rotation at a time. `AgentPasscode` and `ServerPasscode` track each
+//! side, and both should be persisted whenever they change so a restart mid-rotation cannot lock
+//! the agent out.
+//!
+//! # Example
+//! ```rust
+//! use ws_com_framework::rotation::{AgentPasscode, ServerPasscode};
+//! use ws_com_framework::Message;
+//!
+//! let mut agent = AgentPasscode::new(b"old".to_vec());
+//! let mut server = ServerPasscode::new(b"old".to_vec());
+//!
+//! // the server issues a new passcode, and the agent accepts it
+//! let req = server.issue(1, b"new".to_vec()).unwrap();
+//! let res = agent.handle(&req);
+//! assert!(matches!(res, Some(Message::RotatePasscodeRes { accepted: true, .. })));
+//! assert_eq!(agent.current(), b"new");
+//!
+//! // the connection drops before the server sees the response, so both passcodes still work
+//! assert_eq!(server.pending(), Some((1, &b"new".to_vec())));
+//! assert!(server.verify(b"new"));
+//! assert_eq!(server.current(), b"new");
+//! assert!(!server.verify(b"old"));
+//! ```
+
+use subtle::{Choice, ConstantTimeEq};
+
+use crate::message::{Message, Passcode};
+
+/// The agent's side of passcode rotation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentPasscode {
+    current: Passcode,
+    /// The passcode before the last rotation, to fall back on if the server rejects `current`
+    previous: Option<Passcode>,
+    /// A passcode we proposed which the server has not yet answered
+    proposed: Option<(u64, Passcode)>,
+}
+
+impl AgentPasscode {
+    /// Start with the agent's provisioned passcode.
+    pub fn new(passcode: Passcode) -> Self {
+        Self::restore(passcode, None)
+    }
+
+    /// Restore the passcodes persisted from `current` and `previous`.
+    pub fn restore(current: Passcode, previous: Option<Passcode>) -> Self {
+        Self {
+            current,
+            previous,
+            proposed: None,
+        }
+    }
+
+    /// The passcode to authenticate with.
+    pub fn current(&self) -> &Passcode {
+        &self.current
+    }
+
+    /// The passcode to fall back on if the server rejects `current`.
+    pub fn previous(&self) -> Option<&Passcode> {
+        self.previous.as_ref()
+    }
+
+    /// The passcode for authentication attempt `attempt` on a connection, counting from `0`:
+    /// the current passcode, then the previous one, then the current one again.
+    pub fn attempt(&self, attempt: usize) -> &Passcode {
+        match (attempt, &self.previous) {
+            (1, Some(previous)) => previous,
+            _ => &self.current,
+        }
+    }
+
+    /// Propose `passcode` to the server, returning the `Message::RotatePasscodeReq` to send.
+    ///
+    /// The agent keeps authenticating with its current passcode until the server accepts.
+    pub fn propose(&mut self, rotation_id: u64, passcode: Passcode) -> Message {
+        self.proposed = Some((rotation_id, passcode.clone()));
+        Message::RotatePasscodeReq {
+            rotation_id,
+            passcode,
+        }
+    }
+
+    /// Handle a rotation message from the server, returning the reply to send, if any.
+    ///
+    /// A `Message::RotatePasscodeReq` switches to the issued passcode and is accepted, and a
+    /// `Message::RotatePasscodeRes` accepting our proposal switches to the proposed passcode.
+    /// Any other message is ignored.
+    pub fn handle(&mut self, msg: &Message) -> Option<Message> {
+        match msg {
+            Message::RotatePasscodeReq {
+                rotation_id,
+                passcode,
+            } => {
+                if passcode.is_empty() {
+                    return Some(rejected(*rotation_id, "the passcode is empty"));
+                }
+                // the server's rotation supersedes any of ours
+                self.proposed = None;
+                self.switch(passcode.clone());
+                Some(Message::RotatePasscodeRes {
+                    rotation_id: *rotation_id,
+                    accepted: true,
+                    reason: None,
+                })
+            }
+            Message::RotatePasscodeRes {
+                rotation_id,
+                accepted,
+                ..
+            } => {
+                if !matches!(&self.proposed, Some((id, _)) if id == rotation_id) {
+                    return None;
+                }
+                let (_, passcode) = self.proposed.take().expect("checked above");
+                if *accepted {
+                    self.switch(passcode);
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn switch(&mut self, passcode: Passcode) {
+        // a repeated request must not lose the passcode to fall back on
+        if passcode != self.current {
+            self.previous = Some(std::mem::replace(&mut self.current, passcode));
+        }
+    }
+}
+
+/// The server's side of passcode rotation, for a single agent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerPasscode {
+    current: Passcode,
+    /// A passcode which has been proposed, and the id of its rotation
+    pending: Option<(u64, Passcode)>,
+}
+
+impl ServerPasscode {
+    /// Start with the agent's provisioned passcode.
+    pub fn new(passcode: Passcode) -> Self {
+        Self::restore(passcode, None)
+    }
+
+    /// Restore the passcodes persisted from `current` and `pending`.
+    pub fn restore(current: Passcode, pending: Option<(u64, Passcode)>) -> Self {
+        Self { current, pending }
+    }
+
+    /// The agent's passcode, as last committed.
+    pub fn current(&self) -> &Passcode {
+        &self.current
+    }
+
+    /// The rotation in progress and the passcode it proposes, if any.
+    pub fn pending(&self) -> Option<(u64, &Passcode)> {
+        self.pending.as_ref().map(|(id, passcode)| (*id, passcode))
+    }
+
+    /// Issue the agent a new passcode, returning the `Message::RotatePasscodeReq` to send.
+    ///
+    /// Returns `None` if a rotation is already in progress.
+    pub fn issue(&mut self, rotation_id: u64, passcode: Passcode) -> Option<Message> {
+        if self.pending.is_some() {
+            return None;
+        }
+        self.pending = Some((rotation_id, passcode.clone()));
+        Some(Message::RotatePasscodeReq {
+            rotation_id,
+            passcode,
+        })
+    }
+
+    /// Handle a rotation message from the agent, returning the reply to send, if any.
+    ///
+    /// A `Message::RotatePasscodeReq` is accepted unless the passcode is empty or another
+    /// rotation is in progress, and a `Message::RotatePasscodeRes` to our request commits or
+    /// abandons it. Any other message is ignored.
+    pub fn handle(&mut self, msg: &Message) -> Option<Message> {
+        match msg {
+            Message::RotatePasscodeReq {
+                rotation_id,
+                passcode,
+            } => {
+                if passcode.is_empty() {
+                    return Some(rejected(*rotation_id, "the passcode is empty"));
+                }
+                match &self.pending {
+                    // a repeated request is accepted again
+                    Some(pending) if pending.0 == *rotation_id && pending.1 == *passcode => {}
+                    Some(_) => {
+                        return Some(rejected(*rotation_id, "a rotation is already in progress"))
+                    }
+                    None => self.pending = Some((*rotation_id, passcode.clone())),
+                }
+                Some(Message::RotatePasscodeRes {
+                    rotation_id: *rotation_id,
+                    accepted: true,
+                    reason: None,
+                })
+            }
+            Message::RotatePasscodeRes {
+                rotation_id,
+                accepted,
+                ..
+            } => {
+                if !matches!(&self.pending, Some((id, _)) if id == rotation_id) {
+                    return None;
+                }
+                let (_, passcode) = self.pending.take().expect("checked above");
+                // the agent switched before accepting
+                if *accepted {
+                    self.current = passcode;
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Check the passcode of a `Message::AuthRes`.
+    ///
+    /// While a rotation is in progress both passcodes are accepted: the new passcode commits
+    /// it, and the old passcode abandons it.
+    pub fn verify(&mut self, passcode: &[u8]) -> bool {
+        // both candidates are always compared, in constant time, so timing reveals neither
+        let matches_pending = self
+            .pending
+            .as_ref()
+            .map_or(Choice::from(0), |(_, pending)| pending.ct_eq(passcode));
+        let matches_current = self.current.ct_eq(passcode);
+
+        if bool::from(matches_pending) {
+            let (_, pending) = self.pending.take().expect("checked above");
+            self.current = pending;
+            return true;
+        }
+        if bool::from(matches_current) {
+            self.pending = None;
+            return true;
+        }
+        false
+    }
+}
+
+fn rejected(rotation_id: u64, reason: &str) -> Message {
+    Message::RotatePasscodeRes {
+        rotation_id,
+        accepted: false,
+        reason: Some(String::from(reason)),
+    }
+}
@@ -0,0 +1,143 @@
+//! State and helpers for a single authenticated connection between an agent and the server.
+
+use std::time::Duration;
+
+use crate::error::Error;
+#[cfg(any(feature = "encryption", feature = "signing"))]
+use crate::message::PublicId;
+
+/// Which end of a connection we are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// The agent, which shares files and authenticates itself to the server
+    Agent,
+    /// The central server, which agents connect to
+    Server,
+}
+
+/// Length of the random salt each end of a connection contributes to the session keys.
+#[cfg(any(feature = "encryption", feature = "signing"))]
+pub(crate) const SESSION_SALT_LEN: usize = 16;
+
+/// Generate the salt for our end of a new connection.
+#[cfg(any(feature = "encryption", feature = "signing"))]
+pub(crate) fn session_salt() -> [u8; SESSION_SALT_LEN] {
+    let mut salt = [0; SESSION_SALT_LEN];
+    getrandom::getrandom(&mut salt).expect("the operating system failed to generate randomness");
+    salt
+}
+
+/// Whether an envelope of type `ty` carries its sender's session salt: the server's `AuthReq`,
+/// and the agent's reply to it.
+#[cfg(any(feature = "encryption", feature = "signing"))]
+pub(crate) fn carries_salt(ty: i32) -> bool {
+    use crate::message::websocket_message::protobuf_types::fsp_comm::Type;
+
+    ty == Type::Authreq as i32 || ty == Type::Auth as i32 || ty == Type::TokenAuth as i32
+}
+
+/// Derive a 32 byte key for one direction of a connection from the agent's passcode.
+///
+/// `salt` ties the key to its purpose, so that the encryption and signing keys differ.
+/// `session_salt` is chosen by the receiving end for each connection, so that a key from an
+/// earlier connection is never used again.
+#[cfg(any(feature = "encryption", feature = "signing"))]
+pub(crate) fn derive_key(
+    salt: &[u8],
+    role: Role,
+    public_id: PublicId,
+    passcode: &[u8],
+    session_salt: &[u8],
+) -> [u8; 32] {
+    use hkdf::Hkdf;
+    use sha2::Sha256;
+
+    let direction: &[u8] = match role {
+        Role::Agent => b"agent to server",
+        Role::Server => b"server to agent",
+    };
+    let mut info = Vec::with_capacity(direction.len() + 8 + session_salt.len());
+    info.extend_from_slice(direction);
+    info.extend_from_slice(&public_id.to_be_bytes());
+    info.extend_from_slice(session_salt);
+
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(salt), passcode)
+        .expand(&info, &mut key)
+        .expect("Should never fail, as 32 bytes is a valid output length for sha256");
+    key
+}
+
+/// The current time, in milliseconds since epoch.
+pub(crate) fn now_millis() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Number of sequence numbers behind the highest seen that a `ReplayWindow` remembers.
+const WINDOW_SIZE: u64 = 64;
+
+/// Tracks the sequence numbers and timestamps of the signed messages received on a session,
+/// rejecting any message that has already been received or that is too old.
+///
+/// Sequence numbers may arrive slightly out of order, but any more than 64 behind the highest
+/// seen so far are rejected. A window only covers a single connection, so messages replayed
+/// from an earlier connection must be rejected another way, as `SessionSigner` does by keying
+/// each connection differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayWindow {
+    max_age: Duration,
+    highest: u64,
+    /// Bit `n` is set if the sequence number `highest - n` has been seen
+    seen: u64,
+}
+
+impl ReplayWindow {
+    /// Create a window which rejects messages sent more than `max_age` ago, or more than
+    /// `max_age` in the future to allow for clock skew.
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            highest: 0,
+            seen: 0,
+        }
+    }
+
+    /// Check a received message with the provided `sequence` number and `timestamp`, recording
+    /// it as seen if it is accepted. Timestamps are in milliseconds since epoch.
+    ///
+    /// This should only be called once the message's signature has been verified.
+    pub fn check(&mut self, sequence: u64, timestamp: u64, now: u64) -> Result<(), Error> {
+        if u128::from(now.abs_diff(timestamp)) > self.max_age.as_millis() {
+            return Err(Error::StaleMessage { timestamp, now });
+        }
+        if sequence == 0 {
+            return Err(Error::InvalidSignature(String::from(
+                "sequence numbers start at 1",
+            )));
+        }
+
+        if sequence > self.highest {
+            let shift = sequence - self.highest;
+            self.seen = if shift >= WINDOW_SIZE {
+                0
+            } else {
+                self.seen << shift
+            };
+            self.seen |= 1;
+            self.highest = sequence;
+            return Ok(());
+        }
+
+        let offset = self.highest - sequence;
+        if offset >= WINDOW_SIZE || self.seen & (1 << offset) != 0 {
+            return Err(Error::DuplicateMessage { sequence });
+        }
+        self.seen |= 1 << offset;
+        Ok(())
+    }
+}
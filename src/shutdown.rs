@@ -0,0 +1,200 @@
+//! Draining connections when the server shuts down, so agents are told when and where to
+//! reconnect rather than seeing their connection dropped.
+//!
+//! Once `Shutdown::start` is called, each connection stops accepting new requests, waits for
+//! the requests it is already handling to finish, sends the agent a `Message::Goodbye`, and
+//! closes with the `transport::GOING_AWAY` close code.
+//!
+//! # Example
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use futures_util::StreamExt;
+//! use ws_com_framework::shutdown::Shutdown;
+//! use ws_com_framework::Message;
+//!
+//! # #[cfg(not(feature = "loopback"))]
+//! # fn main() {}
+//! # #[cfg(feature = "loopback")]
+//! # #[tokio::main]
+//! # async fn main() {
+//! # use ws_com_framework::transport::loopback::duplex;
+//! # use ws_com_framework::transport::GOING_AWAY;
+//! let shutdown = Shutdown::new();
+//! let (mut server, mut agent) = duplex();
+//!
+//! // in the task handling the connection
+//! let drain = shutdown.connection();
+//! let request = drain.begin_request().unwrap();
+//!
+//! shutdown.start();
+//! assert!(drain.begin_request().is_none());
+//!
+//! // the request in progress finishes
+//! drop(request);
+//!
+//! let goodbye = Message::Goodbye {
+//!     reason: Some(String::from("redeploying")),
+//!     reconnect_after: Some(5),
+//!     redirect_url: None,
+//! };
+//! drain
+//!     .finish(&mut server, goodbye.clone(), Duration::from_secs(30))
+//!     .await
+//!     .unwrap();
+//! assert_eq!(agent.next().await.unwrap().unwrap(), goodbye);
+//! assert!(agent.next().await.is_none());
+//! assert_eq!(agent.close_frame(), Some((GOING_AWAY, "redeploying")));
+//! # }
+//! ```
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::{Sink, SinkExt};
+use tokio::sync::{watch, Notify};
+
+use crate::error::Error;
+use crate::message::Message;
+use crate::transport::{CloseWith, GOING_AWAY};
+
+/// Tells every connection of a server when to drain.
+///
+/// Cloning gives another handle to the same signal.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    draining: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            draining: Arc::new(watch::channel(false).0),
+        }
+    }
+}
+
+impl Shutdown {
+    /// Create a signal which has not yet been started.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start draining every connection.
+    pub fn start(&self) {
+        self.draining.send_replace(true);
+    }
+
+    /// Whether draining has started.
+    pub fn is_draining(&self) -> bool {
+        *self.draining.borrow()
+    }
+
+    /// Wait until draining starts.
+    pub async fn draining(&self) {
+        let mut rx = self.draining.subscribe();
+        // the sender is held by `self`, so this cannot fail
+        let _ = rx.wait_for(|draining| *draining).await;
+    }
+
+    /// Track the requests being handled on a new connection.
+    pub fn connection(&self) -> ConnectionDrain {
+        ConnectionDrain {
+            shutdown: self.clone(),
+            in_flight: Arc::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct InFlight {
+    count: AtomicUsize,
+    /// Woken when the count drops to zero
+    idle: Notify,
+}
+
+/// The requests being handled on a single connection, held by the task handling it.
+#[derive(Debug)]
+pub struct ConnectionDrain {
+    shutdown: Shutdown,
+    in_flight: Arc<InFlight>,
+}
+
+impl ConnectionDrain {
+    /// Record that a request from the agent is being handled, until the returned guard is
+    /// dropped.
+    ///
+    /// Returns `None` once draining has started, in which case the request should be refused.
+    pub fn begin_request(&self) -> Option<RequestGuard> {
+        if self.shutdown.is_draining() {
+            return None;
+        }
+        self.in_flight.count.fetch_add(1, Ordering::SeqCst);
+        Some(RequestGuard {
+            in_flight: self.in_flight.clone(),
+        })
+    }
+
+    /// The number of requests being handled.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.count.load(Ordering::SeqCst)
+    }
+
+    /// Wait until draining starts.
+    pub async fn draining(&self) {
+        self.shutdown.draining().await
+    }
+
+    /// Wait until no requests are being handled.
+    pub async fn idle(&self) {
+        loop {
+            let idle = self.in_flight.idle.notified();
+            if self.in_flight() == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+
+    /// Wait up to `timeout` for the requests being handled to finish, send `goodbye`, and close
+    /// the connection with `transport::GOING_AWAY`.
+    ///
+    /// The close frame carries the reason from `goodbye`, if it has one. The connection is closed
+    /// even if `goodbye` could not be sent, in which case that error is returned.
+    pub async fn finish<T>(
+        &self,
+        transport: &mut T,
+        goodbye: Message,
+        timeout: Duration,
+    ) -> Result<(), Error>
+    where
+        T: Sink<Message, Error = Error> + CloseWith + Unpin,
+    {
+        let _ = tokio::time::timeout(timeout, self.idle()).await;
+        let reason = match &goodbye {
+            Message::Goodbye {
+                reason: Some(reason),
+                ..
+            } => reason.clone(),
+            _ => String::from("the server is shutting down"),
+        };
+        let sent = transport.send(goodbye).await;
+        let closed = transport.close_with(GOING_AWAY, &reason).await;
+        sent.and(closed)
+    }
+}
+
+/// Marks a request as being handled, until it is dropped.
+#[derive(Debug)]
+pub struct RequestGuard {
+    in_flight: Arc<InFlight>,
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        if self.in_flight.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.in_flight.idle.notify_waiters();
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Signing of messages between an agent and the server, with replay protection.
+//!
+//! Without this, anyone able to capture a frame, such as a `Message::UploadTo` or
+//! `Message::AuthRes`, could send it again later. A `SessionSigner` attaches a sequence number,
+//! timestamp and HMAC-SHA256 to each envelope, and rejects received envelopes that have been
+//! tampered with, have already been received, or are too old. Keys are derived from the
+//! agent's `Passcode` and salts picked by each end of the connection, in the same way as for
+//! the `SessionCipher`, so messages captured on an earlier connection fail to verify on a later
+//! one. The server's `Message::AuthReq`, which carries its salt, is the only message sent
+//! unsigned.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::error::Error;
+use crate::message::websocket_message::protobuf_types::fsp_comm::{Signature, Type};
+use crate::message::websocket_message::protobuf_types::FspComm;
+use crate::message::PublicId;
+use crate::session::{
+    carries_salt, derive_key, now_millis, session_salt, ReplayWindow, Role, SESSION_SALT_LEN,
+};
+
+/// Salt used when deriving keys, which ties them to this protocol.
+const KDF_SALT: &[u8] = b"ws-com-framework session signer v1";
+
+/// How far a received message's timestamp may be from our own clock by default.
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(30);
+
+/// Signs outgoing and verifies incoming messages for one end of a connection.
+///
+/// Each direction uses its own key, so a message can never be reflected back to its sender.
+/// A new signer should be created for every connection.
+pub struct SessionSigner {
+    role: Role,
+    public_id: PublicId,
+    passcode: Vec<u8>,
+    /// Our salt for this connection, which the peer's key is derived from
+    salt: [u8; SESSION_SALT_LEN],
+    verify_key: Hmac<Sha256>,
+    /// Derived from the peer's salt, once it has been received
+    sign_key: RwLock<Option<Hmac<Sha256>>>,
+    next_sequence: AtomicU64,
+    window: Mutex<ReplayWindow>,
+}
+
+impl std::fmt::Debug for SessionSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionSigner")
+            .field("next_sequence", &self.next_sequence)
+            .field("window", &self.window)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SessionSigner {
+    /// Derive the signer for `role`'s end of a connection with the agent `public_id`, which
+    /// authenticates with `passcode`.
+    ///
+    /// Received messages are rejected if their timestamp is more than 30 seconds from our own
+    /// clock, see `with_max_age`. Messages can be received straight away, but only the
+    /// `Message::AuthReq` can be sent until the peer's salt has been received in the handshake.
+    pub fn new(role: Role, public_id: PublicId, passcode: &[u8]) -> Self {
+        let salt = session_salt();
+        let peer = match role {
+            Role::Agent => Role::Server,
+            Role::Server => Role::Agent,
+        };
+        let verify = derive_key(KDF_SALT, peer, public_id, passcode, &salt);
+        Self {
+            role,
+            public_id,
+            passcode: passcode.to_vec(),
+            salt,
+            verify_key: Hmac::new_from_slice(&verify).expect("HMAC can take a key of any size"),
+            sign_key: RwLock::new(None),
+            next_sequence: AtomicU64::new(1),
+            window: Mutex::new(ReplayWindow::new(DEFAULT_MAX_AGE)),
+        }
+    }
+
+    /// Reject received messages whose timestamp is more than `max_age` from our own clock.
+    pub fn with_max_age(self, max_age: Duration) -> Self {
+        Self {
+            window: Mutex::new(ReplayWindow::new(max_age)),
+            ..self
+        }
+    }
+
+    /// Sign an outgoing envelope, or attach our salt to an `AuthReq`.
+    pub(crate) fn sign(&self, mut envelope: FspComm) -> Result<FspComm, Error> {
+        if carries_salt(envelope.r#type) {
+            envelope.signing_salt = self.salt.to_vec().into();
+        }
+        // sent before the peer's salt is known, so there is no key to sign it with
+        if envelope.r#type == Type::Authreq as i32 {
+            return Ok(envelope);
+        }
+
+        let sign_key = self.sign_key.read().expect("session signer lock poisoned");
+        let sign_key = sign_key.as_ref().ok_or_else(|| {
+            Error::InvalidSignature(String::from(
+                "cannot sign a message before the peer's salt is received in the handshake",
+            ))
+        })?;
+        let sequence = self.next_sequence.fetch_add(1, Ordering::Relaxed);
+        let timestamp = now_millis();
+        let mac = mac(sign_key, &envelope, sequence, timestamp)
+            .finalize()
+            .into_bytes();
+        envelope.signature = Some(Signature {
+            sequence,
+            timestamp,
+            mac: mac.to_vec().into(),
+        });
+        Ok(envelope)
+    }
+
+    /// Verify a received envelope, and check that it is not a replay.
+    pub(crate) fn verify(&self, mut envelope: FspComm) -> Result<FspComm, Error> {
+        if envelope.r#type == Type::Authreq as i32 && envelope.signature.is_none() {
+            self.receive_salt(&envelope.signing_salt)?;
+            return Ok(envelope);
+        }
+        let signature = envelope.signature.take().ok_or_else(|| {
+            Error::InvalidSignature(String::from(
+                "received an unsigned message on a signed session",
+            ))
+        })?;
+        mac(
+            &self.verify_key,
+            &envelope,
+            signature.sequence,
+            signature.timestamp,
+        )
+        .verify_slice(&signature.mac)
+        .map_err(|_| {
+            Error::InvalidSignature(String::from(
+                "signature does not match, the message may have been tampered with",
+            ))
+        })?;
+
+        self.window
+            .lock()
+            .expect("replay window lock poisoned")
+            .check(signature.sequence, signature.timestamp, now_millis())?;
+        if carries_salt(envelope.r#type) {
+            self.receive_salt(&envelope.signing_salt)?;
+        }
+        Ok(envelope)
+    }
+
+    /// Derive the key for the messages we send from the peer's salt.
+    fn receive_salt(&self, salt: &[u8]) -> Result<(), Error> {
+        if salt.len() != SESSION_SALT_LEN {
+            return Err(Error::InvalidSignature(format!(
+                "invalid session salt length {}",
+                salt.len()
+            )));
+        }
+        let key = derive_key(KDF_SALT, self.role, self.public_id, &self.passcode, salt);
+        *self.sign_key.write().expect("session signer lock poisoned") =
+            Some(Hmac::new_from_slice(&key).expect("HMAC can take a key of any size"));
+        Ok(())
+    }
+}
+
+/// Compute the MAC of an envelope, covering every field except the signature itself.
+fn mac(key: &Hmac<Sha256>, envelope: &FspComm, sequence: u64, timestamp: u64) -> Hmac<Sha256> {
+    let mut mac = key.clone();
+    mac.update(&sequence.to_be_bytes());
+    mac.update(&timestamp.to_be_bytes());
+    mac.update(&envelope.r#type.to_be_bytes());
+    mac.update(&envelope.compression.to_be_bytes());
+    mac.update(&envelope.stream_id.to_be_bytes());
+    for field in [
+        &envelope.nonce,
+        &envelope.encryption_salt,
+        &envelope.signing_salt,
+    ] {
+        mac.update(&(field.len() as u64).to_be_bytes());
+        mac.update(field);
+    }
+    mac.update(&envelope.value);
+    mac
+}
@@ -0,0 +1,108 @@
+//! Collecting the status an agent reports in a `Message::StatusRes`.
+//!
+//! `ProcessStatus` is created when the agent starts and fills in the fields which describe the
+//! process itself: its uptime, version and operating system. The agent adds what only it knows,
+//! such as its share metrics and free disk space, to the `StatusReport` it returns.
+//!
+//! # Example
+//! ```rust
+//! use ws_com_framework::status::{ProcessStatus, StatusReport};
+//! use ws_com_framework::Message;
+//!
+//! // when the agent starts
+//! let process = ProcessStatus::new("1.2.0");
+//!
+//! // in answer to a `StatusReq`
+//! let res = StatusReport {
+//!     active_shares: Some(3),
+//!     bytes_shared: Some(1_500_000),
+//!     ..process.report()
+//! }
+//! .into_res(43, 1);
+//! assert!(matches!(
+//!     res,
+//!     Message::StatusRes { ready: true, active_shares: Some(3), os: Some(_), .. }
+//! ));
+//! ```
+
+use std::time::{Duration, Instant};
+
+use crate::message::{Message, PublicId, UploadId};
+
+/// The fields of a `Message::StatusRes` which describe the agent, rather than the request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatusReport {
+    /// Whether the agent is ready to accept connections
+    pub ready: bool,
+    /// Uptime of the agent in seconds
+    pub uptime: u64,
+    /// A human readable status message
+    pub message: Option<String>,
+    /// The number of files the agent is sharing
+    pub active_shares: Option<u32>,
+    /// The total size of the files the agent is sharing, in bytes
+    pub bytes_shared: Option<u64>,
+    /// The number of uploads the agent has in progress
+    pub uploads_in_progress: Option<u32>,
+    /// Free space on the disk holding the shared files, in bytes
+    pub free_disk: Option<u64>,
+    /// The version of the agent's software
+    pub agent_version: Option<String>,
+    /// The operating system the agent runs on
+    pub os: Option<String>,
+}
+
+impl StatusReport {
+    /// Build the `Message::StatusRes` reporting this status, in reply to the `StatusReq` with
+    /// `upload_id`.
+    pub fn into_res(self, public_id: PublicId, upload_id: UploadId) -> Message {
+        Message::StatusRes {
+            public_id,
+            ready: self.ready,
+            uptime: self.uptime,
+            upload_id,
+            message: self.message,
+            active_shares: self.active_shares,
+            bytes_shared: self.bytes_shared,
+            uploads_in_progress: self.uploads_in_progress,
+            free_disk: self.free_disk,
+            agent_version: self.agent_version,
+            os: self.os,
+        }
+    }
+}
+
+/// Collects the process-level fields of an agent's status.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessStatus {
+    started: Instant,
+    agent_version: String,
+}
+
+impl ProcessStatus {
+    /// Start measuring uptime from now, for an agent running version `agent_version` of its
+    /// software.
+    pub fn new(agent_version: impl Into<String>) -> Self {
+        Self {
+            started: Instant::now(),
+            agent_version: agent_version.into(),
+        }
+    }
+
+    /// How long since the agent started.
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// A report of a ready agent with its uptime, version and operating system filled in, and
+    /// every other field left empty.
+    pub fn report(&self) -> StatusReport {
+        StatusReport {
+            ready: true,
+            uptime: self.uptime().as_secs(),
+            agent_version: Some(self.agent_version.clone()),
+            os: Some(String::from(std::env::consts::OS)),
+            ..Default::default()
+        }
+    }
+}
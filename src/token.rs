@@ -0,0 +1,242 @@
+//! Expiring tokens which let an agent authenticate without its `Passcode`.
+//!
+//! A `TokenIssuer` mints tokens for an agent, and verifies them when they are presented in a
+//! `Message::TokenAuth`. Tokens are signed with HMAC-SHA256 using a secret shared by every
+//! server instance, so a token minted by one instance, such as in a `Message::Redirect`, is
+//! accepted by any other.
+//!
+//! Tokens can be presented any number of times until they expire, so they should be short
+//! lived. Once an agent has authenticated, the server can hand it a session token in a
+//! `Message::AuthOk`, built with `TokenIssuer::auth_ok`, which the agent presents when it
+//! reconnects instead of its passcode. `TokenIssuer::expire` forces an agent to authenticate
+//! with its passcode again, by rejecting every token issued to it so far. An issuer remembers
+//! the agents it has expired for as long as their earlier tokens could still be valid, which is
+//! bounded by `TokenIssuer::with_max_ttl`.
+//!
+//! # Example
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use ws_com_framework::token::TokenIssuer;
+//! use ws_com_framework::Message;
+//!
+//! let issuer = TokenIssuer::new(b"secret shared by every server instance");
+//!
+//! // on the server the agent is currently connected to
+//! let redirect = issuer.redirect(43, "wss://other.example.com/ws", Duration::from_secs(60));
+//!
+//! // on the other server, once the agent reconnects and answers its `AuthReq`
+//! if let Message::Redirect { token, .. } = redirect {
+//!     issuer.verify(43, &token).unwrap();
+//!     assert!(issuer.verify(44, &token).is_err());
+//! }
+//!
+//! // once the agent has authenticated, hand it a session token
+//! if let Message::AuthOk { session_token, .. } = issuer.auth_ok(43, Duration::from_secs(3600)) {
+//!     issuer.verify(43, &session_token).unwrap();
+//!
+//!     // force the agent to use its passcode next time
+//!     issuer.expire(43);
+//!     assert!(issuer.verify(43, &session_token).is_err());
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::error::Error;
+use crate::message::{Message, PublicId};
+use crate::session::now_millis;
+
+/// Identifies the layout of a token, so it can change in future.
+const TOKEN_VERSION: u8 = 1;
+
+/// The version, `PublicId`, issue stamp and expiry, which are covered by the MAC.
+const CLAIMS_LEN: usize = 1 + 8 + 8 + 8;
+
+/// The length of a token, including its MAC.
+const TOKEN_LEN: usize = CLAIMS_LEN + 32;
+
+/// Mints and verifies tokens for agents.
+///
+/// Cloning gives another handle to the same issuer, sharing the agents expired with `expire`.
+#[derive(Clone)]
+pub struct TokenIssuer {
+    key: Hmac<Sha256>,
+    /// The last stamp handed out by `stamp`
+    clock: Arc<AtomicU64>,
+    /// Tokens issued to each agent with a stamp at or before this one are rejected
+    expired: Arc<Mutex<HashMap<PublicId, u64>>>,
+    /// The longest a token may be valid for, in milliseconds
+    max_ttl: u64,
+}
+
+impl std::fmt::Debug for TokenIssuer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenIssuer").finish_non_exhaustive()
+    }
+}
+
+impl TokenIssuer {
+    /// Create an issuer which signs tokens with `secret`, which must be the same on every
+    /// server instance that should accept them.
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            key: Hmac::new_from_slice(secret).expect("HMAC can take a key of any size"),
+            clock: Arc::default(),
+            expired: Arc::default(),
+            max_ttl: u64::MAX,
+        }
+    }
+
+    /// Reject tokens which are valid for longer than `max_ttl`, and cap the `ttl` of tokens
+    /// minted by this issuer to it, instead of allowing tokens of any lifetime.
+    ///
+    /// This lets the issuer forget an agent expired with `expire` once `max_ttl` has passed, as
+    /// every token issued to it before then has expired. Without a limit it is never forgotten.
+    pub fn with_max_ttl(mut self, max_ttl: Duration) -> Self {
+        self.max_ttl = max_ttl.as_millis().try_into().unwrap_or(u64::MAX);
+        self
+    }
+
+    /// Mint a token for the agent `public_id`, which is valid for `ttl`.
+    pub fn mint(&self, public_id: PublicId, ttl: Duration) -> Vec<u8> {
+        self.mint_until(public_id, self.expires_at(ttl))
+    }
+
+    /// Mint a token for the agent `public_id`, which is valid until `expires_at`, in
+    /// milliseconds since epoch.
+    pub fn mint_until(&self, public_id: PublicId, expires_at: u64) -> Vec<u8> {
+        let mut token = Vec::with_capacity(TOKEN_LEN);
+        token.push(TOKEN_VERSION);
+        token.extend_from_slice(&public_id.to_be_bytes());
+        token.extend_from_slice(&self.stamp().to_be_bytes());
+        token.extend_from_slice(&expires_at.to_be_bytes());
+        let mac = self.mac(&token).finalize().into_bytes();
+        token.extend_from_slice(&mac);
+        token
+    }
+
+    /// Build a `Message::Redirect` instructing the agent `public_id` to reconnect to `url`,
+    /// carrying a token which is valid for `ttl`.
+    pub fn redirect(&self, public_id: PublicId, url: impl Into<String>, ttl: Duration) -> Message {
+        Message::Redirect {
+            url: url.into(),
+            token: self.mint(public_id, ttl),
+        }
+    }
+
+    /// Build the `Message::AuthOk` sent to the agent `public_id` once it has authenticated,
+    /// carrying a session token which is valid for `ttl`.
+    pub fn auth_ok(&self, public_id: PublicId, ttl: Duration) -> Message {
+        let expires_at = self.expires_at(ttl);
+        Message::AuthOk {
+            session_token: self.mint_until(public_id, expires_at),
+            expires_at,
+        }
+    }
+
+    /// Reject every token issued to the agent `public_id` so far, so that it must authenticate
+    /// with its passcode.
+    ///
+    /// This only applies to tokens verified by this issuer and its clones, not other issuers
+    /// sharing the same secret.
+    pub fn expire(&self, public_id: PublicId) {
+        let stamp = self.stamp();
+        let now = now_millis();
+        let mut expired = self.expired.lock().expect("expired agents lock poisoned");
+        // every token issued before an entry older than `max_ttl` has itself expired
+        expired.retain(|_, &mut at| now < at.saturating_add(self.max_ttl));
+        expired.insert(public_id, stamp);
+    }
+
+    /// The number of agents whose earlier tokens are being rejected after `expire`.
+    pub fn expired_agents(&self) -> usize {
+        self.expired
+            .lock()
+            .expect("expired agents lock poisoned")
+            .len()
+    }
+
+    /// Check a token presented by the agent `public_id`, returning when it expires, in
+    /// milliseconds since epoch.
+    ///
+    /// Fails with `Error::InvalidToken` if the token was not minted by this issuer for this
+    /// agent, has expired, is valid for longer than the issuer's `max_ttl`, or was issued before
+    /// the agent was expired with `expire`.
+    pub fn verify(&self, public_id: PublicId, token: &[u8]) -> Result<u64, Error> {
+        if token.len() != TOKEN_LEN || token[0] != TOKEN_VERSION {
+            return Err(Error::InvalidToken(String::from("malformed token")));
+        }
+        let (claims, mac) = token.split_at(CLAIMS_LEN);
+        self.mac(claims)
+            .verify_slice(mac)
+            .map_err(|_| Error::InvalidToken(String::from("mac does not match")))?;
+
+        let owner = u64::from_be_bytes(claims[1..9].try_into().expect("8 bytes"));
+        let issued_at = u64::from_be_bytes(claims[9..17].try_into().expect("8 bytes"));
+        let expires_at = u64::from_be_bytes(claims[17..25].try_into().expect("8 bytes"));
+        if owner != public_id {
+            return Err(Error::InvalidToken(format!(
+                "issued to agent {}, not {}",
+                owner, public_id
+            )));
+        }
+        if now_millis() >= expires_at {
+            return Err(Error::InvalidToken(format!("expired at {}", expires_at)));
+        }
+        if expires_at.saturating_sub(issued_at) > self.max_ttl {
+            return Err(Error::InvalidToken(format!(
+                "valid for longer than {}ms",
+                self.max_ttl
+            )));
+        }
+        let expired = self
+            .expired
+            .lock()
+            .expect("expired agents lock poisoned")
+            .get(&owner)
+            .copied();
+        if matches!(expired, Some(expired) if issued_at <= expired) {
+            return Err(Error::InvalidToken(format!(
+                "issued before agent {} was expired",
+                owner
+            )));
+        }
+        Ok(expires_at)
+    }
+
+    /// When a token minted now for `ttl` expires, capped to the issuer's `max_ttl`.
+    fn expires_at(&self, ttl: Duration) -> u64 {
+        let ttl = ttl.as_millis().try_into().unwrap_or(u64::MAX);
+        now_millis().saturating_add(ttl.min(self.max_ttl))
+    }
+
+    /// A stamp for a token being issued or an agent being expired, which is strictly later than
+    /// every stamp handed out before it by this issuer and its clones.
+    ///
+    /// Stamps follow the wall clock in milliseconds, so that they compare sensibly with those
+    /// of other instances, but move on by one whenever the clock has not, so that a token
+    /// minted in the same millisecond as `expire` is still accepted.
+    fn stamp(&self) -> u64 {
+        let now = now_millis();
+        let last = self
+            .clock
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(now.max(last.saturating_add(1)))
+            })
+            .expect("the update always succeeds");
+        now.max(last.saturating_add(1))
+    }
+
+    fn mac(&self, claims: &[u8]) -> Hmac<Sha256> {
+        let mut mac = self.key.clone();
+        mac.update(claims);
+        mac
+    }
+}
@@ -0,0 +1,69 @@
+//! Adapters which carry `Message`s over a websocket implementation, so that a connection can
+//! be used as a `Stream` of received messages and a `Sink` of messages to send.
+
+#[cfg(feature = "axum")]
+pub mod axum;
+#[cfg(feature = "loopback")]
+pub mod loopback;
+#[cfg(feature = "tungstenite")]
+pub mod tungstenite;
+
+use std::future::Future;
+
+use crate::error::Error;
+
+/// A connection which can be closed with a websocket close code, implemented by every transport
+/// so that helpers such as `shutdown::ConnectionDrain::finish` can close any of them.
+pub trait CloseWith {
+    /// Close the connection with a close code, such as `GOING_AWAY`, and a reason.
+    fn close_with(&mut self, code: u16, reason: &str) -> impl Future<Output = Result<(), Error>>;
+}
+
+/// The websocket close code to send when closing a connection because of `err`.
+///
+/// Malformed messages map to `1007` (invalid payload), oversized messages to `1009` (message too
+/// big), messages failing decryption or signature checks, invalid tokens, rejected enrolments and
+/// duplicate logins to `1008` (policy violation), frames which are not messages, misused streams
+/// and malformed diagnostics reports to `1002` (protocol error), a peer too slow to keep up with
+/// its outbound queue to `1013` (try again later), and other failures to `1011` (internal error).
+pub fn close_code(err: &Error) -> u16 {
+    match err {
+        Error::ByteDecodeError(_) | Error::CompressionError(_) | Error::BatchEntryError { .. } => {
+            1007
+        }
+        Error::FrameTooLarge { .. } | Error::FieldTooLarge { .. } => 1009,
+        Error::EncryptionError(_)
+        | Error::InvalidSignature(_)
+        | Error::DuplicateMessage { .. }
+        | Error::StaleMessage { .. }
+        | Error::InvalidToken(_)
+        | Error::EnrolmentFailed(_)
+        | Error::AlreadyConnected { .. } => 1008,
+        Error::TransportError(_) | Error::StreamError { .. } | Error::DiagnosticsError { .. } => {
+            1002
+        }
+        Error::QueueFull { .. } => 1013,
+        Error::ByteEncodeError(_) | Error::NotConnected { .. } | Error::Timeout { .. } => 1011,
+    }
+}
+
+/// The websocket close code to send once a connection has been drained and sent a
+/// `Message::Goodbye`, as the server is going away.
+pub const GOING_AWAY: u16 = 1001;
+
+/// A reason to send in a close frame, truncated to fit.
+#[cfg(any(feature = "axum", feature = "tungstenite"))]
+pub(crate) fn close_reason(reason: &str) -> String {
+    // a close frame payload is at most 125 bytes, two of which hold the code
+    const MAX_REASON_LEN: usize = 123;
+
+    let mut reason = reason.to_owned();
+    if reason.len() > MAX_REASON_LEN {
+        let mut end = MAX_REASON_LEN;
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        reason.truncate(end);
+    }
+    reason
+}
@@ -0,0 +1,256 @@
+//! Websocket upgrade helper for `axum` handlers.
+//!
+//! # Example
+//! ```rust,no_run
+//! use axum::{response::Response, routing::get, Router};
+//! use futures_util::{SinkExt, StreamExt};
+//! use ws_com_framework::transport::axum::{AxumTransport, MessageUpgrade};
+//! use ws_com_framework::Message;
+//!
+//! async fn handler(upgrade: MessageUpgrade) -> Response {
+//!     upgrade.on_upgrade(handle_socket)
+//! }
+//!
+//! async fn handle_socket(mut transport: AxumTransport) {
+//!     while let Some(msg) = transport.next().await {
+//!         match msg {
+//!             Ok(Message::AuthReq { public_id }) => {
+//!                 let res = Message::AuthRes {
+//!                     public_id,
+//!                     passcode: b"passcode".to_vec(),
+//!                 };
+//!                 if transport.send(res).await.is_err() {
+//!                     return;
+//!                 }
+//!             }
+//!             Ok(_) => {}
+//!             Err(e) => {
+//!                 let _ = transport.close_with_error(&e).await;
+//!                 return;
+//!             }
+//!         }
+//!     }
+//! }
+//!
+//! let app: Router = Router::new().route("/ws", get(handler));
+//! ```
+
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use ::axum::extract::ws::rejection::WebSocketUpgradeRejection;
+use ::axum::extract::ws::{CloseFrame, Message as WsMessage, WebSocket, WebSocketUpgrade};
+use ::axum::extract::FromRequestParts;
+use ::axum::http::request::Parts;
+use ::axum::response::Response;
+use bytes::Bytes;
+use futures_util::{Sink, Stream};
+
+use crate::codec::Codec;
+use crate::error::Error;
+use crate::message::Message;
+use crate::mux::StreamId;
+use crate::transport::{close_code, close_reason, CloseWith};
+
+/// Extractor which upgrades the request to a websocket carrying `Message`s.
+///
+/// Use in place of `axum::extract::ws::WebSocketUpgrade`, then call `on_upgrade` with a callback
+/// receiving an `AxumTransport`.
+#[derive(Debug)]
+pub struct MessageUpgrade {
+    upgrade: WebSocketUpgrade,
+    codec: Codec,
+}
+
+impl MessageUpgrade {
+    /// Wrap an upgrade, using the default `Codec`.
+    pub fn new(upgrade: WebSocketUpgrade) -> Self {
+        Self {
+            upgrade,
+            codec: Codec::default(),
+        }
+    }
+
+    /// Encode and decode messages on the upgraded connection with the provided `Codec`.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Configure the underlying `WebSocketUpgrade`, such as its protocols or buffer sizes.
+    pub fn map_upgrade<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(WebSocketUpgrade) -> WebSocketUpgrade,
+    {
+        self.upgrade = f(self.upgrade);
+        self
+    }
+
+    /// Finalize the upgrade, calling `callback` with the connection once it is established.
+    ///
+    /// The returned response must be returned from the handler for the upgrade to happen.
+    #[must_use = "to set up the WebSocket connection, this response must be returned"]
+    pub fn on_upgrade<C, Fut>(self, callback: C) -> Response
+    where
+        C: FnOnce(AxumTransport) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let codec = self.codec;
+        self.upgrade
+            .on_upgrade(move |ws| callback(AxumTransport::with_codec(ws, codec)))
+    }
+}
+
+impl<S> FromRequestParts<S> for MessageUpgrade
+where
+    S: Send + Sync,
+{
+    type Rejection = WebSocketUpgradeRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        WebSocketUpgrade::from_request_parts(parts, state)
+            .await
+            .map(Self::new)
+    }
+}
+
+/// An upgraded axum `WebSocket` which sends and receives `Message`s.
+///
+/// Messages are sent as binary frames. Ping and pong frames are skipped, a close frame ends the
+/// stream, and a text frame is reported as an `Error::TransportError` without ending the stream.
+#[derive(Debug)]
+pub struct AxumTransport {
+    ws: WebSocket,
+    codec: Codec,
+}
+
+impl AxumTransport {
+    /// Wrap a websocket, using the default `Codec`.
+    pub fn new(ws: WebSocket) -> Self {
+        Self::with_codec(ws, Codec::default())
+    }
+
+    /// Wrap a websocket, encoding and decoding messages with the provided `Codec`.
+    pub fn with_codec(ws: WebSocket, codec: Codec) -> Self {
+        Self { ws, codec }
+    }
+
+    /// The codec used to encode and decode messages.
+    pub fn codec(&self) -> &Codec {
+        &self.codec
+    }
+
+    /// The underlying websocket.
+    pub fn get_ref(&self) -> &WebSocket {
+        &self.ws
+    }
+
+    /// Unwrap the underlying websocket.
+    pub fn into_inner(self) -> WebSocket {
+        self.ws
+    }
+
+    /// Close the connection because of `err`, sending the matching `close_code` and the error as
+    /// the reason.
+    pub async fn close_with_error(&mut self, err: &Error) -> Result<(), Error> {
+        self.close_with(close_code(err), &err.to_string()).await
+    }
+
+    /// Close the connection with a close code, such as `transport::GOING_AWAY`, and a reason.
+    pub async fn close_with(&mut self, code: u16, reason: &str) -> Result<(), Error> {
+        let frame = CloseFrame {
+            code,
+            reason: close_reason(reason).into(),
+        };
+        self.ws
+            .send(WsMessage::Close(Some(frame)))
+            .await
+            .map_err(transport_error)
+    }
+
+    /// Send a message on a stream of a `mux::Multiplexer`.
+    pub async fn send_on(&mut self, stream_id: StreamId, msg: &Message) -> Result<(), Error> {
+        let bytes = self.codec.encode_on(stream_id, msg)?;
+        self.ws
+            .send(WsMessage::Binary(bytes.into()))
+            .await
+            .map_err(transport_error)
+    }
+
+    /// Receive the next message along with the stream it was sent on, for a
+    /// `mux::Multiplexer`. Returns `None` once the connection is closed.
+    pub async fn next_on(&mut self) -> Option<Result<(StreamId, Message), Error>> {
+        let frame = poll_fn(|cx| self.poll_frame(cx)).await?;
+        Some(frame.and_then(|frame| self.codec.decode_on(frame)))
+    }
+
+    /// The next binary frame, skipping ping and pong frames.
+    fn poll_frame(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Error>>> {
+        loop {
+            let frame = match ready!(Pin::new(&mut self.ws).poll_next(cx)) {
+                Some(Ok(frame)) => frame,
+                Some(Err(e)) => return Poll::Ready(Some(Err(transport_error(e)))),
+                None => return Poll::Ready(None),
+            };
+            match frame {
+                WsMessage::Binary(bytes) => return Poll::Ready(Some(Ok(bytes))),
+                WsMessage::Text(_) => {
+                    return Poll::Ready(Some(Err(Error::TransportError(String::from(
+                        "received a text frame, but messages are sent as binary",
+                    )))))
+                }
+                WsMessage::Close(_) => return Poll::Ready(None),
+                WsMessage::Ping(_) | WsMessage::Pong(_) => continue,
+            }
+        }
+    }
+}
+
+impl CloseWith for AxumTransport {
+    fn close_with(&mut self, code: u16, reason: &str) -> impl Future<Output = Result<(), Error>> {
+        AxumTransport::close_with(self, code, reason)
+    }
+}
+
+impl Stream for AxumTransport {
+    type Item = Result<Message, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let frame = ready!(self.poll_frame(cx));
+        Poll::Ready(frame.map(|frame| frame.and_then(|frame| self.codec.decode(frame))))
+    }
+}
+
+impl Sink<Message> for AxumTransport {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.ws)
+            .poll_ready(cx)
+            .map_err(transport_error)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Error> {
+        let bytes = self.codec.encode(&item)?;
+        Pin::new(&mut self.ws)
+            .start_send(WsMessage::Binary(bytes.into()))
+            .map_err(transport_error)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.ws)
+            .poll_flush(cx)
+            .map_err(transport_error)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.ws)
+            .poll_close(cx)
+            .map_err(transport_error)
+    }
+}
+
+fn transport_error(err: ::axum::Error) -> Error {
+    Error::TransportError(err.to_string())
+}
@@ -0,0 +1,253 @@
+//! In-memory transport connecting two endpoints, for testing an agent against a server without
+//! opening sockets.
+//!
+//! Every message is encoded and decoded by each endpoint's `Codec`, exactly as it would be over
+//! a websocket, and `Faults` can delay, drop or corrupt the encoded frames in flight.
+//!
+//! # Example
+//! ```rust
+//! use futures_util::{SinkExt, StreamExt};
+//! use ws_com_framework::transport::loopback;
+//! use ws_com_framework::Message;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let (mut agent, mut server) = loopback::duplex();
+//!
+//! agent.send(Message::AuthReq { public_id: 43 }).await.unwrap();
+//! let msg = server.next().await.unwrap().unwrap();
+//! assert_eq!(msg, Message::AuthReq { public_id: 43 });
+//! # }
+//! ```
+
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_util::{Sink, Stream};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::{sleep_until, Instant, Sleep};
+
+use crate::codec::Codec;
+use crate::error::Error;
+use crate::message::Message;
+use crate::mux::StreamId;
+use crate::transport::CloseWith;
+
+type Frame = (Instant, Packet);
+
+/// What one endpoint sends the other.
+#[derive(Debug)]
+enum Packet {
+    Data(Bytes),
+    /// A close frame, with its close code and reason
+    Close(u16, String),
+}
+
+/// Decides whether a frame is dropped, see `Faults::drop`.
+pub type DropHook = Box<dyn FnMut(&[u8]) -> bool + Send>;
+
+/// Modifies a frame before it is delivered, see `Faults::corrupt`.
+pub type CorruptHook = Box<dyn FnMut(&mut Vec<u8>) + Send>;
+
+/// Faults applied to the frames an endpoint sends, after they are encoded.
+#[derive(Default)]
+pub struct Faults {
+    /// Every frame is delivered this long after it was sent, frames stay in order
+    pub latency: Duration,
+    /// Frames for which this returns `true` are silently dropped
+    pub drop: Option<DropHook>,
+    /// Called with every frame which is not dropped, and may modify its bytes
+    pub corrupt: Option<CorruptHook>,
+}
+
+impl std::fmt::Debug for Faults {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Faults")
+            .field("latency", &self.latency)
+            .field("drop", &self.drop.is_some())
+            .field("corrupt", &self.corrupt.is_some())
+            .finish()
+    }
+}
+
+/// One end of an in-memory connection created by `duplex`.
+///
+/// The stream ends once the other endpoint is closed or dropped, after any frames still in
+/// flight have been received. An endpoint closed with `CloseWith::close_with` sends its close
+/// code and reason, which the other endpoint reports with `close_frame`.
+#[derive(Debug)]
+pub struct LoopbackTransport {
+    tx: Option<UnboundedSender<Frame>>,
+    rx: UnboundedReceiver<Frame>,
+    pending: Option<(Pin<Box<Sleep>>, Packet)>,
+    /// The close code and reason received from the other endpoint
+    closed_with: Option<(u16, String)>,
+    codec: Codec,
+    faults: Faults,
+}
+
+/// Create two connected endpoints, each using the default `Codec` and no `Faults`.
+pub fn duplex() -> (LoopbackTransport, LoopbackTransport) {
+    let (a_tx, a_rx) = unbounded_channel();
+    let (b_tx, b_rx) = unbounded_channel();
+    (
+        LoopbackTransport::new(a_tx, b_rx),
+        LoopbackTransport::new(b_tx, a_rx),
+    )
+}
+
+impl LoopbackTransport {
+    fn new(tx: UnboundedSender<Frame>, rx: UnboundedReceiver<Frame>) -> Self {
+        Self {
+            tx: Some(tx),
+            rx,
+            pending: None,
+            closed_with: None,
+            codec: Codec::default(),
+            faults: Faults::default(),
+        }
+    }
+
+    /// Encode and decode messages on this endpoint with the provided `Codec`.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Apply `faults` to every frame this endpoint sends.
+    pub fn with_faults(mut self, faults: Faults) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    /// The codec used to encode and decode messages.
+    pub fn codec(&self) -> &Codec {
+        &self.codec
+    }
+
+    /// Send a message on a stream of a `mux::Multiplexer`, applying any faults.
+    pub fn send_on(&mut self, stream_id: StreamId, msg: &Message) -> Result<(), Error> {
+        let frame = self.codec.encode_on(stream_id, msg)?;
+        self.send_frame(frame)
+    }
+
+    /// Receive the next message along with the stream it was sent on, for a
+    /// `mux::Multiplexer`. Returns `None` once the other endpoint is closed.
+    pub async fn next_on(&mut self) -> Option<Result<(StreamId, Message), Error>> {
+        let frame = poll_fn(|cx| self.poll_frame(cx)).await?;
+        Some(self.codec.decode_on(frame))
+    }
+
+    /// Send a raw frame to the other endpoint, bypassing the codec and any faults.
+    pub fn send_raw(&self, frame: impl Into<Bytes>) -> Result<(), Error> {
+        self.deliver(Instant::now(), Packet::Data(frame.into()))
+    }
+
+    /// The close code and reason the other endpoint closed the connection with, once its close
+    /// frame has been received, or `None` if it was closed without one.
+    pub fn close_frame(&self) -> Option<(u16, &str)> {
+        self.closed_with
+            .as_ref()
+            .map(|(code, reason)| (*code, reason.as_str()))
+    }
+
+    fn send_frame(&mut self, mut frame: Vec<u8>) -> Result<(), Error> {
+        if let Some(drop) = self.faults.drop.as_mut() {
+            if drop(&frame) {
+                return Ok(());
+            }
+        }
+        if let Some(corrupt) = self.faults.corrupt.as_mut() {
+            corrupt(&mut frame);
+        }
+        let at = Instant::now() + self.faults.latency;
+        self.deliver(at, Packet::Data(frame.into()))
+    }
+
+    /// The next frame from the other endpoint, once its latency has passed, or `None` once it
+    /// has closed.
+    fn poll_frame(&mut self, cx: &mut Context<'_>) -> Poll<Option<Bytes>> {
+        if self.closed_with.is_some() {
+            return Poll::Ready(None);
+        }
+        if self.pending.is_none() {
+            let (at, packet) = match ready!(self.rx.poll_recv(cx)) {
+                Some(frame) => frame,
+                None => return Poll::Ready(None),
+            };
+            if at <= Instant::now() {
+                return Poll::Ready(self.receive(packet));
+            }
+            self.pending = Some((Box::pin(sleep_until(at)), packet));
+        }
+
+        let (sleep, _) = self.pending.as_mut().expect("a frame is pending");
+        ready!(sleep.as_mut().poll(cx));
+        let (_, packet) = self.pending.take().expect("a frame is pending");
+        Poll::Ready(self.receive(packet))
+    }
+
+    fn receive(&mut self, packet: Packet) -> Option<Bytes> {
+        match packet {
+            Packet::Data(frame) => Some(frame),
+            Packet::Close(code, reason) => {
+                self.closed_with = Some((code, reason));
+                None
+            }
+        }
+    }
+
+    fn deliver(&self, at: Instant, packet: Packet) -> Result<(), Error> {
+        self.tx
+            .as_ref()
+            .ok_or_else(|| Error::TransportError(String::from("the loopback is closed")))?
+            .send((at, packet))
+            .map_err(|_| {
+                Error::TransportError(String::from("the other end of the loopback was dropped"))
+            })
+    }
+}
+
+impl CloseWith for LoopbackTransport {
+    /// Send a close frame after any frames still in flight, and close this endpoint.
+    fn close_with(&mut self, code: u16, reason: &str) -> impl Future<Output = Result<(), Error>> {
+        let at = Instant::now() + self.faults.latency;
+        let sent = self.deliver(at, Packet::Close(code, reason.to_owned()));
+        self.tx = None;
+        std::future::ready(sent)
+    }
+}
+
+impl Stream for LoopbackTransport {
+    type Item = Result<Message, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let frame = ready!(self.poll_frame(cx));
+        Poll::Ready(frame.map(|frame| self.codec.decode(frame)))
+    }
+}
+
+impl Sink<Message> for LoopbackTransport {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Error> {
+        let frame = self.codec.encode(&item)?;
+        self.send_frame(frame)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.tx = None;
+        Poll::Ready(Ok(()))
+    }
+}
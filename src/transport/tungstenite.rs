@@ -0,0 +1,198 @@
+//! Adapter for `tokio_tungstenite::WebSocketStream`.
+//!
+//! # Example
+//! ```rust,no_run
+//! use futures_util::{SinkExt, StreamExt};
+//! use ws_com_framework::transport::tungstenite::WebSocketTransport;
+//! use ws_com_framework::Message;
+//!
+//! async fn example() -> Result<(), ws_com_framework::Error> {
+//!     let (ws, _) = tokio_tungstenite::connect_async("ws://localhost:8080/ws")
+//!         .await
+//!         .unwrap();
+//!     let mut transport = WebSocketTransport::new(ws);
+//!
+//!     while let Some(msg) = transport.next().await {
+//!         if let Message::AuthReq { public_id } = msg? {
+//!             transport
+//!                 .send(Message::AuthRes {
+//!                     public_id,
+//!                     passcode: b"passcode".to_vec(),
+//!                 })
+//!                 .await?;
+//!         }
+//!     }
+//!     Ok(())
+//! }
+//! ```
+
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use bytes::Bytes;
+use futures_util::{Sink, SinkExt, Stream};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
+use tokio_tungstenite::WebSocketStream;
+
+use crate::codec::Codec;
+use crate::error::Error;
+use crate::message::Message;
+use crate::mux::StreamId;
+use crate::transport::{close_code, close_reason, CloseWith};
+
+/// A `WebSocketStream` which sends and receives `Message`s.
+///
+/// Messages are sent as binary frames. Received ping and pong frames are handled by
+/// tungstenite and skipped, a close frame ends the stream, and a text frame is reported as an
+/// `Error::TransportError` without ending the stream.
+#[derive(Debug)]
+pub struct WebSocketTransport<S> {
+    ws: WebSocketStream<S>,
+    codec: Codec,
+}
+
+impl<S> WebSocketTransport<S> {
+    /// Wrap a websocket, using the default `Codec`.
+    pub fn new(ws: WebSocketStream<S>) -> Self {
+        Self::with_codec(ws, Codec::default())
+    }
+
+    /// Wrap a websocket, encoding and decoding messages with the provided `Codec`.
+    pub fn with_codec(ws: WebSocketStream<S>, codec: Codec) -> Self {
+        Self { ws, codec }
+    }
+
+    /// The codec used to encode and decode messages.
+    pub fn codec(&self) -> &Codec {
+        &self.codec
+    }
+
+    /// The underlying websocket.
+    pub fn get_ref(&self) -> &WebSocketStream<S> {
+        &self.ws
+    }
+
+    /// Unwrap the underlying websocket.
+    pub fn into_inner(self) -> WebSocketStream<S> {
+        self.ws
+    }
+}
+
+impl<S> WebSocketTransport<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Close the connection because of `err`, sending the matching `close_code` and the error as
+    /// the reason.
+    pub async fn close_with_error(&mut self, err: &Error) -> Result<(), Error> {
+        self.close_with(close_code(err), &err.to_string()).await
+    }
+
+    /// Close the connection with a close code, such as `transport::GOING_AWAY`, and a reason.
+    pub async fn close_with(&mut self, code: u16, reason: &str) -> Result<(), Error> {
+        let frame = CloseFrame {
+            code: code.into(),
+            reason: close_reason(reason).into(),
+        };
+        self.ws.close(Some(frame)).await.map_err(transport_error)
+    }
+
+    /// Send a message on a stream of a `mux::Multiplexer`.
+    pub async fn send_on(&mut self, stream_id: StreamId, msg: &Message) -> Result<(), Error> {
+        let bytes = self.codec.encode_on(stream_id, msg)?;
+        self.ws
+            .send(WsMessage::Binary(bytes.into()))
+            .await
+            .map_err(transport_error)
+    }
+
+    /// Receive the next message along with the stream it was sent on, for a
+    /// `mux::Multiplexer`. Returns `None` once the connection is closed.
+    pub async fn next_on(&mut self) -> Option<Result<(StreamId, Message), Error>> {
+        let frame = poll_fn(|cx| self.poll_frame(cx)).await?;
+        Some(frame.and_then(|frame| self.codec.decode_on(frame)))
+    }
+
+    /// The next binary frame, skipping control frames.
+    fn poll_frame(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Error>>> {
+        loop {
+            let frame = match ready!(Pin::new(&mut self.ws).poll_next(cx)) {
+                Some(Ok(frame)) => frame,
+                Some(Err(WsError::ConnectionClosed | WsError::AlreadyClosed)) | None => {
+                    return Poll::Ready(None)
+                }
+                Some(Err(e)) => return Poll::Ready(Some(Err(transport_error(e)))),
+            };
+            match frame {
+                WsMessage::Binary(bytes) => return Poll::Ready(Some(Ok(bytes))),
+                WsMessage::Text(_) => {
+                    return Poll::Ready(Some(Err(Error::TransportError(String::from(
+                        "received a text frame, but messages are sent as binary",
+                    )))))
+                }
+                WsMessage::Close(_) => return Poll::Ready(None),
+                WsMessage::Ping(_) | WsMessage::Pong(_) | WsMessage::Frame(_) => continue,
+            }
+        }
+    }
+}
+
+impl<S> CloseWith for WebSocketTransport<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn close_with(&mut self, code: u16, reason: &str) -> impl Future<Output = Result<(), Error>> {
+        WebSocketTransport::close_with(self, code, reason)
+    }
+}
+
+impl<S> Stream for WebSocketTransport<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    type Item = Result<Message, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let frame = ready!(self.poll_frame(cx));
+        Poll::Ready(frame.map(|frame| frame.and_then(|frame| self.codec.decode(frame))))
+    }
+}
+
+impl<S> Sink<Message> for WebSocketTransport<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.ws)
+            .poll_ready(cx)
+            .map_err(transport_error)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Error> {
+        let bytes = self.codec.encode(&item)?;
+        Pin::new(&mut self.ws)
+            .start_send(WsMessage::Binary(bytes.into()))
+            .map_err(transport_error)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.ws)
+            .poll_flush(cx)
+            .map_err(transport_error)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.ws)
+            .poll_close(cx)
+            .map_err(transport_error)
+    }
+}
+
+fn transport_error(err: WsError) -> Error {
+    Error::TransportError(err.to_string())
+}
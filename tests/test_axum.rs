@@ -0,0 +1,83 @@
+//! Test the axum upgrade helper against a tungstenite client.
+#![cfg(feature = "axum")]
+
+use axum::response::Response;
+use axum::routing::get;
+use axum::Router;
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use ws_com_framework::transport::axum::{AxumTransport, MessageUpgrade};
+use ws_com_framework::{Codec, Limits, Message};
+
+async fn echo(upgrade: MessageUpgrade) -> Response {
+    let codec = Codec {
+        decode_limits: Limits {
+            max_frame_size: 256,
+            ..Limits::default()
+        },
+        ..Codec::default()
+    };
+    upgrade.with_codec(codec).on_upgrade(echo_socket)
+}
+
+async fn echo_socket(mut transport: AxumTransport) {
+    while let Some(msg) = transport.next().await {
+        match msg {
+            Ok(msg) => transport.send(msg).await.unwrap(),
+            Err(e) => {
+                transport.close_with_error(&e).await.unwrap();
+                return;
+            }
+        }
+    }
+}
+
+async fn serve() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = Router::new().route("/ws", get(echo));
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("ws://{}/ws", addr)
+}
+
+async fn close_code_for(frame: WsMessage) -> u16 {
+    let (mut ws, _) = tokio_tungstenite::connect_async(serve().await)
+        .await
+        .unwrap();
+    ws.send(frame).await.unwrap();
+    match ws.next().await.unwrap().unwrap() {
+        WsMessage::Close(Some(frame)) => frame.code.into(),
+        other => panic!("expected a close frame, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_echo() {
+    let (mut ws, _) = tokio_tungstenite::connect_async(serve().await)
+        .await
+        .unwrap();
+
+    let msg = Message::AuthRes {
+        public_id: 43,
+        passcode: b"passcode".to_vec(),
+    };
+    ws.send(WsMessage::Binary(msg.to_bytes().into()))
+        .await
+        .unwrap();
+    match ws.next().await.unwrap().unwrap() {
+        WsMessage::Binary(bytes) => assert_eq!(Message::decode_bytes(bytes).unwrap(), msg),
+        other => panic!("expected a binary frame, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_close_codes() {
+    let invalid = WsMessage::Binary(vec![8, 99, 18, 1, 0].into());
+    assert_eq!(close_code_for(invalid).await, 1007);
+
+    let too_large = WsMessage::Binary(vec![0; 512].into());
+    assert_eq!(close_code_for(too_large).await, 1009);
+
+    let text = WsMessage::text("hello");
+    assert_eq!(close_code_for(text).await, 1002);
+}
@@ -14,7 +14,7 @@ fn test_bad_type_conversion() {
         .to_string()
         .contains("failed to decode bytes as valid message"));
 
-    let bytes: Vec<u8> = vec![8, 10, 18, 7, 10, 5, 104, 101, 108, 108, 111];
+    let bytes: Vec<u8> = vec![8, 99, 18, 7, 10, 5, 104, 101, 108, 108, 111];
     let msg2: Result<Message, Error> = Message::try_from(bytes);
 
     assert!(msg2.is_err());
@@ -25,3 +25,15 @@ fn test_bad_type_conversion() {
         .contains("failed to decode bytes as valid message"));
     assert!(msg2.to_string().contains("unrecognised i32 variant"));
 }
+
+#[test]
+fn test_config_entry_without_value() {
+    // a `ConfigUpdate` whose only entry has the key "a" and no value
+    let bytes: Vec<u8> = vec![8, 21, 18, 5, 18, 3, 10, 1, 97];
+    assert_eq!(
+        Message::try_from(bytes),
+        Err(Error::ByteDecodeError(String::from(
+            "config entry a has no value"
+        )))
+    );
+}
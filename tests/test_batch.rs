@@ -0,0 +1,123 @@
+//! Test sending several messages in a single `Message::Batch` frame.
+
+use bytes::{Bytes, BytesMut};
+use prost::Message as _;
+use ws_com_framework::message::websocket_message::protobuf_types::{fsp_comm, FspComm};
+use ws_com_framework::{Error, Limits, Message};
+
+fn metadata_reqs(n: u32) -> Vec<Message> {
+    (0..n)
+        .map(|i| Message::MetadataReq {
+            file_id: i,
+            upload_id: u64::from(i) * 7,
+            deadline: None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_batch_round_trip() {
+    let mut messages = metadata_reqs(200);
+    messages.insert(3, Message::Ok);
+    messages.push(Message::AuthRes {
+        public_id: 43,
+        passcode: vec![1; 32],
+    });
+
+    let bytes = Message::batch_to_bytes(&messages);
+    let decoded = Message::decode_batch(Bytes::from(bytes)).unwrap();
+    assert_eq!(decoded, messages);
+}
+
+#[test]
+fn test_batch_to_bytes_matches_variant() {
+    let messages = metadata_reqs(10);
+    let bytes = Message::batch_to_bytes(&messages);
+
+    let msg = Message::from(messages.clone());
+    assert_eq!(bytes, msg.to_bytes());
+    assert_eq!(bytes.len(), msg.encoded_len());
+    assert_eq!(bytes, FspComm::from(msg.clone()).encode_to_vec());
+
+    let mut buf = BytesMut::new();
+    msg.encode_into(&mut buf);
+    assert_eq!(&buf[..], &bytes[..]);
+    assert_eq!(Message::try_from(bytes).unwrap(), msg);
+}
+
+#[test]
+fn test_empty_batch() {
+    let bytes = Message::batch_to_bytes(&[]);
+    assert_eq!(Message::decode_batch(Bytes::from(bytes)).unwrap(), vec![]);
+}
+
+#[test]
+fn test_decode_batch_single_message() {
+    let msg = Message::AuthReq { public_id: 43 };
+    let decoded = Message::decode_batch(Bytes::from(msg.to_bytes())).unwrap();
+    assert_eq!(decoded, vec![msg]);
+}
+
+#[test]
+fn test_batch_reports_failed_entry() {
+    let batch = fsp_comm::Batch {
+        messages: vec![
+            Message::Ok.into(),
+            Message::AuthReq { public_id: 43 }.into(),
+            FspComm {
+                r#type: 42,
+                value: Bytes::new(),
+                ..Default::default()
+            },
+        ],
+    };
+    let bytes = FspComm::from(batch).encode_to_vec();
+
+    match Message::decode_batch(Bytes::from(bytes)) {
+        Err(Error::BatchEntryError { index, error }) => {
+            assert_eq!(index, 2);
+            assert!(error.to_string().contains("unrecognised i32 variant"));
+        }
+        res => panic!("expected a batch entry error, got {:?}", res),
+    }
+}
+
+#[test]
+fn test_nested_batch_rejected() {
+    let msg = Message::from(vec![Message::Ok, Message::from(vec![Message::Ok])]);
+    assert!(matches!(
+        msg.validate(&Limits::default()),
+        Err(Error::BatchEntryError { index: 1, .. })
+    ));
+
+    let err = Message::try_from(msg.to_bytes()).unwrap_err();
+    assert!(matches!(err, Error::BatchEntryError { index: 1, .. }));
+    assert!(err.to_string().contains("batches cannot be nested"));
+}
+
+#[test]
+fn test_batch_limits_report_entry() {
+    let limits = Limits {
+        max_string_len: 8,
+        ..Default::default()
+    };
+    let msg = Message::from(vec![
+        Message::Ok,
+        Message::UploadTo {
+            file_id: 1,
+            upload_url: String::from("https://example.com/upload"),
+            deadline: None,
+        },
+    ]);
+    assert_eq!(
+        msg.validate(&limits),
+        Err(Error::BatchEntryError {
+            index: 1,
+            error: Box::new(Error::FieldTooLarge {
+                field: "upload_url",
+                size: 26,
+                limit: 8
+            })
+        })
+    );
+}
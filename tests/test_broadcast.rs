@@ -0,0 +1,176 @@
+//! Test broadcasting a message to several agents and gathering their replies.
+#![cfg(feature = "server")]
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_util::{sink, stream, Sink, StreamExt};
+use tokio::sync::mpsc;
+use ws_com_framework::broadcast::{broadcast, broadcast_with, send_all};
+use ws_com_framework::error::ErrorKind;
+use ws_com_framework::{Codec, Error, Message};
+
+type FrameSink = Pin<Box<dyn Sink<Bytes, Error = Error>>>;
+
+/// A sink of frames into a channel, which fails once the receiver is dropped.
+fn channel_sink(tx: mpsc::Sender<Bytes>) -> FrameSink {
+    Box::pin(sink::unfold(tx, |tx, frame| async move {
+        tx.send(frame)
+            .await
+            .map_err(|_| Error::TransportError(String::from("closed")))?;
+        Ok(tx)
+    }))
+}
+
+fn status_res(public_id: u64, upload_id: u64) -> Message {
+    Message::StatusRes {
+        public_id,
+        ready: true,
+        uptime: 10,
+        upload_id,
+        message: None,
+        active_shares: None,
+        bytes_shared: None,
+        uploads_in_progress: None,
+        free_disk: None,
+        agent_version: None,
+        os: None,
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_broadcast_gathers_replies() {
+    let (tx_1, mut rx_1) = mpsc::channel(1);
+    let (tx_2, mut rx_2) = mpsc::channel(1);
+    let (tx_3, rx_3) = mpsc::channel(1);
+    drop(rx_3);
+    let mut sinks = HashMap::from([
+        (1, channel_sink(tx_1)),
+        (2, channel_sink(tx_2)),
+        (3, channel_sink(tx_3)),
+    ]);
+
+    // agent 1 answers after unrelated messages and a reply to another request, agent 2 never
+    // answers, and agent 3 has disconnected
+    let replies = stream::iter(vec![
+        (1, Message::Ok),
+        (1, status_res(1, 99)),
+        (1, status_res(1, 7)),
+    ])
+    .chain(stream::pending());
+    let mut replies = Box::pin(replies);
+
+    let req = Message::StatusReq {
+        public_id: 0,
+        upload_id: 7,
+        deadline: None,
+    };
+    let start = tokio::time::Instant::now();
+    let results = broadcast(&req, &mut sinks, &mut replies, Duration::from_secs(5)).await;
+
+    assert_eq!(start.elapsed(), Duration::from_secs(5));
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[&1], Ok(status_res(1, 7)));
+    assert_eq!(results[&2], Err(Error::Timeout { public_id: 2 }));
+    assert!(matches!(results[&3], Err(Error::TransportError(_))));
+
+    // the same encoded frame went to every agent
+    let frame = Bytes::from(req.to_bytes());
+    assert_eq!(rx_1.recv().await.unwrap(), frame);
+    assert_eq!(rx_2.recv().await.unwrap(), frame);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_broadcast_replies_end() {
+    let mut sinks = HashMap::from([(1, sink::drain()), (2, sink::drain())]);
+    let mut replies = stream::iter(vec![(2, Message::Ok)]);
+
+    let results = broadcast(
+        &Message::Ok,
+        &mut sinks,
+        &mut replies,
+        Duration::from_secs(5),
+    )
+    .await;
+    assert_eq!(results[&1], Err(Error::NotConnected { public_id: 1 }));
+    assert_eq!(results[&2], Ok(Message::Ok));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_broadcast_error_answers_upload_request() {
+    let mut sinks = HashMap::from([(1, sink::drain()), (2, sink::drain())]);
+    let missing = Message::Error {
+        kind: ErrorKind::FileDoesntExist,
+        reason: None,
+    };
+    let mut replies = Box::pin(
+        stream::iter(vec![(1, missing.clone()), (2, status_res(2, 7))]).chain(stream::pending()),
+    );
+
+    let req = Message::StatusReq {
+        public_id: 0,
+        upload_id: 7,
+        deadline: None,
+    };
+    let start = tokio::time::Instant::now();
+    let results = broadcast(&req, &mut sinks, &mut replies, Duration::from_secs(5)).await;
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(results[&1], Ok(missing));
+    assert_eq!(results[&2], Ok(status_res(2, 7)));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_send_all_times_out_full_sinks() {
+    let (tx_1, mut rx_1) = mpsc::channel(1);
+    let (tx_2, _rx_2) = mpsc::channel(1);
+    tx_2.send(Bytes::new()).await.unwrap();
+    let mut sinks = HashMap::from([(1, channel_sink(tx_1)), (2, channel_sink(tx_2))]);
+
+    let results = send_all(&Message::Ok, &mut sinks, Duration::from_secs(1)).await;
+    assert_eq!(results[&1], Ok(()));
+    assert_eq!(results[&2], Err(Error::Timeout { public_id: 2 }));
+    assert_eq!(
+        rx_1.recv().await.unwrap(),
+        Bytes::from(Message::Ok.to_bytes())
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_broadcast_encodes_for_each_agent() {
+    let (tx_1, mut rx_1) = mpsc::channel(1);
+    let (tx_2, mut rx_2) = mpsc::channel(1);
+    let mut sinks = HashMap::from([(1, channel_sink(tx_1)), (2, channel_sink(tx_2))]);
+    let codecs = HashMap::from([(1, Codec::default())]);
+
+    // each agent's message is encoded by its own codec, and agent 2 has none
+    let encode = |public_id, msg: &Message| match codecs.get(&public_id) {
+        Some(codec) => codec.encode_on(7, msg).map(Bytes::from),
+        None => Err(Error::EncryptionError(String::from("no codec"))),
+    };
+    let req = Message::StatusReq {
+        public_id: 0,
+        upload_id: 7,
+        deadline: None,
+    };
+    let mut replies = stream::iter(vec![(1, status_res(1, 7))]);
+    let results = broadcast_with(
+        &req,
+        &mut sinks,
+        encode,
+        &mut replies,
+        Duration::from_secs(5),
+    )
+    .await;
+
+    assert_eq!(results[&1], Ok(status_res(1, 7)));
+    assert_eq!(
+        results[&2],
+        Err(Error::EncryptionError(String::from("no codec")))
+    );
+    let frame = rx_1.recv().await.unwrap();
+    assert_eq!(codecs[&1].decode_on(frame).unwrap(), (7, req));
+    drop(sinks);
+    assert_eq!(rx_2.recv().await, None);
+}
@@ -0,0 +1,251 @@
+//! Test the `Bytes` based encode/decode paths against the `Vec<u8>` conversions.
+
+use bytes::BytesMut;
+use prost::Message as _;
+use ws_com_framework::message::websocket_message::protobuf_types::FspComm;
+use ws_com_framework::message::{ConfigEntry, ConfigValue};
+use ws_com_framework::{error::ErrorKind, AgentInfo, Message};
+
+fn messages() -> Vec<Message> {
+    vec![
+        Message::Ok,
+        Message::Error {
+            kind: ErrorKind::FileDoesntExist,
+            reason: Some(String::from("no such file")),
+        },
+        Message::Error {
+            kind: ErrorKind::Unknown,
+            reason: Some(String::new()),
+        },
+        Message::UploadTo {
+            file_id: 123,
+            upload_url: String::from("https://example.com/upload"),
+            deadline: None,
+        },
+        Message::MetadataReq {
+            file_id: 0,
+            upload_id: 1234,
+            deadline: None,
+        },
+        Message::MetadataReq {
+            file_id: 1,
+            upload_id: 1234,
+            deadline: Some(0),
+        },
+        Message::MetadataRes {
+            file_id: 12343,
+            exp: 1234,
+            crt: 13834,
+            file_size: 34014,
+            username: String::from("hello, world"),
+            file_name: String::from("hello.txt"),
+            upload_id: 123434199,
+        },
+        Message::AuthReq { public_id: 0 },
+        Message::AuthRes {
+            public_id: 123087497859,
+            passcode: vec![7; 32],
+        },
+        Message::StatusReq {
+            public_id: 12308749783359,
+            upload_id: 2103408934,
+            deadline: None,
+        },
+        Message::StatusRes {
+            public_id: 123031803797834,
+            ready: false,
+            uptime: 123,
+            upload_id: 2103408934,
+            message: None,
+            active_shares: None,
+            bytes_shared: None,
+            uploads_in_progress: None,
+            free_disk: None,
+            agent_version: None,
+            os: None,
+        },
+        Message::StreamData {
+            data: vec![3; 100],
+            end: true,
+        },
+        Message::WindowUpdate { increment: 65536 },
+        Message::Goodbye {
+            reason: Some(String::from("redeploying")),
+            reconnect_after: Some(0),
+            redirect_url: None,
+        },
+        Message::Redirect {
+            url: String::from("wss://other.example.com/ws"),
+            token: vec![9; 57],
+        },
+        Message::TokenAuth {
+            public_id: 43,
+            token: vec![9; 57],
+        },
+        Message::AuthOk {
+            session_token: vec![9; 57],
+            expires_at: 1_700_000_000_000,
+        },
+        Message::RefreshSession,
+        Message::RotatePasscodeReq {
+            rotation_id: 7,
+            passcode: b"rotated".to_vec(),
+        },
+        Message::RotatePasscodeRes {
+            rotation_id: 7,
+            accepted: false,
+            reason: Some(String::from("a rotation is already in progress")),
+        },
+        Message::RegisterReq {
+            enrolment_code: String::from("7K2M-Q9XD-4HTB-0WNE"),
+            agent_info: AgentInfo {
+                hostname: String::from("fileserver"),
+                version: String::from("1.2.0"),
+                os: String::new(),
+            },
+        },
+        Message::RegisterReq {
+            enrolment_code: String::new(),
+            agent_info: AgentInfo::default(),
+        },
+        Message::StatusRes {
+            public_id: 43,
+            ready: false,
+            uptime: 0,
+            upload_id: 2,
+            message: None,
+            active_shares: Some(0),
+            bytes_shared: Some(u64::MAX),
+            uploads_in_progress: Some(7),
+            free_disk: Some(0),
+            agent_version: Some(String::new()),
+            os: Some(String::from("linux")),
+        },
+        Message::ConfigUpdate {
+            version: 4,
+            entries: vec![
+                ConfigEntry {
+                    key: String::from("max_upload_bps"),
+                    value: ConfigValue::Int(-1),
+                },
+                ConfigEntry {
+                    key: String::new(),
+                    value: ConfigValue::Bool(false),
+                },
+                ConfigEntry {
+                    key: String::from("motd"),
+                    value: ConfigValue::Text(String::new()),
+                },
+            ],
+        },
+        Message::ConfigUpdate {
+            version: 0,
+            entries: vec![],
+        },
+        Message::ConfigAck {
+            version: 4,
+            applied: false,
+            rejected: vec![String::from("colour"), String::new()],
+            reason: Some(String::from("unknown key")),
+        },
+        Message::DiagnosticsReq {
+            request_id: 9,
+            since: 1_700_000_000_000,
+            max_bytes: 0,
+        },
+        Message::DiagnosticsRes {
+            request_id: 9,
+            part: 2,
+            data: b"1700000000000 started\n".to_vec(),
+            end: true,
+        },
+        Message::StreamReset {
+            reason: Some(String::from("download cancelled")),
+        },
+        Message::StreamReset { reason: None },
+        Message::RegisterRes {
+            public_id: 43,
+            passcode: vec![7; 32],
+        },
+        Message::Error {
+            kind: ErrorKind::InvalidEnrolmentCode,
+            reason: None,
+        },
+    ]
+}
+
+#[test]
+fn test_encode_into_matches_prost() {
+    for msg in messages() {
+        let mut buf = BytesMut::new();
+        msg.encode_into(&mut buf);
+        let envelope = FspComm::from(msg.clone());
+        assert_eq!(&buf[..], &envelope.encode_to_vec()[..], "{:?}", msg);
+    }
+}
+
+#[test]
+fn test_decode_bytes_round_trip() {
+    for msg in messages() {
+        let mut buf = BytesMut::new();
+        msg.encode_into(&mut buf);
+        let msg2 = Message::decode_bytes(buf.freeze()).unwrap();
+        assert_eq!(msg, msg2);
+    }
+}
+
+#[test]
+fn test_encode_into_appends() {
+    let mut buf = BytesMut::new();
+    Message::AuthReq { public_id: 43 }.encode_into(&mut buf);
+    let first = buf.len();
+    Message::Ok.encode_into(&mut buf);
+    Message::AuthReq { public_id: 44 }.encode_into(&mut buf);
+
+    let mut buf = buf.freeze();
+    let msg = Message::decode_bytes(buf.split_to(first)).unwrap();
+    assert_eq!(msg, Message::AuthReq { public_id: 43 });
+}
+
+#[test]
+fn test_decode_bytes_bad_input() {
+    let bytes = bytes::Bytes::from_static(&[8, 99, 18, 7, 10, 5, 104, 101, 108, 108, 111]);
+    let err = Message::decode_bytes(bytes).unwrap_err();
+    assert!(err.to_string().contains("unrecognised i32 variant"));
+}
+
+#[test]
+fn test_encoded_len() {
+    for msg in messages() {
+        let bytes: Vec<u8> = msg.clone().into();
+        assert_eq!(msg.encoded_len(), bytes.len(), "{:?}", msg);
+    }
+}
+
+#[test]
+fn test_encode_to_reused_buffer() {
+    let mut buf: Vec<u8> = Vec::with_capacity(1024);
+    for msg in messages() {
+        buf.clear();
+        msg.encode_to(&mut buf).unwrap();
+        assert_eq!(Message::try_from(&buf[..]).unwrap(), msg);
+    }
+}
+
+#[test]
+fn test_encode_to_insufficient_capacity() {
+    let msg = Message::UploadTo {
+        file_id: 123,
+        upload_url: String::from("https://example.com/upload"),
+        deadline: None,
+    };
+
+    let mut exact = vec![0u8; msg.encoded_len()];
+    msg.encode_to(&mut &mut exact[..]).unwrap();
+    assert_eq!(Message::try_from(exact).unwrap(), msg);
+
+    let mut small = vec![0u8; msg.encoded_len() - 1];
+    let err = msg.encode_to(&mut &mut small[..]).unwrap_err();
+    assert!(err.to_string().contains("insufficient buffer capacity"));
+    assert!(small.iter().all(|b| *b == 0));
+}
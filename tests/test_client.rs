@@ -0,0 +1,463 @@
+//! Test the reconnecting client against loopback connections.
+#![cfg(all(feature = "client", feature = "loopback"))]
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use ws_com_framework::client::{
+    enrol, Backoff, ClientOptions, ConnectionEvent, ReconnectingClient,
+};
+use ws_com_framework::error::ErrorKind;
+use ws_com_framework::transport::loopback::{duplex, LoopbackTransport};
+use ws_com_framework::{AgentInfo, Error, Message};
+
+fn spawn_client(
+    options: ClientOptions,
+) -> (ReconnectingClient, UnboundedReceiver<LoopbackTransport>) {
+    let (tx, rx) = unbounded_channel();
+    let connector = move || {
+        let (agent, server) = duplex();
+        tx.send(server).unwrap();
+        async move { Ok(agent) }
+    };
+    let client = ReconnectingClient::spawn(43, b"passcode".to_vec(), options, connector);
+    (client, rx)
+}
+
+async fn authenticate(server: &mut LoopbackTransport) {
+    server
+        .send(Message::AuthReq { public_id: 43 })
+        .await
+        .unwrap();
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        Message::AuthRes {
+            public_id: 43,
+            passcode: b"passcode".to_vec()
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_handshake_and_exchange() {
+    let (mut client, mut servers) = spawn_client(ClientOptions::default());
+    let mut server = servers.recv().await.unwrap();
+
+    authenticate(&mut server).await;
+    assert_eq!(
+        client.next_event().await,
+        Some(ConnectionEvent::Connecting { attempt: 1 })
+    );
+    assert_eq!(client.next_event().await, Some(ConnectionEvent::Connected));
+
+    client.send(Message::Ok).await.unwrap();
+    assert_eq!(server.next().await.unwrap().unwrap(), Message::Ok);
+    server
+        .send(Message::StatusReq {
+            public_id: 43,
+            upload_id: 1,
+            deadline: None,
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        client.recv().await.unwrap().unwrap(),
+        Message::StatusReq {
+            public_id: 43,
+            upload_id: 1,
+            deadline: None,
+        }
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_reconnects_and_buffers() {
+    let (mut client, mut servers) = spawn_client(ClientOptions::default());
+    let mut server = servers.recv().await.unwrap();
+    authenticate(&mut server).await;
+    drop(server);
+
+    assert_eq!(
+        client.next_event().await,
+        Some(ConnectionEvent::Connecting { attempt: 1 })
+    );
+    assert_eq!(client.next_event().await, Some(ConnectionEvent::Connected));
+    assert!(matches!(
+        client.next_event().await,
+        Some(ConnectionEvent::Disconnected {
+            error: Error::TransportError(_),
+            ..
+        })
+    ));
+
+    // sent while disconnected, delivered once authenticated again
+    client.send(Message::Ok).await.unwrap();
+    let mut server = servers.recv().await.unwrap();
+    authenticate(&mut server).await;
+    assert_eq!(server.next().await.unwrap().unwrap(), Message::Ok);
+    assert_eq!(
+        client.next_event().await,
+        Some(ConnectionEvent::Connecting { attempt: 1 })
+    );
+    assert_eq!(client.next_event().await, Some(ConnectionEvent::Connected));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_goodbye_delays_reconnect() {
+    let (mut client, mut servers) = spawn_client(ClientOptions::default());
+    let mut server = servers.recv().await.unwrap();
+    authenticate(&mut server).await;
+
+    let goodbye = Message::Goodbye {
+        reason: Some(String::from("redeploying")),
+        reconnect_after: Some(60),
+        redirect_url: None,
+    };
+    server.send(goodbye.clone()).await.unwrap();
+    assert_eq!(client.recv().await.unwrap().unwrap(), goodbye);
+    drop(server);
+
+    client.next_event().await.unwrap();
+    client.next_event().await.unwrap();
+    match client.next_event().await.unwrap() {
+        ConnectionEvent::Disconnected { retry_in, .. } => {
+            assert_eq!(retry_in, Duration::from_secs(60))
+        }
+        event => panic!("expected a disconnection, got {:?}", event),
+    }
+
+    let start = tokio::time::Instant::now();
+    let _server = servers.recv().await.unwrap();
+    assert_eq!(start.elapsed(), Duration::from_secs(60));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_session_token() {
+    let (mut client, mut servers) = spawn_client(ClientOptions::default());
+    let mut server = servers.recv().await.unwrap();
+    authenticate(&mut server).await;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let expires_at = now.as_millis() as u64 + 3_600_000;
+    server
+        .send(Message::AuthOk {
+            session_token: vec![1, 2, 3],
+            expires_at,
+        })
+        .await
+        .unwrap();
+
+    // halfway through the token's lifetime, the client asks for a new one
+    let start = tokio::time::Instant::now();
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        Message::RefreshSession
+    );
+    assert!(start.elapsed() >= Duration::from_secs(1799));
+    drop(server);
+
+    // the token is presented instead of the passcode
+    let mut server = servers.recv().await.unwrap();
+    server
+        .send(Message::AuthReq { public_id: 43 })
+        .await
+        .unwrap();
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        Message::TokenAuth {
+            public_id: 43,
+            token: vec![1, 2, 3]
+        }
+    );
+
+    // once the server rejects it, the client falls back to its passcode for good
+    authenticate(&mut server).await;
+    drop(server);
+    let mut server = servers.recv().await.unwrap();
+    authenticate(&mut server).await;
+
+    server.send(Message::Ok).await.unwrap();
+    assert_eq!(client.recv().await.unwrap().unwrap(), Message::Ok);
+}
+
+#[tokio::test]
+async fn test_passcode_rotation() {
+    let (mut client, mut servers) = spawn_client(ClientOptions::default());
+    let mut server = servers.recv().await.unwrap();
+    authenticate(&mut server).await;
+
+    server
+        .send(Message::RotatePasscodeReq {
+            rotation_id: 1,
+            passcode: b"rotated".to_vec(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        Message::RotatePasscodeRes {
+            rotation_id: 1,
+            accepted: true,
+            reason: None,
+        }
+    );
+    client.next_event().await.unwrap();
+    client.next_event().await.unwrap();
+    assert_eq!(
+        client.next_event().await,
+        Some(ConnectionEvent::PasscodeRotated {
+            passcode: b"rotated".to_vec()
+        })
+    );
+    drop(server);
+
+    // the new passcode is presented, falling back to the old one if it is rejected
+    let mut server = servers.recv().await.unwrap();
+    for passcode in [&b"rotated"[..], b"passcode", b"rotated"] {
+        server
+            .send(Message::AuthReq { public_id: 43 })
+            .await
+            .unwrap();
+        assert_eq!(
+            server.next().await.unwrap().unwrap(),
+            Message::AuthRes {
+                public_id: 43,
+                passcode: passcode.to_vec()
+            }
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_enrol() {
+    let (mut agent, mut server) = duplex();
+    let info = AgentInfo {
+        hostname: String::from("fileserver"),
+        ..Default::default()
+    };
+    let handle = tokio::spawn(async move {
+        server
+            .send(Message::AuthReq { public_id: 0 })
+            .await
+            .unwrap();
+        assert_eq!(
+            server.next().await.unwrap().unwrap(),
+            Message::RegisterReq {
+                enrolment_code: String::from("7K2M-Q9XD"),
+                agent_info: AgentInfo {
+                    hostname: String::from("fileserver"),
+                    ..Default::default()
+                },
+            }
+        );
+        server
+            .send(Message::RegisterRes {
+                public_id: 43,
+                passcode: b"issued".to_vec(),
+            })
+            .await
+            .unwrap();
+    });
+    assert_eq!(
+        enrol(&mut agent, "7K2M-Q9XD", info.clone()).await,
+        Ok((43, b"issued".to_vec()))
+    );
+    handle.await.unwrap();
+
+    // the server rejects the code
+    let (mut agent, mut server) = duplex();
+    server
+        .send(Message::AuthReq { public_id: 0 })
+        .await
+        .unwrap();
+    server
+        .send(Message::Error {
+            kind: ErrorKind::InvalidEnrolmentCode,
+            reason: Some(String::from("unknown or already used code")),
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        enrol(&mut agent, "7K2M-Q9XD", info).await,
+        Err(Error::EnrolmentFailed(String::from(
+            "unknown or already used code"
+        )))
+    );
+}
+
+#[tokio::test]
+async fn test_follows_redirect() {
+    let (tx, mut servers) = unbounded_channel();
+    let connector = move |url: &str| {
+        let (agent, server) = duplex();
+        tx.send((url.to_owned(), server)).unwrap();
+        async move { Ok(agent) }
+    };
+    let mut client = ReconnectingClient::spawn_to(
+        "wss://one.example.com/ws",
+        43,
+        b"passcode".to_vec(),
+        ClientOptions::default(),
+        connector,
+    );
+
+    let (url, mut server) = servers.recv().await.unwrap();
+    assert_eq!(url, "wss://one.example.com/ws");
+    authenticate(&mut server).await;
+    let redirect = Message::Redirect {
+        url: String::from("wss://two.example.com/ws"),
+        token: vec![1, 2, 3],
+    };
+    server.send(redirect.clone()).await.unwrap();
+    assert_eq!(client.recv().await.unwrap().unwrap(), redirect);
+
+    // the new server is presented the token, and asks for the passcode if it is rejected
+    let (url, mut server) = servers.recv().await.unwrap();
+    assert_eq!(url, "wss://two.example.com/ws");
+    server
+        .send(Message::AuthReq { public_id: 43 })
+        .await
+        .unwrap();
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        Message::TokenAuth {
+            public_id: 43,
+            token: vec![1, 2, 3]
+        }
+    );
+    authenticate(&mut server).await;
+
+    let mut events = Vec::new();
+    for _ in 0..5 {
+        events.push(client.next_event().await.unwrap());
+    }
+    assert_eq!(
+        events,
+        vec![
+            ConnectionEvent::Connecting { attempt: 1 },
+            ConnectionEvent::Connected,
+            ConnectionEvent::Redirected {
+                url: String::from("wss://two.example.com/ws")
+            },
+            ConnectionEvent::Connecting { attempt: 1 },
+            ConnectionEvent::Connected,
+        ]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_spawn_ignores_redirect() {
+    let (mut client, mut servers) = spawn_client(ClientOptions::default());
+    let mut server = servers.recv().await.unwrap();
+    authenticate(&mut server).await;
+
+    let redirect = Message::Redirect {
+        url: String::from("wss://two.example.com/ws"),
+        token: vec![1, 2, 3],
+    };
+    server.send(redirect.clone()).await.unwrap();
+    assert_eq!(client.recv().await.unwrap().unwrap(), redirect);
+
+    // the connection is kept, and the token is not presented on the next one
+    client.send(Message::Ok).await.unwrap();
+    assert_eq!(server.next().await.unwrap().unwrap(), Message::Ok);
+    drop(server);
+    let mut server = servers.recv().await.unwrap();
+    authenticate(&mut server).await;
+
+    let mut events = Vec::new();
+    for _ in 0..3 {
+        events.push(client.next_event().await.unwrap());
+    }
+    assert_eq!(
+        events[..2],
+        [
+            ConnectionEvent::Connecting { attempt: 1 },
+            ConnectionEvent::Connected
+        ]
+    );
+    assert!(matches!(events[2], ConnectionEvent::Disconnected { .. }));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_gives_up() {
+    let options = ClientOptions {
+        backoff: Backoff {
+            max_attempts: Some(3),
+            ..Backoff::default()
+        },
+        ..ClientOptions::default()
+    };
+    let connector =
+        || async { Err::<LoopbackTransport, _>(Error::TransportError(String::from("refused"))) };
+    let mut client = ReconnectingClient::spawn(43, b"passcode".to_vec(), options, connector);
+    let refused = Error::TransportError(String::from("refused"));
+
+    for attempt in 1..=3 {
+        assert_eq!(
+            client.next_event().await,
+            Some(ConnectionEvent::Connecting { attempt })
+        );
+        match client.next_event().await.unwrap() {
+            ConnectionEvent::Disconnected { error, retry_in } => {
+                assert_eq!(error, refused);
+                // the first retry waits at most the initial delay
+                assert!(retry_in <= Duration::from_millis(500) * (1 << (attempt - 1)));
+            }
+            event => assert_eq!(
+                event,
+                ConnectionEvent::GaveUp {
+                    error: refused.clone()
+                }
+            ),
+        }
+    }
+
+    assert_eq!(client.next_event().await, None);
+    assert!(client.recv().await.is_none());
+    assert!(client.send(Message::Ok).await.is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_handshake_timeout() {
+    let (mut client, mut servers) = spawn_client(ClientOptions::default());
+    let _server = servers.recv().await.unwrap();
+
+    let start = tokio::time::Instant::now();
+    client.next_event().await.unwrap();
+    match client.next_event().await.unwrap() {
+        ConnectionEvent::Disconnected { error, .. } => assert_eq!(
+            error,
+            Error::TransportError(String::from(
+                "timed out waiting for the server to authenticate"
+            ))
+        ),
+        event => panic!("expected a disconnection, got {:?}", event),
+    }
+    assert_eq!(start.elapsed(), ClientOptions::default().handshake_timeout);
+}
+
+#[test]
+fn test_backoff_delay() {
+    let backoff = Backoff {
+        initial: Duration::from_secs(1),
+        max: Duration::from_secs(10),
+        jitter: 0.0,
+        max_attempts: None,
+    };
+    assert_eq!(backoff.delay(1), Duration::from_secs(1));
+    assert_eq!(backoff.delay(2), Duration::from_secs(2));
+    assert_eq!(backoff.delay(4), Duration::from_secs(8));
+    assert_eq!(backoff.delay(5), Duration::from_secs(10));
+    assert_eq!(backoff.delay(100), Duration::from_secs(10));
+
+    let backoff = Backoff {
+        jitter: 0.5,
+        ..backoff
+    };
+    for _ in 0..100 {
+        let delay = backoff.delay(3);
+        assert!(delay > Duration::from_secs(2) && delay <= Duration::from_secs(4));
+    }
+}
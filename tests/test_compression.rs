@@ -0,0 +1,159 @@
+//! Test compression of large messages by the `Codec`.
+
+use bytes::Bytes;
+use prost::Message as _;
+use ws_com_framework::message::websocket_message::protobuf_types::{fsp_comm, FspComm};
+use ws_com_framework::{Codec, Error, Message};
+
+fn large_status() -> Message {
+    Message::StatusRes {
+        public_id: 123031803797834,
+        ready: true,
+        uptime: 123,
+        upload_id: 2103408934,
+        message: Some("all shares healthy. ".repeat(500)),
+        active_shares: None,
+        bytes_shared: None,
+        uploads_in_progress: None,
+        free_disk: None,
+        agent_version: None,
+        os: None,
+    }
+}
+
+#[test]
+fn test_no_compression_by_default() {
+    let codec = Codec::default();
+    let msg = large_status();
+    let bytes = codec.encode(&msg).unwrap();
+    assert_eq!(bytes, msg.to_bytes());
+    assert_eq!(codec.decode(bytes.into()).unwrap(), msg);
+}
+
+#[test]
+fn test_unknown_compression() {
+    let envelope = FspComm {
+        r#type: fsp_comm::Type::StatusRes as i32,
+        value: Bytes::from_static(b"garbage"),
+        compression: 42,
+        ..Default::default()
+    };
+    let err = Message::try_from(envelope.encode_to_vec()).unwrap_err();
+    assert!(matches!(err, Error::CompressionError(_)));
+    assert!(err
+        .to_string()
+        .contains("unrecognised compression algorithm"));
+}
+
+#[cfg(not(feature = "compression"))]
+#[test]
+fn test_compressed_without_feature() {
+    let envelope = FspComm {
+        r#type: fsp_comm::Type::StatusRes as i32,
+        value: Bytes::from_static(b"not really deflate"),
+        compression: fsp_comm::Compression::Deflate as i32,
+        ..Default::default()
+    };
+    let err = Codec::default()
+        .decode(envelope.encode_to_vec().into())
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("the `compression` feature is not enabled"));
+}
+
+#[cfg(feature = "compression")]
+mod deflate {
+    use super::*;
+    use ws_com_framework::{Compression, Limits};
+
+    fn codec() -> Codec {
+        Codec {
+            compression: Compression::Deflate,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_compress_above_threshold() {
+        let codec = codec();
+        let msg = large_status();
+        let bytes = codec.encode(&msg).unwrap();
+        assert!(bytes.len() < msg.encoded_len() / 10);
+
+        let envelope = FspComm::decode(&bytes[..]).unwrap();
+        assert_eq!(envelope.compression, fsp_comm::Compression::Deflate as i32);
+
+        assert_eq!(codec.decode(bytes.clone().into()).unwrap(), msg);
+        assert_eq!(Message::try_from(bytes).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_below_threshold_not_compressed() {
+        let codec = codec();
+        let msg = Message::AuthReq { public_id: 43 };
+        assert_eq!(codec.encode(&msg).unwrap(), msg.to_bytes());
+    }
+
+    #[test]
+    fn test_incompressible_not_compressed() {
+        let codec = Codec {
+            compression_threshold: 0,
+            ..codec()
+        };
+        let msg = Message::AuthRes {
+            public_id: 43,
+            passcode: (0..=255).collect(),
+        };
+        assert_eq!(codec.encode(&msg).unwrap(), msg.to_bytes());
+    }
+
+    #[test]
+    fn test_decompression_limit() {
+        let msg = large_status();
+        let bytes = codec().encode(&msg).unwrap();
+
+        let receiver = Codec {
+            decode_limits: Limits {
+                max_frame_size: 4096,
+                ..Default::default()
+            },
+            ..codec()
+        };
+        let err = receiver.decode(bytes.into()).unwrap_err();
+        assert!(matches!(err, Error::CompressionError(_)));
+        assert!(err.to_string().contains("maximum frame size of 4096 bytes"));
+    }
+
+    #[test]
+    fn test_default_decompression_limit() {
+        use flate2::write::DeflateEncoder;
+        use std::io::Write;
+
+        let limit = Limits::default().max_frame_size;
+        let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&vec![0; limit + 1]).unwrap();
+        let envelope = FspComm {
+            r#type: fsp_comm::Type::StatusRes as i32,
+            value: encoder.finish().unwrap().into(),
+            compression: fsp_comm::Compression::Deflate as i32,
+            ..Default::default()
+        };
+
+        let err = Message::try_from(envelope.encode_to_vec()).unwrap_err();
+        assert!(matches!(err, Error::CompressionError(_)));
+        assert!(err.to_string().contains("maximum frame size"));
+    }
+
+    #[test]
+    fn test_invalid_deflate_stream() {
+        let envelope = FspComm {
+            r#type: fsp_comm::Type::StatusRes as i32,
+            value: Bytes::from_static(&[0xff; 16]),
+            compression: fsp_comm::Compression::Deflate as i32,
+            ..Default::default()
+        };
+        let err = Message::try_from(envelope.encode_to_vec()).unwrap_err();
+        assert!(err.to_string().contains("invalid deflate stream"));
+    }
+}
@@ -0,0 +1,102 @@
+//! Test applying settings pushed by the server.
+
+use ws_com_framework::config::ConfigStore;
+use ws_com_framework::message::{ConfigEntry, ConfigValue};
+use ws_com_framework::Message;
+
+fn store() -> ConfigStore {
+    let mut store = ConfigStore::new();
+    store.declare("max_upload_bps", ConfigValue::Int(0));
+    store.declare("uploads_enabled", ConfigValue::Bool(true));
+    store.declare("motd", ConfigValue::Text(String::new()));
+    store
+}
+
+fn entry(key: &str, value: ConfigValue) -> ConfigEntry {
+    ConfigEntry {
+        key: String::from(key),
+        value,
+    }
+}
+
+fn ack(version: u64, applied: bool, rejected: &[&str]) -> (u64, bool, Vec<String>) {
+    (
+        version,
+        applied,
+        rejected.iter().map(|key| key.to_string()).collect(),
+    )
+}
+
+fn unpack(msg: Option<Message>) -> (u64, bool, Vec<String>) {
+    match msg {
+        Some(Message::ConfigAck {
+            version,
+            applied,
+            rejected,
+            ..
+        }) => (version, applied, rejected),
+        other => panic!("expected an ack, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_apply() {
+    let mut store = store();
+    let update = Message::ConfigUpdate {
+        version: 3,
+        entries: vec![
+            entry("max_upload_bps", ConfigValue::Int(-1)),
+            entry("uploads_enabled", ConfigValue::Bool(false)),
+        ],
+    };
+    assert_eq!(unpack(store.apply(&update)), ack(3, true, &[]));
+    assert_eq!(store.version(), 3);
+    assert_eq!(store.get("max_upload_bps"), Some(&ConfigValue::Int(-1)));
+    assert_eq!(
+        store.entries().map(|(key, _)| key).collect::<Vec<_>>(),
+        ["max_upload_bps", "motd", "uploads_enabled"]
+    );
+
+    // a redelivered update is acknowledged again, and an older one rejected
+    assert_eq!(unpack(store.apply(&update)), ack(3, true, &[]));
+    let stale = Message::ConfigUpdate {
+        version: 2,
+        entries: vec![entry("motd", ConfigValue::Text(String::from("hi")))],
+    };
+    assert_eq!(unpack(store.apply(&stale)), ack(3, false, &[]));
+    assert_eq!(store.get("motd"), Some(&ConfigValue::Text(String::new())));
+
+    assert_eq!(store.apply(&Message::Ok), None);
+}
+
+#[test]
+fn test_rejected_atomically() {
+    let mut store = store();
+    let update = Message::ConfigUpdate {
+        version: 1,
+        entries: vec![
+            entry("max_upload_bps", ConfigValue::Int(1_000_000)),
+            entry("uploads_enabled", ConfigValue::Int(1)),
+            entry("colour", ConfigValue::Text(String::from("blue"))),
+        ],
+    };
+    assert_eq!(
+        unpack(store.apply(&update)),
+        ack(0, false, &["uploads_enabled", "colour"])
+    );
+    assert_eq!(store.version(), 0);
+    assert_eq!(store.get("max_upload_bps"), Some(&ConfigValue::Int(0)));
+    assert_eq!(store.get("colour"), None);
+}
+
+#[test]
+fn test_version_zero_rejected() {
+    let mut store = store();
+    let update = Message::ConfigUpdate {
+        version: 0,
+        entries: vec![entry("uploads_enabled", ConfigValue::Bool(false))],
+    };
+    // a new store is at version 0, but the update must not be acknowledged as applied
+    assert_eq!(unpack(store.apply(&update)), ack(0, false, &[]));
+    assert_eq!(store.get("uploads_enabled"), Some(&ConfigValue::Bool(true)));
+}
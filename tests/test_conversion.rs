@@ -1,6 +1,11 @@
 //! Test creating and converting every variant of the `Message` enum.
 
-use ws_com_framework::Message;
+// `try_into` is kept where it was used before encoding became infallible, to check that code
+// written against the fallible conversion still compiles
+#![allow(clippy::unnecessary_fallible_conversions)]
+
+use ws_com_framework::message::{ConfigEntry, ConfigValue};
+use ws_com_framework::{AgentInfo, Message};
 
 /// Test creating and parsing the OK message variant of `Message`.
 #[test]
@@ -37,6 +42,14 @@ fn test_converting_error() {
     let msg2: Message = Message::try_from(bytes).unwrap();
     assert_eq!(msg, msg2);
 
+    let msg = Message::Error {
+        kind: ws_com_framework::error::ErrorKind::Timeout,
+        reason: None,
+    };
+    let bytes: Vec<u8> = msg.clone().try_into().unwrap();
+    let msg2: Message = Message::try_from(bytes).unwrap();
+    assert_eq!(msg, msg2);
+
     let msg = Message::Error {
         kind: ws_com_framework::error::ErrorKind::Unknown,
         reason: Some(String::from("unable to valid install4")),
@@ -51,6 +64,7 @@ fn test_converting_upload_to() {
     let msg = Message::UploadTo {
         file_id: 123,
         upload_url: String::from("https://example.com/upload"),
+        deadline: None,
     };
     let bytes: Vec<u8> = msg.clone().try_into().unwrap();
     let msg2: Message = Message::try_from(bytes).unwrap();
@@ -62,6 +76,7 @@ fn test_converting_metadata_req() {
     let msg = Message::MetadataReq {
         upload_id: 1234,
         file_id: 1234,
+        deadline: None,
     };
     let bytes: Vec<u8> = msg.clone().try_into().unwrap();
     let msg2: Message = Message::try_from(bytes).unwrap();
@@ -111,6 +126,7 @@ fn test_converting_status_req() {
     let msg = Message::StatusReq {
         public_id: 12308749783359,
         upload_id: 2103408934,
+        deadline: Some(1_700_000_000_000),
     };
     let bytes: Vec<u8> = msg.clone().try_into().unwrap();
     let msg2: Message = Message::try_from(bytes).unwrap();
@@ -124,9 +140,244 @@ fn test_converting_status_res() {
         ready: true,
         uptime: 123,
         message: Some(String::from("ooga buuga my booga")),
+        active_shares: None,
+        bytes_shared: None,
+        uploads_in_progress: None,
+        free_disk: None,
+        agent_version: None,
+        os: None,
         upload_id: 2103408934,
     };
     let bytes: Vec<u8> = msg.clone().try_into().unwrap();
     let msg2: Message = Message::try_from(bytes).unwrap();
     assert_eq!(msg, msg2);
+
+    let msg = Message::StatusRes {
+        public_id: 123031803797834,
+        ready: true,
+        uptime: 123,
+        message: None,
+        active_shares: Some(0),
+        bytes_shared: Some(1_500_000_000_000),
+        uploads_in_progress: Some(2),
+        free_disk: Some(0),
+        agent_version: Some(String::from("1.2.0")),
+        os: Some(String::from("linux")),
+        upload_id: 2103408934,
+    };
+    let bytes: Vec<u8> = msg.clone().into();
+    let msg2: Message = Message::try_from(bytes).unwrap();
+    assert_eq!(msg, msg2);
+}
+
+/// Test that encoding is infallible, so `From` and `TryFrom` give the same bytes.
+#[test]
+fn test_converting_infallibly() {
+    let messages = [
+        Message::Ok,
+        Message::AuthReq { public_id: 43 },
+        Message::Error {
+            kind: ws_com_framework::error::ErrorKind::FileDoesntExist,
+            reason: Some(String::from("no such file")),
+        },
+    ];
+    for msg in messages {
+        let bytes: Vec<u8> = msg.clone().into();
+        let fallible: Vec<u8> = msg.clone().try_into().unwrap();
+        assert_eq!(bytes, fallible);
+        let msg2: Message = Message::try_from(bytes).unwrap();
+        assert_eq!(msg, msg2);
+    }
+}
+
+/// Test that `?` still works on the conversion into bytes, as it did while it was fallible.
+#[test]
+fn test_converting_with_question_mark() -> Result<(), ws_com_framework::Error> {
+    let bytes: Vec<u8> = Message::Ok.try_into()?;
+    assert_eq!(Message::try_from(bytes)?, Message::Ok);
+    Ok(())
+}
+
+#[test]
+fn test_converting_stream_data() {
+    let msg = Message::StreamData {
+        data: vec![1, 2, 3, 4],
+        end: false,
+    };
+    let bytes: Vec<u8> = msg.clone().into();
+    let msg2: Message = Message::try_from(bytes).unwrap();
+    assert_eq!(msg, msg2);
+}
+
+#[test]
+fn test_converting_window_update() {
+    let msg = Message::WindowUpdate { increment: 16384 };
+    let bytes: Vec<u8> = msg.clone().into();
+    let msg2: Message = Message::try_from(bytes).unwrap();
+    assert_eq!(msg, msg2);
+}
+
+#[test]
+fn test_converting_goodbye() {
+    let msg = Message::Goodbye {
+        reason: Some(String::from("redeploying")),
+        reconnect_after: Some(30),
+        redirect_url: Some(String::from("wss://other.example.com/ws")),
+    };
+    let bytes: Vec<u8> = msg.clone().into();
+    let msg2: Message = Message::try_from(bytes).unwrap();
+    assert_eq!(msg, msg2);
+}
+
+#[test]
+fn test_converting_redirect() {
+    let msg = Message::Redirect {
+        url: String::from("wss://other.example.com/ws"),
+        token: vec![1, 2, 3],
+    };
+    let bytes: Vec<u8> = msg.clone().into();
+    let msg2: Message = Message::try_from(bytes).unwrap();
+    assert_eq!(msg, msg2);
+}
+
+#[test]
+fn test_converting_token_auth() {
+    let msg = Message::TokenAuth {
+        public_id: 123087497859,
+        token: vec![4, 5, 6],
+    };
+    let bytes: Vec<u8> = msg.clone().into();
+    let msg2: Message = Message::try_from(bytes).unwrap();
+    assert_eq!(msg, msg2);
+}
+
+#[test]
+fn test_converting_auth_ok() {
+    let msg = Message::AuthOk {
+        session_token: vec![4, 5, 6],
+        expires_at: 1_700_000_000_000,
+    };
+    let bytes: Vec<u8> = msg.clone().into();
+    let msg2: Message = Message::try_from(bytes).unwrap();
+    assert_eq!(msg, msg2);
+}
+
+#[test]
+fn test_converting_refresh_session() {
+    let msg = Message::RefreshSession;
+    let bytes: Vec<u8> = msg.clone().into();
+    let msg2: Message = Message::try_from(bytes).unwrap();
+    assert_eq!(msg, msg2);
+}
+
+#[test]
+fn test_converting_rotate_passcode() {
+    let msg = Message::RotatePasscodeReq {
+        rotation_id: 1,
+        passcode: vec![4, 5, 6],
+    };
+    let bytes: Vec<u8> = msg.clone().into();
+    let msg2: Message = Message::try_from(bytes).unwrap();
+    assert_eq!(msg, msg2);
+
+    let msg = Message::RotatePasscodeRes {
+        rotation_id: 1,
+        accepted: true,
+        reason: None,
+    };
+    let bytes: Vec<u8> = msg.clone().into();
+    let msg2: Message = Message::try_from(bytes).unwrap();
+    assert_eq!(msg, msg2);
+}
+
+#[test]
+fn test_converting_register() {
+    let msg = Message::RegisterReq {
+        enrolment_code: String::from("7K2M-Q9XD-4HTB-0WNE"),
+        agent_info: AgentInfo {
+            hostname: String::from("fileserver"),
+            version: String::from("1.2.0"),
+            os: String::from("linux"),
+        },
+    };
+    let bytes: Vec<u8> = msg.clone().into();
+    let msg2: Message = Message::try_from(bytes).unwrap();
+    assert_eq!(msg, msg2);
+
+    let msg = Message::RegisterRes {
+        public_id: 123087497859,
+        passcode: vec![4, 5, 6],
+    };
+    let bytes: Vec<u8> = msg.clone().into();
+    let msg2: Message = Message::try_from(bytes).unwrap();
+    assert_eq!(msg, msg2);
+}
+
+#[test]
+fn test_converting_config() {
+    let msg = Message::ConfigUpdate {
+        version: 7,
+        entries: vec![
+            ConfigEntry {
+                key: String::from("max_upload_bps"),
+                value: ConfigValue::Int(1_000_000),
+            },
+            ConfigEntry {
+                key: String::from("uploads_enabled"),
+                value: ConfigValue::Bool(false),
+            },
+            ConfigEntry {
+                key: String::from("motd"),
+                value: ConfigValue::Text(String::from("hello")),
+            },
+        ],
+    };
+    let bytes: Vec<u8> = msg.clone().into();
+    let msg2: Message = Message::try_from(bytes).unwrap();
+    assert_eq!(msg, msg2);
+
+    let msg = Message::ConfigAck {
+        version: 7,
+        applied: false,
+        rejected: vec![String::from("colour")],
+        reason: None,
+    };
+    let bytes: Vec<u8> = msg.clone().into();
+    let msg2: Message = Message::try_from(bytes).unwrap();
+    assert_eq!(msg, msg2);
+}
+
+#[test]
+fn test_converting_diagnostics_req() {
+    let msg = Message::DiagnosticsReq {
+        request_id: 3,
+        since: 1_700_000_000_000,
+        max_bytes: 65536,
+    };
+    let bytes: Vec<u8> = msg.clone().into();
+    let msg2: Message = Message::try_from(bytes).unwrap();
+    assert_eq!(msg, msg2);
+}
+
+#[test]
+fn test_converting_diagnostics_res() {
+    let msg = Message::DiagnosticsRes {
+        request_id: 3,
+        part: 0,
+        data: b"# status\nready: true\n".to_vec(),
+        end: false,
+    };
+    let bytes: Vec<u8> = msg.clone().into();
+    let msg2: Message = Message::try_from(bytes).unwrap();
+    assert_eq!(msg, msg2);
+}
+
+#[test]
+fn test_converting_stream_reset() {
+    let msg = Message::StreamReset {
+        reason: Some(String::from("download cancelled")),
+    };
+    let bytes: Vec<u8> = msg.clone().into();
+    let msg2: Message = Message::try_from(bytes).unwrap();
+    assert_eq!(msg, msg2);
 }
@@ -0,0 +1,70 @@
+//! Test cancelling requests once the deadline set by the server passes.
+#![cfg(any(feature = "client", feature = "server"))]
+
+use std::time::Duration;
+
+use ws_com_framework::deadline::{deadline_in, remaining, with_deadline};
+use ws_com_framework::error::ErrorKind;
+use ws_com_framework::Message;
+
+fn status_req(deadline: Option<u64>) -> Message {
+    Message::StatusReq {
+        public_id: 43,
+        upload_id: 1,
+        deadline,
+    }
+}
+
+fn is_timeout(msg: &Message) -> bool {
+    matches!(
+        msg,
+        Message::Error {
+            kind: ErrorKind::Timeout,
+            ..
+        }
+    )
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_handler_cancelled() {
+    let req = status_req(Some(deadline_in(Duration::from_secs(5))));
+    let left = remaining(&req).unwrap();
+    assert!(left <= Duration::from_secs(5) && left > Duration::from_secs(4));
+
+    let start = tokio::time::Instant::now();
+    let reply = with_deadline(&req, async {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        Message::Ok
+    })
+    .await;
+    assert!(is_timeout(&reply), "{:?}", reply);
+    assert!(start.elapsed() <= Duration::from_secs(5));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_handler_in_time() {
+    let req = status_req(Some(deadline_in(Duration::from_secs(5))));
+    let reply = with_deadline(&req, async {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        Message::Ok
+    })
+    .await;
+    assert_eq!(reply, Message::Ok);
+
+    // without a deadline the handler may take as long as it needs
+    let reply = with_deadline(&status_req(None), async {
+        tokio::time::sleep(Duration::from_secs(3600)).await;
+        Message::Ok
+    })
+    .await;
+    assert_eq!(reply, Message::Ok);
+    assert_eq!(remaining(&status_req(None)), None);
+}
+
+#[tokio::test]
+async fn test_expired_request_not_handled() {
+    let req = status_req(Some(1));
+    assert_eq!(remaining(&req), Some(Duration::ZERO));
+    let reply = with_deadline(&req, async { panic!("handler should not run") }).await;
+    assert!(is_timeout(&reply));
+}
@@ -0,0 +1,205 @@
+//! Test collecting diagnostics on an agent and reassembling them on the server.
+
+use ws_com_framework::diagnostics::{collect, respond, split, DiagnosticsAssembler, LogBuffer};
+use ws_com_framework::error::Error;
+use ws_com_framework::status::StatusReport;
+use ws_com_framework::Message;
+
+fn logs() -> LogBuffer {
+    let mut logs = LogBuffer::new(1024);
+    logs.push_at(1_000, "started");
+    logs.push_at(2_000, "shared report.pdf");
+    logs.push_at(3_000, "upload 7 finished");
+    logs
+}
+
+fn part(request_id: u64, part: u32, data: &[u8], end: bool) -> Message {
+    Message::DiagnosticsRes {
+        request_id,
+        part,
+        data: data.to_vec(),
+        end,
+    }
+}
+
+#[test]
+fn test_log_buffer_drops_oldest_lines() {
+    let mut logs = LogBuffer::new(10);
+    logs.push_at(1, "aaaa");
+    logs.push_at(2, "bbbb");
+    assert_eq!(logs.len(), 8);
+    logs.push_at(3, "cccc");
+    assert_eq!(logs.len(), 8);
+    let lines: Vec<_> = logs.since(0).collect();
+    assert_eq!(lines, [(2, "bbbb"), (3, "cccc")]);
+    assert_eq!(logs.since(3).count(), 1);
+
+    // a line larger than the buffer is not kept
+    logs.push_at(4, "x".repeat(11));
+    assert!(logs.is_empty());
+}
+
+#[test]
+fn test_collect_without_limit() {
+    let status = StatusReport {
+        ready: true,
+        uptime: 60,
+        free_disk: Some(4096),
+        ..Default::default()
+    };
+    let report = collect(2_000, 0, Some(&status), &logs());
+    assert_eq!(
+        String::from_utf8(report).unwrap(),
+        "# status\nready: true\nuptime: 60\nfree_disk: 4096\n# logs since 2000\n\
+         2000 shared report.pdf\n3000 upload 7 finished\n"
+    );
+}
+
+#[test]
+fn test_collect_drops_oldest_lines_over_limit() {
+    let full = collect(0, 0, None, &logs());
+    assert_eq!(
+        String::from_utf8(full.clone()).unwrap(),
+        "# logs since 0\n1000 started\n2000 shared report.pdf\n3000 upload 7 finished\n"
+    );
+
+    let report = collect(0, full.len() as u64 - 1, None, &logs());
+    assert!(report.len() < full.len());
+    assert_eq!(
+        String::from_utf8(report).unwrap(),
+        "# logs since 0\n# 2 earlier lines omitted\n3000 upload 7 finished\n"
+    );
+
+    // too small for any lines
+    let report = collect(0, 30, None, &logs());
+    assert_eq!(
+        String::from_utf8(report).unwrap(),
+        "# logs since 0\n# 3 earlier lin"
+    );
+}
+
+#[test]
+fn test_split_into_parts() {
+    let parts = split(5, b"abcdefg", 3);
+    assert_eq!(
+        parts,
+        [
+            part(5, 0, b"abc", false),
+            part(5, 1, b"def", false),
+            part(5, 2, b"g", true),
+        ]
+    );
+    assert_eq!(split(5, b"abcdef", 3).len(), 2);
+    assert_eq!(split(5, b"", 3), [part(5, 0, b"", true)]);
+}
+
+#[test]
+fn test_respond_ignores_other_messages() {
+    assert_eq!(respond(&Message::Ok, None, &logs(), 1024), None);
+}
+
+#[test]
+fn test_round_trip() {
+    let mut assembler = DiagnosticsAssembler::new();
+    let req = assembler.request(8, 0, 0);
+    assert!(assembler.is_pending(8));
+
+    let status = StatusReport::default();
+    let parts = respond(&req, Some(&status), &logs(), 10).unwrap();
+    let expected = collect(0, 0, Some(&status), &logs());
+    let last = parts.len() - 1;
+    for (i, part) in parts.into_iter().enumerate() {
+        let report = assembler.receive(part).unwrap();
+        if i < last {
+            assert_eq!(report, None);
+        } else {
+            assert_eq!(report, Some((8, expected.clone())));
+        }
+    }
+    assert!(!assembler.is_pending(8));
+}
+
+#[test]
+fn test_reject_unexpected_parts() {
+    let mut assembler = DiagnosticsAssembler::new();
+    assert!(matches!(
+        assembler.receive(part(1, 0, b"abc", true)),
+        Err(Error::DiagnosticsError { request_id: 1, .. })
+    ));
+
+    assembler.request(1, 0, 0);
+    assembler.receive(part(1, 0, b"abc", false)).unwrap();
+    assert!(matches!(
+        assembler.receive(part(1, 2, b"abc", true)),
+        Err(Error::DiagnosticsError { request_id: 1, .. })
+    ));
+    // the request was abandoned
+    assert!(!assembler.is_pending(1));
+
+    assembler.request(2, 0, 4);
+    assembler.receive(part(2, 0, b"abc", false)).unwrap();
+    assert!(matches!(
+        assembler.receive(part(2, 1, b"de", true)),
+        Err(Error::DiagnosticsError { request_id: 2, .. })
+    ));
+    assert!(!assembler.is_pending(2));
+
+    assembler.request(3, 0, 0);
+    assert!(assembler.cancel(3));
+    assert!(!assembler.cancel(3));
+    assert!(assembler.receive(part(3, 0, b"", true)).is_err());
+}
+
+#[test]
+fn test_assembler_ignores_other_messages() {
+    let mut assembler = DiagnosticsAssembler::new();
+    assert_eq!(assembler.receive(Message::Ok).unwrap(), None);
+}
+
+#[test]
+fn test_collect_cuts_at_character_boundary() {
+    let status = StatusReport {
+        message: Some(String::from("prêt à partager ✓")),
+        ..Default::default()
+    };
+    let mut logs = LogBuffer::new(1024);
+    logs.push_at(1_000, "fichier reçu: résumé.pdf");
+    logs.push_at(2_000, "上传完成");
+
+    let full = collect(0, 0, Some(&status), &logs);
+    for max_bytes in 1..full.len() as u64 {
+        let report = collect(0, max_bytes, Some(&status), &logs);
+        assert!(report.len() as u64 <= max_bytes);
+        String::from_utf8(report).unwrap();
+    }
+
+    // a limit falling inside the "ê" of the status keeps the text before it
+    let text = String::from_utf8(full).unwrap();
+    let at = text.find('ê').unwrap();
+    let report = collect(0, at as u64 + 1, Some(&status), &logs);
+    assert_eq!(String::from_utf8(report).unwrap(), text[..at]);
+}
+
+#[test]
+fn test_assembler_limit_without_request_limit() {
+    let mut assembler = DiagnosticsAssembler::new().with_max_report(5);
+
+    // a request for an unlimited report is still held to the assembler's limit
+    assembler.request(1, 0, 0);
+    assembler.receive(part(1, 0, b"abc", false)).unwrap();
+    assert!(matches!(
+        assembler.receive(part(1, 1, b"def", true)),
+        Err(Error::DiagnosticsError { request_id: 1, .. })
+    ));
+    assert!(!assembler.is_pending(1));
+
+    // as is one asking for more than it
+    assembler.request(2, 0, 1024);
+    assert!(assembler.receive(part(2, 0, b"abcdef", true)).is_err());
+
+    assembler.request(3, 0, 0);
+    assert_eq!(
+        assembler.receive(part(3, 0, b"abcde", true)).unwrap(),
+        Some((3, b"abcde".to_vec()))
+    );
+}
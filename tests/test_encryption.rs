@@ -0,0 +1,218 @@
+//! Test end-to-end encryption of messages between an agent and the server.
+
+use bytes::Bytes;
+use prost::Message as _;
+use ws_com_framework::message::websocket_message::protobuf_types::{fsp_comm, FspComm};
+use ws_com_framework::{Error, Message};
+
+#[test]
+fn test_encrypted_without_cipher() {
+    let envelope = FspComm {
+        r#type: fsp_comm::Type::Auth as i32,
+        value: Bytes::from_static(b"ciphertext"),
+        nonce: Bytes::from_static(&[1; 24]),
+        ..Default::default()
+    };
+    let err = Message::try_from(envelope.encode_to_vec()).unwrap_err();
+    assert!(matches!(err, Error::EncryptionError(_)));
+    assert!(err.to_string().contains("no session cipher is configured"));
+}
+
+#[cfg(feature = "encryption")]
+mod cipher {
+    use super::*;
+    use ws_com_framework::session::Role;
+    use ws_com_framework::{Codec, SessionCipher};
+
+    const PUBLIC_ID: u64 = 123087497859;
+    const PASSCODE: &[u8] = b"correct horse battery staple";
+
+    fn codec(role: Role, passcode: &[u8]) -> Codec {
+        Codec {
+            cipher: Some(SessionCipher::new(role, PUBLIC_ID, passcode)),
+            ..Default::default()
+        }
+    }
+
+    fn auth_res() -> Message {
+        Message::AuthRes {
+            public_id: PUBLIC_ID,
+            passcode: PASSCODE.to_vec(),
+        }
+    }
+
+    /// Codecs for the agent and server ends of a new connection, once the agent has received the
+    /// server's `AuthReq`.
+    fn connect(passcode: &[u8]) -> (Codec, Codec) {
+        let agent = codec(Role::Agent, passcode);
+        let server = codec(Role::Server, PASSCODE);
+        let req = Message::AuthReq {
+            public_id: PUBLIC_ID,
+        };
+        let bytes = server.encode(&req).unwrap();
+        assert_eq!(agent.decode(bytes.into()).unwrap(), req);
+        (agent, server)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (agent, server) = connect(PASSCODE);
+
+        let bytes = agent.encode(&auth_res()).unwrap();
+        assert!(!bytes.windows(PASSCODE.len()).any(|w| w == PASSCODE));
+        assert_eq!(server.decode(bytes.into()).unwrap(), auth_res());
+
+        let msg = Message::UploadTo {
+            file_id: 123,
+            upload_url: String::from("https://example.com/upload"),
+            deadline: None,
+        };
+        let bytes = server.encode(&msg).unwrap();
+        assert_eq!(agent.decode(bytes.into()).unwrap(), msg);
+
+        let bytes = server.encode(&Message::Ok).unwrap();
+        assert_eq!(agent.decode(bytes.into()).unwrap(), Message::Ok);
+    }
+
+    #[test]
+    fn test_nonces_are_unique() {
+        let (agent, _server) = connect(PASSCODE);
+        let a = agent.encode(&auth_res()).unwrap();
+        let b = agent.encode(&auth_res()).unwrap();
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_wrong_passcode() {
+        let (agent, server) = connect(b"not the passcode");
+
+        let bytes = agent.encode(&auth_res()).unwrap();
+        let err = server.decode(bytes.into()).unwrap_err();
+        assert!(matches!(err, Error::EncryptionError(_)));
+    }
+
+    #[test]
+    fn test_reflected_message_rejected() {
+        let (agent, _server) = connect(PASSCODE);
+        let bytes = agent.encode(&auth_res()).unwrap();
+        assert!(agent.decode(bytes.into()).is_err());
+    }
+
+    #[test]
+    fn test_tampering_detected() {
+        let (agent, server) = connect(PASSCODE);
+        let bytes = agent.encode(&auth_res()).unwrap();
+
+        let mut envelope = FspComm::decode(&bytes[..]).unwrap();
+        let mut value = envelope.value.to_vec();
+        value[0] ^= 1;
+        envelope.value = value.into();
+        let err = server.decode(envelope.encode_to_vec().into()).unwrap_err();
+        assert!(err.to_string().contains("tampered with"));
+
+        let mut envelope = FspComm::decode(&bytes[..]).unwrap();
+        envelope.r#type = fsp_comm::Type::Authreq as i32;
+        assert!(server.decode(envelope.encode_to_vec().into()).is_err());
+
+        let bytes = agent.encode_on(3, &auth_res()).unwrap();
+        let mut envelope = FspComm::decode(&bytes[..]).unwrap();
+        envelope.stream_id = 5;
+        assert!(server.decode(envelope.encode_to_vec().into()).is_err());
+    }
+
+    #[test]
+    fn test_unencrypted_message_rejected() {
+        let server = codec(Role::Server, PASSCODE);
+        let err = server.decode(auth_res().to_bytes().into()).unwrap_err();
+        assert!(err.to_string().contains("unencrypted message"));
+    }
+
+    #[test]
+    fn test_auth_req_carries_salt_unencrypted() {
+        let server = codec(Role::Server, PASSCODE);
+        let bytes = server
+            .encode(&Message::AuthReq {
+                public_id: PUBLIC_ID,
+            })
+            .unwrap();
+        let envelope = FspComm::decode(&bytes[..]).unwrap();
+        assert!(envelope.nonce.is_empty());
+        assert_eq!(envelope.encryption_salt.len(), 16);
+
+        let other = codec(Role::Server, PASSCODE);
+        let bytes2 = other
+            .encode(&Message::AuthReq {
+                public_id: PUBLIC_ID,
+            })
+            .unwrap();
+        assert_ne!(bytes, bytes2);
+    }
+
+    #[test]
+    fn test_send_before_handshake() {
+        let server = codec(Role::Server, PASSCODE);
+        let err = server.encode(&Message::Ok).unwrap_err();
+        assert!(matches!(err, Error::EncryptionError(_)));
+
+        let agent = codec(Role::Agent, PASSCODE);
+        assert!(agent.encode(&auth_res()).is_err());
+    }
+
+    #[test]
+    fn test_replay_across_sessions_rejected() {
+        let msg = Message::UploadTo {
+            file_id: 123,
+            upload_url: String::from("https://example.com/upload"),
+            deadline: None,
+        };
+
+        let (agent, server) = connect(PASSCODE);
+        let old_res = agent.encode(&auth_res()).unwrap();
+        server.decode(old_res.clone().into()).unwrap();
+        let old_upload = server.encode(&msg).unwrap();
+        agent.decode(old_upload.clone().into()).unwrap();
+
+        // a new connection with the same agent, where both ends pick new salts
+        let (agent, server) = connect(PASSCODE);
+        let err = server.decode(old_res.into()).unwrap_err();
+        assert!(matches!(err, Error::EncryptionError(_)));
+        let err = agent.decode(old_upload.into()).unwrap_err();
+        assert!(matches!(err, Error::EncryptionError(_)));
+
+        let bytes = agent.encode(&auth_res()).unwrap();
+        assert_eq!(server.decode(bytes.into()).unwrap(), auth_res());
+        let bytes = server.encode(&msg).unwrap();
+        assert_eq!(agent.decode(bytes.into()).unwrap(), msg);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_and_encrypted() {
+        use ws_com_framework::Compression;
+
+        let (agent, server) = connect(PASSCODE);
+        let agent = Codec {
+            compression: Compression::Deflate,
+            ..agent
+        };
+        let bytes = agent.encode(&auth_res()).unwrap();
+        server.decode(bytes.into()).unwrap();
+        let msg = Message::StatusRes {
+            public_id: PUBLIC_ID,
+            ready: true,
+            uptime: 123,
+            upload_id: 2103408934,
+            message: Some("all shares healthy. ".repeat(500)),
+            active_shares: None,
+            bytes_shared: None,
+            uploads_in_progress: None,
+            free_disk: None,
+            agent_version: None,
+            os: None,
+        };
+
+        let bytes = agent.encode(&msg).unwrap();
+        assert!(bytes.len() < msg.encoded_len() / 10);
+        assert_eq!(server.decode(bytes.into()).unwrap(), msg);
+    }
+}
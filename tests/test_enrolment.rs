@@ -0,0 +1,86 @@
+//! Test enrolling agents with one-time codes.
+#![cfg(feature = "enrolment")]
+
+use std::time::Duration;
+
+use ws_com_framework::enrolment::{generate_passcode, EnrolmentCodes};
+use ws_com_framework::error::ErrorKind;
+use ws_com_framework::{AgentInfo, Error, Message};
+
+#[test]
+fn test_codes() {
+    let codes = EnrolmentCodes::new();
+    let code = codes.issue(Duration::from_secs(60));
+    assert_eq!(code.len(), 19);
+    assert_eq!(code.matches('-').count(), 3);
+    assert_ne!(code, codes.issue(Duration::from_secs(60)));
+
+    // codes are matched ignoring case and separators, and only once
+    let typed = code.replace('-', " ").to_lowercase();
+    codes.redeem(&typed).unwrap();
+    assert_eq!(
+        codes.redeem(&code),
+        Err(Error::EnrolmentFailed(String::from(
+            "unknown or already used code"
+        )))
+    );
+    assert!(codes.redeem("0000-0000-0000-0000").is_err());
+
+    let expired = codes.issue(Duration::ZERO);
+    assert!(matches!(
+        codes.redeem(&expired),
+        Err(Error::EnrolmentFailed(_))
+    ));
+}
+
+#[test]
+fn test_register() {
+    let codes = EnrolmentCodes::new();
+    let info = AgentInfo {
+        hostname: String::from("fileserver"),
+        version: String::from("1.2.0"),
+        os: String::from("linux"),
+    };
+    let req = Message::RegisterReq {
+        enrolment_code: codes.issue(Duration::from_secs(60)),
+        agent_info: info.clone(),
+    };
+
+    let mut stored = None;
+    let res = codes.register(&req, |agent_info, passcode| {
+        stored = Some((agent_info.clone(), passcode.clone()));
+        43
+    });
+    let (agent_info, passcode) = stored.unwrap();
+    assert_eq!(agent_info, info);
+    assert_eq!(passcode.len(), 32);
+    assert_eq!(
+        res,
+        Message::RegisterRes {
+            public_id: 43,
+            passcode
+        }
+    );
+
+    let res = codes.register(&req, |_, _| panic!("the code was already used"));
+    assert_eq!(
+        res,
+        Message::Error {
+            kind: ErrorKind::InvalidEnrolmentCode,
+            reason: Some(String::from("unknown or already used code")),
+        }
+    );
+    assert!(matches!(
+        codes.register(&Message::Ok, |_, _| 44),
+        Message::Error {
+            kind: ErrorKind::Unknown,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn test_generate_passcode() {
+    assert_eq!(generate_passcode().len(), 32);
+    assert_ne!(generate_passcode(), generate_passcode());
+}
@@ -0,0 +1,277 @@
+//! Test the infallible encoding API, and the limits enforced by the validating encoder.
+
+use bytes::Bytes;
+use ws_com_framework::message::{ConfigEntry, ConfigValue};
+use ws_com_framework::{AgentInfo, Error, Limits, Message};
+
+#[test]
+fn test_to_bytes() {
+    let msg = Message::UploadTo {
+        file_id: 123,
+        upload_url: String::from("https://example.com/upload"),
+        deadline: None,
+    };
+    let bytes = msg.to_bytes();
+    assert_eq!(bytes, Vec::<u8>::from(msg.clone()));
+    assert_eq!(Message::try_from(bytes).unwrap(), msg);
+}
+
+#[test]
+fn test_to_bytes_checked() {
+    let msg = Message::MetadataRes {
+        file_id: 12343,
+        exp: 1234,
+        crt: 13834,
+        file_size: 34014,
+        username: String::from("hello, world"),
+        file_name: String::from("hello.txt"),
+        upload_id: 123434199,
+    };
+    let bytes = msg.to_bytes_checked(&Limits::default()).unwrap();
+    assert_eq!(bytes, msg.to_bytes());
+}
+
+#[test]
+fn test_string_too_long() {
+    let limits = Limits {
+        max_string_len: 8,
+        ..Default::default()
+    };
+    let msg = Message::MetadataRes {
+        file_id: 12343,
+        exp: 1234,
+        crt: 13834,
+        file_size: 34014,
+        username: String::from("user"),
+        file_name: String::from("hello_world.txt"),
+        upload_id: 123434199,
+    };
+    assert_eq!(
+        msg.to_bytes_checked(&limits),
+        Err(Error::FieldTooLarge {
+            field: "file_name",
+            size: 15,
+            limit: 8
+        })
+    );
+
+    let msg = Message::Error {
+        kind: ws_com_framework::error::ErrorKind::Unknown,
+        reason: Some(String::from("far too long a reason")),
+    };
+    assert!(matches!(
+        msg.validate(&limits),
+        Err(Error::FieldTooLarge {
+            field: "reason",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_bytes_too_long() {
+    let limits = Limits {
+        max_bytes_len: 32,
+        ..Default::default()
+    };
+    let msg = Message::AuthRes {
+        public_id: 1,
+        passcode: vec![1; 32],
+    };
+    assert!(msg.validate(&limits).is_ok());
+
+    let msg = Message::AuthRes {
+        public_id: 1,
+        passcode: vec![1; 33],
+    };
+    assert_eq!(
+        msg.validate(&limits),
+        Err(Error::FieldTooLarge {
+            field: "passcode",
+            size: 33,
+            limit: 32
+        })
+    );
+}
+
+#[test]
+fn test_frame_too_large() {
+    let msg = Message::UploadTo {
+        file_id: 123,
+        upload_url: String::from("https://example.com/upload"),
+        deadline: None,
+    };
+    let limits = Limits {
+        max_frame_size: msg.encoded_len() - 1,
+        ..Default::default()
+    };
+    assert_eq!(
+        msg.to_bytes_checked(&limits),
+        Err(Error::FrameTooLarge {
+            size: msg.encoded_len(),
+            limit: msg.encoded_len() - 1
+        })
+    );
+}
+
+#[test]
+fn test_decode_limited() {
+    let msg = Message::MetadataRes {
+        file_id: 12343,
+        exp: 1234,
+        crt: 13834,
+        file_size: 34014,
+        username: String::from("hello, world"),
+        file_name: String::from("hello.txt"),
+        upload_id: 123434199,
+    };
+    let bytes = Bytes::from(msg.to_bytes());
+    let msg2 = Message::decode_limited(bytes, &Limits::default()).unwrap();
+    assert_eq!(msg, msg2);
+}
+
+#[test]
+fn test_decode_frame_too_large() {
+    let msg = Message::MetadataRes {
+        file_id: 12343,
+        exp: 1234,
+        crt: 13834,
+        file_size: 34014,
+        username: String::from("hello, world"),
+        file_name: "a".repeat(4 * 1024 * 1024),
+        upload_id: 123434199,
+    };
+    let bytes = Bytes::from(msg.to_bytes());
+    let size = bytes.len();
+    assert_eq!(
+        Message::decode_limited(bytes, &Limits::default()),
+        Err(Error::FrameTooLarge {
+            size,
+            limit: 1024 * 1024
+        })
+    );
+}
+
+#[test]
+fn test_decode_field_too_large() {
+    let limits = Limits {
+        max_string_len: 8,
+        max_bytes_len: 4,
+        ..Default::default()
+    };
+
+    let msg = Message::UploadTo {
+        file_id: 123,
+        upload_url: String::from("https://example.com/upload"),
+        deadline: None,
+    };
+    assert_eq!(
+        Message::decode_limited(msg.to_bytes().into(), &limits),
+        Err(Error::FieldTooLarge {
+            field: "upload_url",
+            size: 26,
+            limit: 8
+        })
+    );
+
+    let msg = Message::AuthRes {
+        public_id: 1,
+        passcode: vec![1; 5],
+    };
+    assert_eq!(
+        Message::decode_limited(msg.to_bytes().into(), &limits),
+        Err(Error::FieldTooLarge {
+            field: "passcode",
+            size: 5,
+            limit: 4
+        })
+    );
+}
+
+#[test]
+fn test_decode_field_checked_before_decoding() {
+    let limits = Limits {
+        max_string_len: 8,
+        ..Default::default()
+    };
+
+    // an UploadTo whose url is followed by a truncated field, which would fail to decode
+    let mut inner = vec![0x12, 26];
+    inner.extend_from_slice(b"https://example.com/upload");
+    inner.push(0xff);
+    let mut frame = vec![0x08, 2, 0x12, inner.len() as u8];
+    frame.extend_from_slice(&inner);
+
+    assert!(matches!(
+        Message::try_from(frame.clone()),
+        Err(Error::ByteDecodeError(_))
+    ));
+    assert_eq!(
+        Message::decode_limited(frame.into(), &limits),
+        Err(Error::FieldTooLarge {
+            field: "upload_url",
+            size: 26,
+            limit: 8
+        })
+    );
+}
+
+#[test]
+fn test_decode_nested_field_too_large() {
+    let limits = Limits {
+        max_string_len: 8,
+        ..Default::default()
+    };
+
+    let msg = Message::RegisterReq {
+        enrolment_code: String::from("AB12"),
+        agent_info: AgentInfo {
+            hostname: String::from("agent.example.com"),
+            ..Default::default()
+        },
+    };
+    assert_eq!(
+        Message::decode_limited(msg.to_bytes().into(), &limits),
+        Err(Error::FieldTooLarge {
+            field: "hostname",
+            size: 17,
+            limit: 8
+        })
+    );
+
+    let msg = Message::ConfigUpdate {
+        version: 1,
+        entries: vec![ConfigEntry {
+            key: String::from("motd"),
+            value: ConfigValue::Text(String::from("hello, world")),
+        }],
+    };
+    assert_eq!(
+        Message::decode_limited(msg.to_bytes().into(), &limits),
+        Err(Error::FieldTooLarge {
+            field: "text_value",
+            size: 12,
+            limit: 8
+        })
+    );
+
+    let msg = Message::from(vec![
+        Message::Ok,
+        Message::UploadTo {
+            file_id: 1,
+            upload_url: String::from("https://example.com/upload"),
+            deadline: None,
+        },
+    ]);
+    assert_eq!(
+        Message::decode_limited(msg.to_bytes().into(), &limits),
+        Err(Error::BatchEntryError {
+            index: 1,
+            error: Box::new(Error::FieldTooLarge {
+                field: "upload_url",
+                size: 26,
+                limit: 8
+            })
+        })
+    );
+}
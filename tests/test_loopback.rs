@@ -0,0 +1,211 @@
+//! Test the in-memory loopback transport.
+#![cfg(feature = "loopback")]
+
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use ws_com_framework::error::ErrorKind;
+use ws_com_framework::mux::{Multiplexer, MuxEvent, MuxOptions};
+use ws_com_framework::session::Role;
+use ws_com_framework::transport::loopback::{duplex, Faults};
+use ws_com_framework::transport::{CloseWith, GOING_AWAY};
+use ws_com_framework::{Codec, Error, Message};
+
+#[tokio::test]
+async fn test_send_and_receive() {
+    let (mut agent, mut server) = duplex();
+
+    agent
+        .send(Message::AuthReq { public_id: 43 })
+        .await
+        .unwrap();
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        Message::AuthReq { public_id: 43 }
+    );
+
+    let res = Message::AuthRes {
+        public_id: 43,
+        passcode: b"passcode".to_vec(),
+    };
+    server.send(res.clone()).await.unwrap();
+    assert_eq!(agent.next().await.unwrap().unwrap(), res);
+}
+
+#[tokio::test]
+async fn test_messages_go_through_the_codec() {
+    let (agent, mut server) = duplex();
+    let mut codec = Codec::default();
+    codec.encode_limits.max_string_len = 4;
+    let mut agent = agent.with_codec(codec);
+
+    let msg = Message::Error {
+        reason: Some(String::from("too long")),
+        kind: ErrorKind::Unknown,
+    };
+    assert!(matches!(
+        agent.send(msg).await,
+        Err(Error::FieldTooLarge { .. })
+    ));
+
+    agent.send_raw(vec![8, 99, 18, 1, 0]).unwrap();
+    assert!(matches!(
+        server.next().await.unwrap(),
+        Err(Error::ByteDecodeError(_))
+    ));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_latency() {
+    let (agent, mut server) = duplex();
+    let mut agent = agent.with_faults(Faults {
+        latency: Duration::from_secs(5),
+        ..Faults::default()
+    });
+
+    let start = tokio::time::Instant::now();
+    agent.send(Message::Ok).await.unwrap();
+    agent
+        .send(Message::StatusReq {
+            public_id: 1,
+            upload_id: 2,
+            deadline: None,
+        })
+        .await
+        .unwrap();
+    assert_eq!(server.next().await.unwrap().unwrap(), Message::Ok);
+    assert_eq!(start.elapsed(), Duration::from_secs(5));
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        Message::StatusReq {
+            public_id: 1,
+            upload_id: 2,
+            deadline: None,
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_drop_and_corrupt() {
+    let (agent, mut server) = duplex();
+    let mut sent = 0;
+    let mut agent = agent.with_faults(Faults {
+        drop: Some(Box::new(move |_| {
+            sent += 1;
+            sent == 1
+        })),
+        corrupt: Some(Box::new(|frame| frame.truncate(frame.len() - 1))),
+        ..Faults::default()
+    });
+
+    agent.send(Message::Ok).await.unwrap();
+    agent
+        .send(Message::AuthReq { public_id: 43 })
+        .await
+        .unwrap();
+    assert!(matches!(
+        server.next().await.unwrap(),
+        Err(Error::ByteDecodeError(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_close_ends_stream() {
+    let (mut agent, mut server) = duplex();
+
+    agent.send(Message::Ok).await.unwrap();
+    agent.close().await.unwrap();
+    assert!(agent.send(Message::Ok).await.is_err());
+
+    assert_eq!(server.next().await.unwrap().unwrap(), Message::Ok);
+    assert!(server.next().await.is_none());
+
+    drop(agent);
+    assert!(matches!(
+        server.send(Message::Ok).await,
+        Err(Error::TransportError(_))
+    ));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_close_with_code() {
+    let (mut agent, server) = duplex();
+    let mut server = server.with_faults(Faults {
+        latency: Duration::from_millis(50),
+        ..Faults::default()
+    });
+
+    server.send(Message::Ok).await.unwrap();
+    server
+        .close_with(GOING_AWAY, "shutting down")
+        .await
+        .unwrap();
+    assert!(server.send(Message::Ok).await.is_err());
+
+    // the close frame arrives after the frames sent before it
+    assert_eq!(agent.next().await.unwrap().unwrap(), Message::Ok);
+    assert_eq!(agent.close_frame(), None);
+    assert!(agent.next().await.is_none());
+    assert_eq!(agent.close_frame(), Some((GOING_AWAY, "shutting down")));
+    assert!(agent.next().await.is_none());
+}
+
+#[tokio::test]
+async fn test_multiplexed_streams() {
+    let (mut agent_conn, mut server_conn) = duplex();
+    let options = MuxOptions {
+        initial_window: 100,
+        max_chunk: 40,
+    };
+    let mut agent = Multiplexer::new(Role::Agent, options);
+    let mut server = Multiplexer::new(Role::Server, options);
+
+    // both streams need window updates from the server to finish
+    let big = agent.open();
+    let small = agent.open();
+    agent.send_data(big, vec![1; 500], true).unwrap();
+    agent.send_data(small, vec![2; 150], true).unwrap();
+
+    let mut received = [0, 0];
+    let mut ended = 0;
+    while ended < 2 {
+        while let Some((stream_id, msg)) = agent.next_outgoing() {
+            agent_conn.send_on(stream_id, &msg).unwrap();
+        }
+        let (stream_id, msg) = server_conn.next_on().await.unwrap().unwrap();
+        if let Some(MuxEvent::Data {
+            stream_id,
+            data,
+            end,
+        }) = server.receive(stream_id, msg).unwrap()
+        {
+            received[(stream_id / 2) as usize] += data.len();
+            ended += usize::from(end);
+            server.consume(stream_id, data.len());
+        }
+        while let Some((stream_id, msg)) = server.next_outgoing() {
+            server_conn.send_on(stream_id, &msg).unwrap();
+            let (stream_id, msg) = agent_conn.next_on().await.unwrap().unwrap();
+            assert_eq!(agent.receive(stream_id, msg).unwrap(), None);
+        }
+    }
+    assert_eq!(received, [500, 150]);
+
+    // a reset travels on its stream, and plain messages on stream 0
+    let stream_id = server.open();
+    server.send_data(stream_id, vec![3; 10], false).unwrap();
+    server.reset(stream_id, None).unwrap();
+    server.send_message(0, Message::Ok);
+    while let Some((stream_id, msg)) = server.next_outgoing() {
+        server_conn.send_on(stream_id, &msg).unwrap();
+    }
+    let (stream_id, msg) = agent_conn.next_on().await.unwrap().unwrap();
+    assert_eq!(
+        agent.receive(stream_id, msg).unwrap(),
+        Some(MuxEvent::Reset {
+            stream_id: 2,
+            reason: None
+        })
+    );
+    assert_eq!(agent_conn.next().await.unwrap().unwrap(), Message::Ok);
+}
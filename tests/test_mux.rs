@@ -0,0 +1,241 @@
+//! Test sharing a connection between several streams with flow control.
+
+use ws_com_framework::mux::{Multiplexer, MuxEvent, MuxOptions};
+use ws_com_framework::session::Role;
+use ws_com_framework::{Codec, Error, Message};
+
+fn options() -> MuxOptions {
+    MuxOptions {
+        initial_window: 100,
+        max_chunk: 40,
+    }
+}
+
+fn chunk(msg: Option<(u32, Message)>) -> (u32, usize, bool) {
+    match msg {
+        Some((stream_id, Message::StreamData { data, end })) => (stream_id, data.len(), end),
+        other => panic!("expected stream data, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_streams_take_turns() {
+    let mut mux = Multiplexer::new(Role::Agent, options());
+    let big = mux.open();
+    let small = mux.open();
+    assert_eq!((big, small), (1, 3));
+
+    mux.send_data(big, vec![0; 100], true).unwrap();
+    mux.send_data(small, vec![0; 50], true).unwrap();
+    let req = Message::MetadataReq {
+        file_id: 1,
+        upload_id: 2,
+        deadline: None,
+    };
+    mux.send_message(0, req.clone());
+
+    assert_eq!(mux.next_outgoing(), Some((0, req)));
+    assert_eq!(chunk(mux.next_outgoing()), (1, 40, false));
+    assert_eq!(chunk(mux.next_outgoing()), (3, 40, false));
+    assert_eq!(chunk(mux.next_outgoing()), (1, 40, false));
+    assert_eq!(chunk(mux.next_outgoing()), (3, 10, true));
+    assert_eq!(chunk(mux.next_outgoing()), (1, 20, true));
+    assert_eq!(mux.next_outgoing(), None);
+    assert!(!mux.has_outgoing());
+
+    assert!(matches!(
+        mux.send_data(small, vec![0; 1], false),
+        Err(Error::StreamError { stream_id: 3, .. })
+    ));
+}
+
+#[test]
+fn test_window_limits_sending() {
+    let mut agent = Multiplexer::new(Role::Agent, options());
+    let mut server = Multiplexer::new(Role::Server, options());
+    let stream_id = agent.open();
+    agent.send_data(stream_id, vec![0; 250], true).unwrap();
+
+    let mut received = 0;
+    while let Some((id, msg)) = agent.next_outgoing() {
+        match server.receive(id, msg).unwrap() {
+            Some(MuxEvent::Data { data, .. }) => received += data.len(),
+            other => panic!("expected stream data, got {:?}", other),
+        }
+    }
+    assert_eq!(received, 100);
+    assert_eq!(agent.send_window(stream_id), Some(0));
+
+    // consuming less than half the window does not extend it
+    server.consume(stream_id, 40);
+    assert!(!server.has_outgoing());
+    server.consume(stream_id, 60);
+    let (id, update) = server.next_outgoing().unwrap();
+    assert_eq!(update, Message::WindowUpdate { increment: 100 });
+    assert_eq!(agent.receive(id, update).unwrap(), None);
+
+    assert_eq!(chunk(agent.next_outgoing()), (1, 40, false));
+    assert_eq!(agent.send_window(stream_id), Some(60));
+}
+
+#[test]
+fn test_window_violation() {
+    let mut server = Multiplexer::new(Role::Server, options());
+    let data = |len| Message::StreamData {
+        data: vec![0; len],
+        end: false,
+    };
+
+    server.receive(1, data(60)).unwrap();
+    assert!(matches!(
+        server.receive(1, data(41)),
+        Err(Error::StreamError { stream_id: 1, .. })
+    ));
+
+    // streams opened by the server cannot carry data from the agent, and data on ended streams
+    // is dropped rather than reopening them
+    assert!(server.receive(2, data(1)).is_err());
+    assert!(server.receive(0, data(1)).is_err());
+    server
+        .receive(
+            3,
+            Message::StreamData {
+                data: vec![],
+                end: true,
+            },
+        )
+        .unwrap();
+    assert_eq!(server.receive(3, data(1)).unwrap(), None);
+}
+
+#[test]
+fn test_messages_pass_through() {
+    let mut server = Multiplexer::new(Role::Server, options());
+    assert_eq!(
+        server.receive(5, Message::Ok).unwrap(),
+        Some(MuxEvent::Message {
+            stream_id: 5,
+            message: Message::Ok
+        })
+    );
+    // window updates for unknown streams are ignored
+    assert_eq!(
+        server
+            .receive(2, Message::WindowUpdate { increment: 10 })
+            .unwrap(),
+        None
+    );
+}
+
+#[test]
+fn test_codec_stream_id() {
+    let codec = Codec::default();
+    let msg = Message::StreamData {
+        data: vec![1, 2, 3],
+        end: true,
+    };
+    let bytes = codec.encode_on(7, &msg).unwrap();
+    assert_eq!(
+        codec.decode_on(bytes.clone().into()).unwrap(),
+        (7, msg.clone())
+    );
+    assert_eq!(codec.decode(bytes.into()).unwrap(), msg);
+
+    let bytes = codec.encode(&Message::Ok).unwrap();
+    assert_eq!(codec.decode_on(bytes.into()).unwrap(), (0, Message::Ok));
+}
+
+#[test]
+fn test_reset_by_sender() {
+    let mut agent = Multiplexer::new(Role::Agent, options());
+    let mut server = Multiplexer::new(Role::Server, options());
+    let stream_id = agent.open();
+    agent.send_data(stream_id, vec![0; 100], false).unwrap();
+    let (_, first) = agent.next_outgoing().unwrap();
+
+    agent
+        .reset(stream_id, Some(String::from("cancelled")))
+        .unwrap();
+    assert_eq!(
+        agent.next_outgoing(),
+        Some((
+            1,
+            Message::StreamReset {
+                reason: Some(String::from("cancelled"))
+            }
+        ))
+    );
+    // the rest of the data is dropped, and the stream cannot be reset twice
+    assert_eq!(agent.next_outgoing(), None);
+    assert_eq!(agent.send_window(stream_id), None);
+    assert!(agent.reset(stream_id, None).is_err());
+
+    // the reset overtakes the data sent before it, which is discarded
+    let reset = Message::StreamReset {
+        reason: Some(String::from("cancelled")),
+    };
+    assert_eq!(
+        server.receive(1, reset).unwrap(),
+        Some(MuxEvent::Reset {
+            stream_id: 1,
+            reason: Some(String::from("cancelled"))
+        })
+    );
+    assert_eq!(server.receive(1, first).unwrap(), None);
+    // a new stream is still accepted
+    assert!(server
+        .receive(
+            3,
+            Message::StreamData {
+                data: vec![0; 10],
+                end: true
+            }
+        )
+        .unwrap()
+        .is_some());
+}
+
+#[test]
+fn test_reset_streams_are_forgotten() {
+    let mut server = Multiplexer::new(Role::Server, options());
+    let chunk = || Message::StreamData {
+        data: vec![0; 10],
+        end: false,
+    };
+    // streams reset before their last chunk arrives leave nothing behind
+    for stream_id in (1..2000).step_by(2) {
+        server.receive(stream_id, chunk()).unwrap();
+        server
+            .receive(stream_id, Message::StreamReset { reason: None })
+            .unwrap();
+        assert_eq!(server.receive(stream_id, chunk()).unwrap(), None);
+    }
+
+    let mut fresh = Multiplexer::new(Role::Server, options());
+    fresh
+        .receive(1999, Message::StreamReset { reason: None })
+        .unwrap();
+    assert_eq!(format!("{server:?}"), format!("{fresh:?}"));
+}
+
+#[test]
+fn test_reset_by_receiver() {
+    let mut agent = Multiplexer::new(Role::Agent, options());
+    let mut server = Multiplexer::new(Role::Server, options());
+    let stream_id = agent.open();
+    agent.send_data(stream_id, vec![0; 100], true).unwrap();
+
+    let (id, msg) = agent.next_outgoing().unwrap();
+    server.receive(id, msg).unwrap();
+    server.reset(stream_id, None).unwrap();
+    let (id, reset) = server.next_outgoing().unwrap();
+    assert_eq!((id, &reset), (1, &Message::StreamReset { reason: None }));
+
+    // data the agent sent before it saw the reset is discarded
+    let (id, msg) = agent.next_outgoing().unwrap();
+    assert_eq!(server.receive(id, msg).unwrap(), None);
+
+    assert!(agent.receive(id, reset).unwrap().is_some());
+    assert_eq!(agent.next_outgoing(), None);
+    assert!(agent.send_data(stream_id, vec![0; 1], true).is_err());
+}
@@ -0,0 +1,194 @@
+//! Test the bounded, prioritized outbound queue.
+#![cfg(feature = "server")]
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use ws_com_framework::error::ErrorKind;
+use ws_com_framework::queue::{outbound_queue, Overflow, Priority, QueueOptions, MAX_OVERTAKE};
+use ws_com_framework::{Error, Message};
+
+fn options(overflow: Overflow) -> QueueOptions {
+    QueueOptions {
+        max_frames: 2,
+        max_bytes: 1024,
+        overflow,
+    }
+}
+
+fn frame(n: u8) -> Bytes {
+    Bytes::from(vec![n; 4])
+}
+
+#[tokio::test]
+async fn test_control_before_bulk() {
+    let (tx, mut rx) = outbound_queue(QueueOptions::default());
+    let status = Message::StatusReq {
+        public_id: 43,
+        upload_id: 1,
+        deadline: None,
+    };
+    let error = Message::Error {
+        kind: ErrorKind::Unknown,
+        reason: None,
+    };
+
+    tx.send_message(&status).await.unwrap();
+    tx.send_message(&Message::Ok).await.unwrap();
+    tx.send_message(&error).await.unwrap();
+    assert_eq!(tx.len(), 3);
+
+    assert_eq!(rx.recv().await.unwrap(), Message::Ok.to_bytes());
+    assert_eq!(rx.recv().await.unwrap(), error.to_bytes());
+    assert_eq!(rx.recv().await.unwrap(), status.to_bytes());
+
+    drop(tx);
+    assert_eq!(rx.recv().await, None);
+}
+
+#[test]
+fn test_stream_flow_control_is_control() {
+    let data = Message::StreamData {
+        data: vec![0; 16],
+        end: false,
+    };
+    assert_eq!(Priority::of(&data), Priority::Bulk);
+    // a window update stuck behind bulk data could stall the peer's streams
+    assert_eq!(
+        Priority::of(&Message::WindowUpdate { increment: 1024 }),
+        Priority::Control
+    );
+    assert_eq!(
+        Priority::of(&Message::StreamReset { reason: None }),
+        Priority::Control
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_block() {
+    let (tx, mut rx) = outbound_queue(options(Overflow::Block));
+    tx.send(frame(1), Priority::Bulk).await.unwrap();
+    tx.send(frame(2), Priority::Bulk).await.unwrap();
+
+    let blocked = tokio::spawn({
+        let tx = tx.clone();
+        async move { tx.send(frame(3), Priority::Bulk).await }
+    });
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert!(!blocked.is_finished());
+
+    assert_eq!(rx.recv().await.unwrap(), frame(1));
+    blocked.await.unwrap().unwrap();
+    assert_eq!(rx.recv().await.unwrap(), frame(2));
+    assert_eq!(rx.recv().await.unwrap(), frame(3));
+}
+
+#[tokio::test]
+async fn test_byte_limit() {
+    let (tx, mut rx) = outbound_queue(QueueOptions {
+        max_frames: 10,
+        max_bytes: 6,
+        overflow: Overflow::DropOldest,
+    });
+
+    // a frame larger than the limit is still accepted on its own
+    tx.send(Bytes::from(vec![0; 10]), Priority::Bulk)
+        .await
+        .unwrap();
+    tx.send(frame(1), Priority::Bulk).await.unwrap();
+    tx.send(frame(2), Priority::Bulk).await.unwrap();
+    assert_eq!(tx.dropped(), 2);
+    assert_eq!(tx.bytes(), 4);
+    assert_eq!(rx.recv().await.unwrap(), frame(2));
+}
+
+#[tokio::test]
+async fn test_drop_oldest() {
+    let (tx, mut rx) = outbound_queue(options(Overflow::DropOldest));
+    tx.send(frame(1), Priority::Control).await.unwrap();
+    tx.send(frame(2), Priority::Bulk).await.unwrap();
+    tx.send(frame(3), Priority::Bulk).await.unwrap();
+    tx.send(frame(4), Priority::Control).await.unwrap();
+
+    assert_eq!(tx.dropped(), 2);
+    assert_eq!(rx.recv().await.unwrap(), frame(1));
+    assert_eq!(rx.recv().await.unwrap(), frame(4));
+    assert!(tx.is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_drop_oldest_keeps_control() {
+    let (tx, mut rx) = outbound_queue(options(Overflow::DropOldest));
+    tx.send(frame(1), Priority::Control).await.unwrap();
+    tx.send(frame(2), Priority::Control).await.unwrap();
+
+    let blocked = tokio::spawn({
+        let tx = tx.clone();
+        async move { tx.send(frame(3), Priority::Bulk).await }
+    });
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert!(!blocked.is_finished());
+    assert_eq!(tx.dropped(), 0);
+
+    assert_eq!(rx.recv().await.unwrap(), frame(1));
+    blocked.await.unwrap().unwrap();
+    assert_eq!(rx.recv().await.unwrap(), frame(2));
+    assert_eq!(rx.recv().await.unwrap(), frame(3));
+}
+
+#[tokio::test]
+async fn test_control_overtakes_a_bounded_number_of_frames() {
+    let (tx, mut rx) = outbound_queue(QueueOptions::default());
+    tx.send(frame(0), Priority::Bulk).await.unwrap();
+    tx.send(frame(1), Priority::Bulk).await.unwrap();
+    for _ in 0..2 * MAX_OVERTAKE {
+        tx.send(frame(2), Priority::Control).await.unwrap();
+    }
+
+    // signed frames reordered any further would fall outside the peer's replay window
+    for _ in 0..MAX_OVERTAKE {
+        assert_eq!(rx.recv().await.unwrap(), frame(2));
+    }
+    assert_eq!(rx.recv().await.unwrap(), frame(0));
+    assert_eq!(rx.recv().await.unwrap(), frame(1));
+    for _ in 0..MAX_OVERTAKE {
+        assert_eq!(rx.recv().await.unwrap(), frame(2));
+    }
+    assert!(tx.is_empty());
+}
+
+#[tokio::test]
+async fn test_disconnect() {
+    let (tx, mut rx) = outbound_queue(options(Overflow::Disconnect));
+    tx.send(frame(1), Priority::Bulk).await.unwrap();
+    tx.send(frame(2), Priority::Bulk).await.unwrap();
+
+    let full = Error::QueueFull {
+        frames: 2,
+        bytes: 8,
+    };
+    assert_eq!(
+        tx.send(frame(3), Priority::Control).await,
+        Err(full.clone())
+    );
+    assert_eq!(rx.recv().await, None);
+    assert_eq!(tx.send(frame(4), Priority::Control).await, Err(full));
+}
+
+#[tokio::test]
+async fn test_receiver_dropped() {
+    let (tx, rx) = outbound_queue(options(Overflow::Block));
+    tx.send(frame(1), Priority::Bulk).await.unwrap();
+    tx.send(frame(2), Priority::Bulk).await.unwrap();
+
+    let blocked = tokio::spawn({
+        let tx = tx.clone();
+        async move { tx.send(frame(3), Priority::Bulk).await }
+    });
+    tokio::task::yield_now().await;
+    drop(rx);
+    assert!(matches!(
+        blocked.await.unwrap(),
+        Err(Error::TransportError(_))
+    ));
+}
@@ -0,0 +1,281 @@
+//! Test routing messages to agents through the connection registry.
+#![cfg(feature = "server")]
+
+use std::time::Duration;
+
+use ws_com_framework::deadline::deadline_in;
+use ws_com_framework::error::ErrorKind;
+use ws_com_framework::registry::{ConnectionRegistry, DuplicateLogin};
+use ws_com_framework::{Error, Message};
+
+fn metadata_res(upload_id: u64) -> Message {
+    Message::MetadataRes {
+        file_id: 1,
+        exp: 2,
+        crt: 3,
+        file_size: 4,
+        username: String::from("user"),
+        file_name: String::from("file"),
+        upload_id,
+    }
+}
+
+#[tokio::test]
+async fn test_send_and_request() {
+    let registry = ConnectionRegistry::default();
+    let mut session = registry.register(43).unwrap();
+    assert!(registry.is_connected(43));
+    assert_eq!(registry.connected(), vec![43]);
+
+    registry.send(43, Message::Ok).await.unwrap();
+    assert_eq!(session.recv().await.unwrap(), Message::Ok);
+
+    let first = tokio::spawn({
+        let registry = registry.clone();
+        async move {
+            let req = Message::MetadataReq {
+                file_id: 1,
+                upload_id: 10,
+                deadline: None,
+            };
+            registry.request(43, req).await
+        }
+    });
+    let second = tokio::spawn({
+        let registry = registry.clone();
+        async move {
+            let req = Message::UploadTo {
+                file_id: 1,
+                upload_url: String::from("https://example.com"),
+                deadline: None,
+            };
+            registry.request(43, req).await
+        }
+    });
+    session.recv().await.unwrap();
+    session.recv().await.unwrap();
+
+    // unrelated messages are handed back to the server
+    let unrelated = Message::AuthReq { public_id: 43 };
+    assert_eq!(session.dispatch(unrelated.clone()), Some(unrelated));
+    let unknown = metadata_res(11);
+    assert_eq!(session.dispatch(unknown.clone()), Some(unknown));
+
+    assert_eq!(session.dispatch(Message::Ok), None);
+    assert_eq!(session.dispatch(metadata_res(10)), None);
+    assert_eq!(first.await.unwrap().unwrap(), metadata_res(10));
+    assert_eq!(second.await.unwrap().unwrap(), Message::Ok);
+
+    let err = Message::Error {
+        kind: ErrorKind::Unknown,
+        reason: None,
+    };
+    assert_eq!(session.dispatch(err.clone()), Some(err));
+}
+
+#[tokio::test]
+async fn test_not_connected() {
+    let registry = ConnectionRegistry::default();
+    assert_eq!(
+        registry.send(43, Message::Ok).await,
+        Err(Error::NotConnected { public_id: 43 })
+    );
+
+    let session = registry.register(43).unwrap();
+    let pending = tokio::spawn({
+        let registry = registry.clone();
+        async move { registry.request(43, Message::Ok).await }
+    });
+    tokio::task::yield_now().await;
+    drop(session);
+
+    assert_eq!(
+        pending.await.unwrap(),
+        Err(Error::NotConnected { public_id: 43 })
+    );
+    assert!(registry.is_empty());
+}
+
+#[tokio::test]
+async fn test_kick_old() {
+    let registry = ConnectionRegistry::new(DuplicateLogin::KickOld);
+    let mut old = registry.register(43).unwrap();
+    let mut new = registry.register(43).unwrap();
+
+    assert_eq!(old.recv().await, None);
+    drop(old);
+    assert!(registry.is_connected(43));
+
+    registry.send(43, Message::Ok).await.unwrap();
+    assert_eq!(new.recv().await.unwrap(), Message::Ok);
+
+    drop(new);
+    assert!(!registry.is_connected(43));
+}
+
+#[tokio::test]
+async fn test_reject_new() {
+    let registry = ConnectionRegistry::new(DuplicateLogin::RejectNew);
+    let session = registry.register(43).unwrap();
+
+    assert!(matches!(
+        registry.register(43),
+        Err(Error::AlreadyConnected { public_id: 43 })
+    ));
+    assert_eq!(registry.len(), 1);
+
+    drop(session);
+    assert!(registry.register(43).is_ok());
+}
+
+#[tokio::test]
+async fn test_abandoned_request_is_skipped() {
+    let registry = ConnectionRegistry::default();
+    let mut session = registry.register(43).unwrap();
+
+    // the first caller gives up before the agent replies
+    let abandoned = tokio::spawn({
+        let registry = registry.clone();
+        async move { registry.request(43, Message::Ok).await }
+    });
+    session.recv().await.unwrap();
+    abandoned.abort();
+    let _ = abandoned.await;
+
+    let waiting = tokio::spawn({
+        let registry = registry.clone();
+        async move { registry.request(43, Message::Ok).await }
+    });
+    session.recv().await.unwrap();
+
+    // the reply goes to the caller still waiting, not the abandoned one
+    assert_eq!(session.dispatch(Message::Ok), None);
+    assert_eq!(waiting.await.unwrap().unwrap(), Message::Ok);
+    assert_eq!(session.dispatch(Message::Ok), Some(Message::Ok));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_request_times_out() {
+    let registry = ConnectionRegistry::default().with_request_timeout(Duration::from_secs(5));
+    let mut session = registry.register(43).unwrap();
+
+    let start = tokio::time::Instant::now();
+    let pending = tokio::spawn({
+        let registry = registry.clone();
+        async move { registry.request(43, Message::Ok).await }
+    });
+    session.recv().await.unwrap();
+    assert_eq!(
+        pending.await.unwrap(),
+        Err(Error::Timeout { public_id: 43 })
+    );
+    assert_eq!(start.elapsed(), Duration::from_secs(5));
+
+    // the late reply is handed back to the server
+    assert_eq!(session.dispatch(Message::Ok), Some(Message::Ok));
+}
+
+#[tokio::test]
+async fn test_request_deadline() {
+    let registry = ConnectionRegistry::default();
+    let _session = registry.register(43).unwrap();
+
+    let req = Message::StatusReq {
+        public_id: 43,
+        upload_id: 1,
+        deadline: Some(deadline_in(Duration::from_millis(10))),
+    };
+    assert_eq!(
+        registry.request(43, req).await,
+        Err(Error::Timeout { public_id: 43 })
+    );
+}
+
+#[tokio::test]
+async fn test_error_answers_oldest_request() {
+    let registry = ConnectionRegistry::default();
+    let mut session = registry.register(43).unwrap();
+
+    let metadata = tokio::spawn({
+        let registry = registry.clone();
+        async move {
+            let req = Message::MetadataReq {
+                file_id: 1,
+                upload_id: 10,
+                deadline: None,
+            };
+            registry.request(43, req).await
+        }
+    });
+    session.recv().await.unwrap();
+    let ok = tokio::spawn({
+        let registry = registry.clone();
+        async move { registry.request(43, Message::Ok).await }
+    });
+    session.recv().await.unwrap();
+
+    // the agent could not find the file, so the metadata request fails
+    let err = Message::Error {
+        kind: ErrorKind::FileDoesntExist,
+        reason: None,
+    };
+    assert_eq!(session.dispatch(err.clone()), None);
+    assert_eq!(metadata.await.unwrap().unwrap(), err);
+
+    assert_eq!(session.dispatch(Message::Ok), None);
+    assert_eq!(ok.await.unwrap().unwrap(), Message::Ok);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_kick_old_with_pending_request() {
+    let registry = ConnectionRegistry::new(DuplicateLogin::KickOld);
+    let mut old = registry.register(43).unwrap();
+
+    let pending = tokio::spawn({
+        let registry = registry.clone();
+        async move { registry.request(43, Message::Ok).await }
+    });
+    old.recv().await.unwrap();
+
+    // the old session ends as soon as it is replaced, not once the request times out
+    let _new = registry.register(43).unwrap();
+    let start = tokio::time::Instant::now();
+    assert_eq!(old.recv().await, None);
+    assert_eq!(start.elapsed(), Duration::ZERO);
+
+    drop(old);
+    assert_eq!(
+        pending.await.unwrap(),
+        Err(Error::NotConnected { public_id: 43 })
+    );
+}
+
+#[tokio::test]
+async fn test_requests_with_same_upload_id() {
+    let registry = ConnectionRegistry::default();
+    let mut session = registry.register(43).unwrap();
+
+    let mut pending = Vec::new();
+    for _ in 0..2 {
+        pending.push(tokio::spawn({
+            let registry = registry.clone();
+            async move {
+                let req = Message::MetadataReq {
+                    file_id: 1,
+                    upload_id: 10,
+                    deadline: None,
+                };
+                registry.request(43, req).await
+            }
+        }));
+        session.recv().await.unwrap();
+    }
+
+    // each request is answered in turn, rather than the first being displaced
+    assert_eq!(session.dispatch(metadata_res(10)), None);
+    assert_eq!(session.dispatch(metadata_res(10)), None);
+    for pending in pending {
+        assert_eq!(pending.await.unwrap().unwrap(), metadata_res(10));
+    }
+    assert!(session.dispatch(metadata_res(10)).is_some());
+}
@@ -0,0 +1,117 @@
+//! Test rotating an agent's passcode, including when the connection drops part way through.
+
+use ws_com_framework::rotation::{AgentPasscode, ServerPasscode};
+use ws_com_framework::Message;
+
+fn accepted(rotation_id: u64) -> Message {
+    Message::RotatePasscodeRes {
+        rotation_id,
+        accepted: true,
+        reason: None,
+    }
+}
+
+#[test]
+fn test_server_issued() {
+    let mut agent = AgentPasscode::new(b"old".to_vec());
+    let mut server = ServerPasscode::new(b"old".to_vec());
+
+    let req = server.issue(1, b"new".to_vec()).unwrap();
+    assert!(server.issue(2, b"other".to_vec()).is_none());
+    let res = agent.handle(&req).unwrap();
+    assert_eq!(res, accepted(1));
+    assert_eq!(agent.current(), b"new");
+    assert_eq!(agent.previous(), Some(&b"old".to_vec()));
+
+    assert_eq!(server.handle(&res), None);
+    assert_eq!(server.current(), b"new");
+    assert_eq!(server.pending(), None);
+    assert!(!server.verify(b"old"));
+
+    // a repeated request keeps the passcode to fall back on
+    agent.handle(&req).unwrap();
+    assert_eq!(agent.previous(), Some(&b"old".to_vec()));
+}
+
+#[test]
+fn test_server_issued_interrupted() {
+    // the request never reaches the agent, which authenticates with the old passcode
+    let mut server = ServerPasscode::new(b"old".to_vec());
+    server.issue(1, b"new".to_vec()).unwrap();
+    assert!(server.verify(b"old"));
+    assert_eq!(server.pending(), None);
+    assert!(!server.verify(b"new"));
+
+    // the response never reaches the server, but the agent authenticates with the new passcode
+    let mut agent = AgentPasscode::new(b"old".to_vec());
+    let mut server = ServerPasscode::new(b"old".to_vec());
+    agent.handle(&server.issue(2, b"new".to_vec()).unwrap());
+    assert!(server.verify(agent.attempt(0)));
+    assert_eq!(server.current(), b"new");
+}
+
+#[test]
+fn test_agent_proposed() {
+    let mut agent = AgentPasscode::new(b"old".to_vec());
+    let mut server = ServerPasscode::new(b"old".to_vec());
+
+    let req = agent.propose(7, b"mine".to_vec());
+    let res = server.handle(&req).unwrap();
+    assert_eq!(res, accepted(7));
+    assert_eq!(server.pending(), Some((7, &b"mine".to_vec())));
+    // the agent keeps its passcode until the server accepts
+    assert_eq!(agent.current(), b"old");
+
+    // the response is lost, so the agent authenticates with the old passcode
+    let mut lost = server.clone();
+    assert!(lost.verify(agent.attempt(0)));
+    assert_eq!(lost.current(), b"old");
+    assert_eq!(lost.pending(), None);
+
+    assert_eq!(agent.handle(&res), None);
+    assert_eq!(agent.current(), b"mine");
+    assert!(server.verify(agent.attempt(0)));
+    assert_eq!(server.current(), b"mine");
+}
+
+#[test]
+fn test_rejected_proposals() {
+    let mut agent = AgentPasscode::new(b"old".to_vec());
+    let mut server = ServerPasscode::new(b"old".to_vec());
+
+    server.issue(1, b"new".to_vec()).unwrap();
+    let res = server.handle(&agent.propose(2, b"mine".to_vec())).unwrap();
+    assert!(matches!(
+        res,
+        Message::RotatePasscodeRes {
+            rotation_id: 2,
+            accepted: false,
+            reason: Some(_),
+        }
+    ));
+    agent.handle(&res);
+    assert_eq!(agent.current(), b"old");
+
+    let res = server.handle(&agent.propose(3, vec![])).unwrap();
+    assert!(matches!(
+        res,
+        Message::RotatePasscodeRes {
+            accepted: false,
+            ..
+        }
+    ));
+
+    // responses to other rotations are ignored
+    assert_eq!(agent.handle(&accepted(4)), None);
+    assert_eq!(agent.current(), b"old");
+}
+
+#[test]
+fn test_fallback() {
+    // the agent switched, but the server lost the pending passcode
+    let agent = AgentPasscode::restore(b"new".to_vec(), Some(b"old".to_vec()));
+    let mut server = ServerPasscode::new(b"old".to_vec());
+    assert!(!server.verify(agent.attempt(0)));
+    assert!(server.verify(agent.attempt(1)));
+    assert_eq!(agent.attempt(2), b"new");
+}
@@ -0,0 +1,79 @@
+//! Test draining connections when the server shuts down.
+#![cfg(all(feature = "server", feature = "loopback"))]
+
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use ws_com_framework::shutdown::Shutdown;
+use ws_com_framework::transport::loopback::duplex;
+use ws_com_framework::transport::GOING_AWAY;
+use ws_com_framework::Message;
+
+fn goodbye() -> Message {
+    Message::Goodbye {
+        reason: None,
+        reconnect_after: Some(5),
+        redirect_url: Some(String::from("wss://other.example.com/ws")),
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_finish_waits_for_requests() {
+    let shutdown = Shutdown::new();
+    let drain = shutdown.connection();
+    let (mut server, mut agent) = duplex();
+
+    let first = drain.begin_request().unwrap();
+    let second = drain.begin_request().unwrap();
+    assert_eq!(drain.in_flight(), 2);
+
+    let waiting = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move { shutdown.draining().await }
+    });
+    assert!(!shutdown.is_draining());
+    shutdown.start();
+    waiting.await.unwrap();
+    assert!(drain.begin_request().is_none());
+
+    let start = tokio::time::Instant::now();
+    let requests = async move {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        drop(first);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        drop(second);
+    };
+    let (finished, ()) = tokio::join!(
+        drain.finish(&mut server, goodbye(), Duration::from_secs(30)),
+        requests
+    );
+    finished.unwrap();
+    assert_eq!(start.elapsed(), Duration::from_secs(2));
+    assert_eq!(drain.in_flight(), 0);
+    assert_eq!(agent.next().await.unwrap().unwrap(), goodbye());
+
+    // the connection is closed as the server is going away
+    assert!(agent.next().await.is_none());
+    assert_eq!(
+        agent.close_frame(),
+        Some((GOING_AWAY, "the server is shutting down"))
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_finish_times_out() {
+    let shutdown = Shutdown::new();
+    let drain = shutdown.connection();
+    let (mut server, mut agent) = duplex();
+
+    let _stuck = drain.begin_request().unwrap();
+    shutdown.start();
+
+    let start = tokio::time::Instant::now();
+    drain
+        .finish(&mut server, goodbye(), Duration::from_secs(10))
+        .await
+        .unwrap();
+    assert_eq!(start.elapsed(), Duration::from_secs(10));
+    assert_eq!(agent.next().await.unwrap().unwrap(), goodbye());
+}
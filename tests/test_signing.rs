@@ -0,0 +1,240 @@
+//! Test signing of messages, and the rejection of replayed messages.
+
+use std::time::Duration;
+use ws_com_framework::session::ReplayWindow;
+use ws_com_framework::Error;
+
+#[test]
+fn test_replay_window_in_order() {
+    let mut window = ReplayWindow::new(Duration::from_secs(30));
+    for sequence in 1..200 {
+        window.check(sequence, 1000, 1000).unwrap();
+    }
+    assert_eq!(
+        window.check(199, 1000, 1000),
+        Err(Error::DuplicateMessage { sequence: 199 })
+    );
+}
+
+#[test]
+fn test_replay_window_out_of_order() {
+    let mut window = ReplayWindow::new(Duration::from_secs(30));
+    window.check(10, 1000, 1000).unwrap();
+    window.check(8, 1000, 1000).unwrap();
+    window.check(9, 1000, 1000).unwrap();
+    assert!(window.check(8, 1000, 1000).is_err());
+
+    window.check(100, 1000, 1000).unwrap();
+    window.check(37, 1000, 1000).unwrap();
+    assert_eq!(
+        window.check(36, 1000, 1000),
+        Err(Error::DuplicateMessage { sequence: 36 })
+    );
+}
+
+#[test]
+fn test_replay_window_stale() {
+    let mut window = ReplayWindow::new(Duration::from_secs(30));
+    assert_eq!(
+        window.check(1, 1000, 31_001),
+        Err(Error::StaleMessage {
+            timestamp: 1000,
+            now: 31_001
+        })
+    );
+    assert!(window.check(1, 62_000, 31_001).is_err());
+    window.check(1, 1000, 31_000).unwrap();
+}
+
+#[test]
+fn test_replay_window_rejects_zero() {
+    let mut window = ReplayWindow::new(Duration::from_secs(30));
+    assert!(matches!(
+        window.check(0, 1000, 1000),
+        Err(Error::InvalidSignature(_))
+    ));
+}
+
+#[cfg(feature = "signing")]
+mod signer {
+    use super::*;
+    use prost::Message as _;
+    use ws_com_framework::message::websocket_message::protobuf_types::FspComm;
+    use ws_com_framework::session::Role;
+    use ws_com_framework::{Codec, Message, SessionSigner};
+
+    const PUBLIC_ID: u64 = 123087497859;
+    const PASSCODE: &[u8] = b"correct horse battery staple";
+
+    fn codec(role: Role, passcode: &[u8]) -> Codec {
+        Codec {
+            signer: Some(SessionSigner::new(role, PUBLIC_ID, passcode)),
+            ..Default::default()
+        }
+    }
+
+    fn upload_to() -> Message {
+        Message::UploadTo {
+            file_id: 123,
+            upload_url: String::from("https://example.com/upload"),
+            deadline: None,
+        }
+    }
+
+    fn auth_res() -> Message {
+        Message::AuthRes {
+            public_id: PUBLIC_ID,
+            passcode: PASSCODE.to_vec(),
+        }
+    }
+
+    /// Codecs for the server and agent ends of a new connection, which have exchanged salts.
+    fn connect() -> (Codec, Codec) {
+        let server = codec(Role::Server, PASSCODE);
+        let agent = codec(Role::Agent, PASSCODE);
+        let req = Message::AuthReq {
+            public_id: PUBLIC_ID,
+        };
+        agent.decode(server.encode(&req).unwrap().into()).unwrap();
+        server
+            .decode(agent.encode(&auth_res()).unwrap().into())
+            .unwrap();
+        (server, agent)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (server, agent) = connect();
+        for _ in 0..3 {
+            let bytes = server.encode(&upload_to()).unwrap();
+            assert_eq!(agent.decode(bytes.into()).unwrap(), upload_to());
+        }
+        let bytes = agent.encode(&Message::Ok).unwrap();
+        assert_eq!(server.decode(bytes.into()).unwrap(), Message::Ok);
+    }
+
+    #[test]
+    fn test_replay_rejected() {
+        let (server, agent) = connect();
+
+        let bytes = server.encode(&upload_to()).unwrap();
+        agent.decode(bytes.clone().into()).unwrap();
+        assert_eq!(
+            agent.decode(bytes.into()),
+            Err(Error::DuplicateMessage { sequence: 1 })
+        );
+    }
+
+    #[test]
+    fn test_signed_messages_readable_without_signer() {
+        let (server, _agent) = connect();
+        let bytes = server.encode(&upload_to()).unwrap();
+        assert_eq!(Message::try_from(bytes).unwrap(), upload_to());
+    }
+
+    #[test]
+    fn test_tampering_detected() {
+        let (server, agent) = connect();
+        let bytes = server.encode(&upload_to()).unwrap();
+
+        let mut envelope = FspComm::decode(&bytes[..]).unwrap();
+        envelope.value = Message::UploadTo {
+            file_id: 123,
+            upload_url: String::from("https://evil.example.com/upload"),
+            deadline: None,
+        }
+        .to_bytes()
+        .into();
+        let err = agent.decode(envelope.encode_to_vec().into()).unwrap_err();
+        assert!(matches!(err, Error::InvalidSignature(_)));
+
+        let mut envelope = FspComm::decode(&bytes[..]).unwrap();
+        envelope.signature.as_mut().unwrap().sequence += 1;
+        assert!(agent.decode(envelope.encode_to_vec().into()).is_err());
+
+        let bytes = server.encode_on(3, &upload_to()).unwrap();
+        let mut envelope = FspComm::decode(&bytes[..]).unwrap();
+        envelope.stream_id = 5;
+        let err = agent.decode(envelope.encode_to_vec().into()).unwrap_err();
+        assert!(matches!(err, Error::InvalidSignature(_)));
+        assert_eq!(agent.decode_on(bytes.into()).unwrap(), (3, upload_to()));
+    }
+
+    #[test]
+    fn test_wrong_key_and_reflection() {
+        let (server, _agent) = connect();
+        let bytes = server.encode(&upload_to()).unwrap();
+        assert!(server.decode(bytes.into()).is_err());
+
+        let server = codec(Role::Server, PASSCODE);
+        let agent = codec(Role::Agent, b"not the passcode");
+        let req = Message::AuthReq {
+            public_id: PUBLIC_ID,
+        };
+        agent.decode(server.encode(&req).unwrap().into()).unwrap();
+        let bytes = agent.encode(&auth_res()).unwrap();
+        let err = server.decode(bytes.into()).unwrap_err();
+        assert!(matches!(err, Error::InvalidSignature(_)));
+    }
+
+    #[test]
+    fn test_sign_before_handshake() {
+        let server = codec(Role::Server, PASSCODE);
+        let err = server.encode(&upload_to()).unwrap_err();
+        assert!(matches!(err, Error::InvalidSignature(_)));
+    }
+
+    #[test]
+    fn test_replay_across_sessions_rejected() {
+        let (server, agent) = connect();
+        let old_upload = server.encode(&upload_to()).unwrap();
+        agent.decode(old_upload.clone().into()).unwrap();
+        let old_ok = agent.encode(&Message::Ok).unwrap();
+        server.decode(old_ok.clone().into()).unwrap();
+
+        // well within the maximum age, but on a new connection
+        let (server, agent) = connect();
+        let err = agent.decode(old_upload.into()).unwrap_err();
+        assert!(matches!(err, Error::InvalidSignature(_)));
+        let err = server.decode(old_ok.into()).unwrap_err();
+        assert!(matches!(err, Error::InvalidSignature(_)));
+
+        let bytes = server.encode(&upload_to()).unwrap();
+        assert_eq!(agent.decode(bytes.into()).unwrap(), upload_to());
+    }
+
+    #[test]
+    fn test_unsigned_rejected() {
+        let agent = codec(Role::Agent, PASSCODE);
+        let err = agent.decode(upload_to().to_bytes().into()).unwrap_err();
+        assert!(err.to_string().contains("unsigned message"));
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_signed_and_encrypted() {
+        use ws_com_framework::SessionCipher;
+
+        let server = Codec {
+            cipher: Some(SessionCipher::new(Role::Server, PUBLIC_ID, PASSCODE)),
+            ..codec(Role::Server, PASSCODE)
+        };
+        let agent = Codec {
+            cipher: Some(SessionCipher::new(Role::Agent, PUBLIC_ID, PASSCODE)),
+            ..codec(Role::Agent, PASSCODE)
+        };
+        let req = Message::AuthReq {
+            public_id: PUBLIC_ID,
+        };
+        agent.decode(server.encode(&req).unwrap().into()).unwrap();
+        let res = Message::AuthRes {
+            public_id: PUBLIC_ID,
+            passcode: PASSCODE.to_vec(),
+        };
+        server.decode(agent.encode(&res).unwrap().into()).unwrap();
+
+        let bytes = server.encode(&upload_to()).unwrap();
+        assert_eq!(agent.decode(bytes.clone().into()).unwrap(), upload_to());
+        assert!(agent.decode(bytes.into()).is_err());
+    }
+}
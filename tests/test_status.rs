@@ -0,0 +1,43 @@
+//! Test building the status an agent reports.
+
+use ws_com_framework::status::{ProcessStatus, StatusReport};
+use ws_com_framework::Message;
+
+#[test]
+fn test_process_report() {
+    let process = ProcessStatus::new("1.2.0");
+    let report = process.report();
+    assert!(report.ready);
+    assert_eq!(report.uptime, process.uptime().as_secs());
+    assert_eq!(report.agent_version.as_deref(), Some("1.2.0"));
+    assert_eq!(report.os.as_deref(), Some(std::env::consts::OS));
+    assert_eq!(report.active_shares, None);
+    assert_eq!(report.free_disk, None);
+}
+
+#[test]
+fn test_into_res() {
+    let report = StatusReport {
+        ready: true,
+        uptime: 10,
+        uploads_in_progress: Some(2),
+        free_disk: Some(1 << 30),
+        ..Default::default()
+    };
+    assert_eq!(
+        report.into_res(43, 7),
+        Message::StatusRes {
+            public_id: 43,
+            ready: true,
+            uptime: 10,
+            upload_id: 7,
+            message: None,
+            active_shares: None,
+            bytes_shared: None,
+            uploads_in_progress: Some(2),
+            free_disk: Some(1 << 30),
+            agent_version: None,
+            os: None,
+        }
+    );
+}
@@ -0,0 +1,134 @@
+//! Test minting and verifying agent tokens.
+#![cfg(feature = "tokens")]
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ws_com_framework::{Error, Message, TokenIssuer};
+
+const SECRET: &[u8] = b"shared by every server instance";
+
+#[test]
+fn test_mint_and_verify() {
+    let issuer = TokenIssuer::new(SECRET);
+    let token = issuer.mint(43, Duration::from_secs(60));
+    let expires_at = issuer.verify(43, &token).unwrap();
+
+    // another instance with the same secret accepts it
+    let other = TokenIssuer::new(SECRET);
+    assert_eq!(other.verify(43, &token), Ok(expires_at));
+
+    let token = issuer.mint_until(43, u64::MAX);
+    assert_eq!(issuer.verify(43, &token), Ok(u64::MAX));
+}
+
+#[test]
+fn test_rejected_tokens() {
+    let issuer = TokenIssuer::new(SECRET);
+    let token = issuer.mint(43, Duration::from_secs(60));
+
+    assert!(matches!(
+        issuer.verify(44, &token),
+        Err(Error::InvalidToken(_))
+    ));
+    assert!(TokenIssuer::new(b"another secret")
+        .verify(43, &token)
+        .is_err());
+    assert!(issuer.verify(43, &token[1..]).is_err());
+    assert!(issuer.verify(43, b"").is_err());
+
+    let mut tampered = token.clone();
+    tampered[16] ^= 1;
+    assert!(issuer.verify(43, &tampered).is_err());
+
+    let expired = issuer.mint_until(43, 1);
+    assert_eq!(
+        issuer.verify(43, &expired),
+        Err(Error::InvalidToken(String::from("expired at 1")))
+    );
+}
+
+#[test]
+fn test_redirect() {
+    let issuer = TokenIssuer::new(SECRET);
+    match issuer.redirect(43, "wss://other.example.com/ws", Duration::from_secs(60)) {
+        Message::Redirect { url, token } => {
+            assert_eq!(url, "wss://other.example.com/ws");
+            issuer.verify(43, &token).unwrap();
+        }
+        msg => panic!("expected a redirect, got {:?}", msg),
+    }
+}
+
+#[test]
+fn test_session_tokens() {
+    let issuer = TokenIssuer::new(SECRET);
+    let (session_token, expires_at) = match issuer.auth_ok(43, Duration::from_secs(3600)) {
+        Message::AuthOk {
+            session_token,
+            expires_at,
+        } => (session_token, expires_at),
+        msg => panic!("expected an auth ok, got {:?}", msg),
+    };
+    assert_eq!(issuer.verify(43, &session_token), Ok(expires_at));
+
+    // expiring the agent rejects the tokens issued to it so far, but not to other agents
+    let other = issuer.mint(44, Duration::from_secs(3600));
+    issuer.clone().expire(43);
+    assert!(matches!(
+        issuer.verify(43, &session_token),
+        Err(Error::InvalidToken(_))
+    ));
+    issuer.verify(44, &other).unwrap();
+
+    // an issuer which has not expired the agent still accepts them
+    TokenIssuer::new(SECRET).verify(43, &session_token).unwrap();
+
+    std::thread::sleep(Duration::from_millis(2));
+    let token = issuer.mint(43, Duration::from_secs(60));
+    issuer.verify(43, &token).unwrap();
+}
+
+#[test]
+fn test_token_minted_right_after_expire() {
+    let issuer = TokenIssuer::new(SECRET);
+    for _ in 0..100 {
+        let before = issuer.mint(43, Duration::from_secs(60));
+        issuer.expire(43);
+        // minted within the same millisecond as the expiry, yet after it
+        let after = issuer.mint(43, Duration::from_secs(60));
+        assert!(issuer.verify(43, &before).is_err());
+        issuer.verify(43, &after).unwrap();
+    }
+}
+
+#[test]
+fn test_max_ttl() {
+    let issuer = TokenIssuer::new(SECRET).with_max_ttl(Duration::from_millis(20));
+
+    // tokens are capped to the limit, and longer ones are rejected
+    let token = issuer.mint(43, Duration::from_secs(3600));
+    let expires_at = issuer.verify(43, &token).unwrap();
+    assert!(
+        expires_at
+            <= SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64
+                + 20
+    );
+    assert_eq!(
+        issuer.verify(43, &issuer.mint_until(43, u64::MAX)),
+        Err(Error::InvalidToken(String::from(
+            "valid for longer than 20ms"
+        )))
+    );
+
+    // expired agents are forgotten once their earlier tokens can no longer be valid
+    issuer.expire(43);
+    issuer.expire(44);
+    assert_eq!(issuer.expired_agents(), 2);
+    std::thread::sleep(Duration::from_millis(25));
+    issuer.expire(45);
+    assert_eq!(issuer.expired_agents(), 1);
+    assert!(issuer.verify(43, &token).is_err());
+}
@@ -0,0 +1,153 @@
+//! Test the `tokio_tungstenite` transport adapter over an in-memory socket.
+#![cfg(feature = "tungstenite")]
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::DuplexStream;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::WebSocketStream;
+use ws_com_framework::transport::tungstenite::WebSocketTransport;
+use ws_com_framework::transport::GOING_AWAY;
+use ws_com_framework::{Error, Message};
+
+async fn pair() -> (WebSocketStream<DuplexStream>, WebSocketStream<DuplexStream>) {
+    let (a, b) = tokio::io::duplex(64 * 1024);
+    let client = WebSocketStream::from_raw_socket(a, Role::Client, None).await;
+    let server = WebSocketStream::from_raw_socket(b, Role::Server, None).await;
+    (client, server)
+}
+
+#[tokio::test]
+async fn test_send_and_receive() {
+    let (client, server) = pair().await;
+    let mut client = WebSocketTransport::new(client);
+    let mut server = WebSocketTransport::new(server);
+
+    server
+        .send(Message::AuthReq { public_id: 43 })
+        .await
+        .unwrap();
+    assert_eq!(
+        client.next().await.unwrap().unwrap(),
+        Message::AuthReq { public_id: 43 }
+    );
+
+    let res = Message::AuthRes {
+        public_id: 43,
+        passcode: b"passcode".to_vec(),
+    };
+    client.send(res.clone()).await.unwrap();
+    assert_eq!(server.next().await.unwrap().unwrap(), res);
+}
+
+#[tokio::test]
+async fn test_control_frames_skipped() {
+    let (mut client, server) = pair().await;
+    let mut server = WebSocketTransport::new(server);
+
+    client
+        .send(WsMessage::Ping(vec![1, 2].into()))
+        .await
+        .unwrap();
+    client.send(WsMessage::Pong(vec![3].into())).await.unwrap();
+    client
+        .send(WsMessage::Binary(Message::Ok.to_bytes().into()))
+        .await
+        .unwrap();
+    assert_eq!(server.next().await.unwrap().unwrap(), Message::Ok);
+
+    // the ping is answered automatically once the server next writes
+    server.send(Message::Ok).await.unwrap();
+    assert_eq!(
+        client.next().await.unwrap().unwrap(),
+        WsMessage::Pong(vec![1, 2].into())
+    );
+}
+
+#[tokio::test]
+async fn test_text_frame_is_an_error() {
+    let (mut client, server) = pair().await;
+    let mut server = WebSocketTransport::new(server);
+
+    client.send(WsMessage::text("hello")).await.unwrap();
+    client
+        .send(WsMessage::Binary(Message::Ok.to_bytes().into()))
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        server.next().await.unwrap(),
+        Err(Error::TransportError(_))
+    ));
+    assert_eq!(server.next().await.unwrap().unwrap(), Message::Ok);
+}
+
+#[tokio::test]
+async fn test_bad_binary_frame_is_an_error() {
+    let (mut client, server) = pair().await;
+    let mut server = WebSocketTransport::new(server);
+
+    client
+        .send(WsMessage::Binary(vec![8, 99, 18, 1, 0].into()))
+        .await
+        .unwrap();
+    assert!(matches!(
+        server.next().await.unwrap(),
+        Err(Error::ByteDecodeError(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_close_ends_stream() {
+    let (client, server) = pair().await;
+    let mut client = WebSocketTransport::new(client);
+    let mut server = WebSocketTransport::new(server);
+
+    client.send(Message::Ok).await.unwrap();
+
+    // closing waits for the server to echo the close frame, which it does while reading
+    let (closed, received) = tokio::join!(client.close(), async {
+        let first = server.next().await;
+        let second = server.next().await;
+        (first, second)
+    });
+    closed.unwrap();
+    assert_eq!(received.0.unwrap().unwrap(), Message::Ok);
+    assert!(received.1.is_none());
+}
+
+#[tokio::test]
+async fn test_close_with_code() {
+    let (mut client, server) = pair().await;
+    let mut server = WebSocketTransport::new(server);
+
+    let (closed, received) = tokio::join!(
+        server.close_with(GOING_AWAY, "shutting down"),
+        client.next()
+    );
+    closed.unwrap();
+    match received.unwrap().unwrap() {
+        WsMessage::Close(Some(frame)) => {
+            assert_eq!(u16::from(frame.code), 1001);
+            assert_eq!(frame.reason.as_str(), "shutting down");
+        }
+        frame => panic!("expected a close frame, got {:?}", frame),
+    }
+}
+
+#[tokio::test]
+async fn test_close_with_error() {
+    let (mut client, server) = pair().await;
+    let mut server = WebSocketTransport::new(server);
+
+    let err = Error::ByteDecodeError(String::from("invalid wire type"));
+    let (closed, received) = tokio::join!(server.close_with_error(&err), client.next());
+    closed.unwrap();
+    match received.unwrap().unwrap() {
+        WsMessage::Close(Some(frame)) => {
+            assert_eq!(u16::from(frame.code), 1007);
+            assert_eq!(frame.reason.as_str(), err.to_string());
+        }
+        frame => panic!("expected a close frame, got {:?}", frame),
+    }
+}